@@ -156,6 +156,7 @@ impl Canvas {
         egui_ctx.set_visuals(egui::Visuals::dark());
 
         let mut gui_state = GuiState::new();
+        gui_state.ies_plot = raytracer.inner.ies_plot();
 
         // Double-buffered pixel snapshot to avoid cloning the entire buffer every frame
         let pixel_count = (self.width * self.height * 4) as usize;
@@ -200,7 +201,7 @@ impl Canvas {
 
             // Update GUI state from raytracer
             {
-                let camera_pos = *raytracer.inner.camera_position.lock().unwrap();
+                let camera_pos = raytracer.inner.camera.lock().unwrap().position;
                 gui_state.update_camera(camera_pos.x(), camera_pos.y(), camera_pos.z());
                 gui_state.is_debug_mode = *raytracer.inner.rendering_mode.lock().unwrap()
                     == crate::raytracer::RenderingMode::Debug;
@@ -397,9 +398,27 @@ impl Canvas {
                         SceneType::Teapot => Scene::new_teapot(),
                         SceneType::Specular => Scene::new_specular(),
                         SceneType::Diffuse => Scene::new_diffuse(),
+                        SceneType::Rough => Scene::new_rough(),
+                        SceneType::Procedural => Scene::new_procedural(),
+                        SceneType::Metals => Scene::new_metals(),
+                        SceneType::Dispersion => Scene::new_dispersion(),
+                        SceneType::Lamps => Scene::new_lamps(),
+                        SceneType::Luminaires => Scene::new_luminaires(),
+                        SceneType::Fog => Scene::new_fog(),
+                        SceneType::Smoke => Scene::new_smoke(),
+                        SceneType::Subsurface => Scene::new_subsurface(),
+                        SceneType::Shapes => Scene::new_shapes(),
+                        SceneType::Instances => Scene::new_instances(),
                         SceneType::Triangle => Scene::new_triangle(),
                     };
+                    println!(
+                        "Scene '{}': {} triangles, {:.1} MiB of geometry",
+                        scene_type.name(),
+                        new_scene.triangle_count(),
+                        new_scene.memory_usage() as f64 / (1024.0 * 1024.0)
+                    );
                     raytracer.set_scene(new_scene);
+                    gui_state.ies_plot = raytracer.inner.ies_plot();
                     raytracer.inner.pixel_buffer.clear();
                     needs_render = true;
                 }
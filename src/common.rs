@@ -42,6 +42,18 @@ impl Spectrum {
         color <= EPS
     }
 
+    /// Linear channel values.
+    #[inline(always)]
+    pub fn rgb(&self) -> [f32; 3] {
+        [self.r, self.g, self.b]
+    }
+
+    /// Mean of the three channels.
+    #[inline(always)]
+    pub fn average(&self) -> f32 {
+        (self.r + self.g + self.b) / 3.0
+    }
+
     #[inline(always)]
     fn to_u8(val: f32) -> u8 {
         if val > 255.0 {
@@ -1,4 +1,4 @@
-use egui_sdl2_gl::egui::{self, Context, RichText};
+use egui_sdl2_gl::egui::{self, Color32, Context, RichText, Stroke};
 
 /// Available scenes that can be rendered
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -7,6 +7,17 @@ pub enum SceneType {
     Teapot,
     Specular,
     Diffuse,
+    Rough,
+    Procedural,
+    Metals,
+    Dispersion,
+    Lamps,
+    Luminaires,
+    Fog,
+    Smoke,
+    Subsurface,
+    Shapes,
+    Instances,
     Triangle,
 }
 
@@ -17,6 +28,17 @@ impl SceneType {
             SceneType::Teapot => "Teapot",
             SceneType::Specular => "Specular Spheres",
             SceneType::Diffuse => "Diffuse Spheres",
+            SceneType::Rough => "Rough Diffuse Spheres",
+            SceneType::Procedural => "Procedural Textures",
+            SceneType::Metals => "Metal Spheres",
+            SceneType::Dispersion => "Dispersive Glass",
+            SceneType::Lamps => "Blackbody Lamps",
+            SceneType::Luminaires => "IES Luminaires",
+            SceneType::Fog => "Fog and Smoke",
+            SceneType::Smoke => "Voxel Smoke",
+            SceneType::Subsurface => "Subsurface Scattering",
+            SceneType::Shapes => "Analytic Shapes",
+            SceneType::Instances => "Instanced Teapots",
             SceneType::Triangle => "Simple Triangle",
         }
     }
@@ -27,6 +49,17 @@ impl SceneType {
             SceneType::Teapot,
             SceneType::Specular,
             SceneType::Diffuse,
+            SceneType::Rough,
+            SceneType::Procedural,
+            SceneType::Metals,
+            SceneType::Dispersion,
+            SceneType::Lamps,
+            SceneType::Luminaires,
+            SceneType::Fog,
+            SceneType::Smoke,
+            SceneType::Subsurface,
+            SceneType::Shapes,
+            SceneType::Instances,
             SceneType::Triangle,
         ]
     }
@@ -121,6 +154,9 @@ pub struct GuiState {
     pub camera_y: f32,
     pub camera_z: f32,
 
+    /// (angle from the nadir, relative intensity) of the scene's light profile, if any
+    pub ies_plot: Option<Vec<(f32, f32)>>,
+
     // UI state
     pub show_settings_panel: bool,
     pub show_help: bool,
@@ -145,6 +181,8 @@ impl Default for GuiState {
             camera_y: 0.0,
             camera_z: 0.0,
 
+            ies_plot: None,
+
             show_settings_panel: true,
             show_help: false,
         }
@@ -351,6 +389,12 @@ impl GuiState {
                     ui.add_space(10.0);
                     ui.separator();
 
+                    if let Some(plot) = &self.ies_plot {
+                        ui.collapsing("Light Profile", |ui| draw_polar_plot(ui, plot));
+                        ui.add_space(10.0);
+                        ui.separator();
+                    }
+
                     // Keyboard shortcuts
                     ui.collapsing("Keyboard Shortcuts", |ui| {
                         ui.label("W/A/S/D - Move camera");
@@ -391,3 +435,42 @@ impl GuiState {
         action
     }
 }
+
+/// Draws intensity against angle, with the nadir pointing down like a candela diagram.
+fn draw_polar_plot(ui: &mut egui::Ui, plot: &[(f32, f32)]) {
+    let size = 160.0;
+    let (response, painter) = ui.allocate_painter(egui::vec2(size, size), egui::Sense::hover());
+    let center = response.rect.center();
+    let radius = size * 0.45;
+
+    let grid = Stroke::new(1.0, Color32::from_gray(70));
+    for ring in 1..=4 {
+        painter.circle_stroke(center, radius * ring as f32 / 4.0, grid);
+    }
+    painter.line_segment(
+        [
+            center - egui::vec2(radius, 0.0),
+            center + egui::vec2(radius, 0.0),
+        ],
+        grid,
+    );
+    painter.line_segment(
+        [
+            center - egui::vec2(0.0, radius),
+            center + egui::vec2(0.0, radius),
+        ],
+        grid,
+    );
+
+    let points = plot
+        .iter()
+        .map(|&(angle, intensity)| {
+            let r = radius * intensity;
+            center + egui::vec2(r * angle.sin(), r * angle.cos())
+        })
+        .collect();
+    painter.add(egui::Shape::line(
+        points,
+        Stroke::new(1.5, Color32::from_rgb(255, 200, 80)),
+    ));
+}
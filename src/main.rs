@@ -6,9 +6,10 @@ mod common;
 mod gui;
 mod raytracer;
 mod scene;
+mod spectral;
 
 use raytracer::Raytracer;
-use scene::{Point, Scene};
+use scene::{NormalOptions, Point, Scene, Scheme, Subdivision};
 
 const DEFAULT_SCREEN_WIDTH: u32 = 600;
 const DEFAULT_SCREEN_HEIGHT: u32 = 600;
@@ -16,6 +17,7 @@ const DEFAULT_FOV_DEGREES: f32 = 90.0;
 const DEFAULT_SAMPLES_PER_PIXEL: u32 = 4;
 const DEFAULT_LIGHT_SAMPLES: u32 = 4;
 const DEFAULT_MAX_BOUNCES: u32 = 50;
+const DEFAULT_EXPOSURE: f32 = 1.0;
 
 pub struct Config {
     screen_width: u32,
@@ -29,6 +31,12 @@ pub struct Config {
     high_dpi: bool,
     image_mode: bool,
     single_threaded: bool,
+    spectral: bool,
+    exposure: f32,
+    /// Applied to OBJ files given as the scene.
+    subdivision: Option<Subdivision>,
+    /// Applied to mesh files given as the scene, instead of what each format does by default.
+    normals: Option<NormalOptions>,
 }
 
 impl Config {
@@ -67,6 +75,34 @@ impl Config {
 			.arg(Arg::with_name("single_threaded")
 				 .long("single-threaded")
 				 .help("Mode that runs without parallelization"))
+			.arg(Arg::with_name("spectral")
+				 .long("spectral")
+				 .help("Traces sampled wavelengths instead of RGB"))
+			.arg(Arg::with_name("exposure")
+				 .long("exposure")
+				 .takes_value(true)
+				 .help("Multiplies the rendered radiance, for scenes lit in physical units"))
+			.arg(Arg::with_name("subdivide")
+				 .long("subdivide")
+				 .takes_value(true)
+				 .help("Subdivides OBJ models this many times as they're loaded"))
+			.arg(Arg::with_name("loop")
+				 .long("loop")
+				 .help("Subdivides with Loop's scheme instead of Catmull-Clark"))
+			.arg(Arg::with_name("crease")
+				 .long("crease")
+				 .takes_value(true)
+				 .help("Keeps edges sharper than this many degrees sharp when subdividing"))
+			.arg(Arg::with_name("smooth")
+				 .long("smooth")
+				 .takes_value(true)
+				 .help("Smooths models without normals, except where faces meet at more than this many degrees"))
+			.arg(Arg::with_name("recompute_normals")
+				 .long("recompute-normals")
+				 .help("Replaces the normals that come with models too"))
+			.arg(Arg::with_name("orient_faces")
+				 .long("orient-faces")
+				 .help("Fixes the winding of models whose faces disagree on it"))
 			.get_matches();
 
         let light_samples = matches
@@ -88,6 +124,43 @@ impl Config {
         let high_dpi = matches.is_present("high_dpi");
         let image_mode = matches.is_present("image_mode");
         let single_threaded = matches.is_present("single_threaded");
+        let spectral = matches.is_present("spectral");
+        let exposure = matches
+            .value_of("exposure")
+            .map_or(DEFAULT_EXPOSURE, |arg| arg.parse().unwrap());
+        let scheme = if matches.is_present("loop") {
+            Scheme::Loop
+        } else {
+            Scheme::CatmullClark
+        };
+        let subdivision = matches.value_of("subdivide").map(|arg| {
+            let subdivision = Subdivision::new(scheme, arg.parse().unwrap());
+            match matches.value_of("crease") {
+                Some(degrees) => {
+                    subdivision.with_crease_angle(f32::to_radians(degrees.parse().unwrap()))
+                }
+                None => subdivision,
+            }
+        });
+        let normals = if ["smooth", "recompute_normals", "orient_faces"]
+            .iter()
+            .any(|arg| matches.is_present(arg))
+        {
+            let mut normals = match matches.value_of("smooth") {
+                Some(degrees) => NormalOptions::flat()
+                    .with_crease_angle(f32::to_radians(degrees.parse().unwrap())),
+                None => NormalOptions::default(),
+            };
+            if matches.is_present("recompute_normals") {
+                normals = normals.recomputed();
+            }
+            if matches.is_present("orient_faces") {
+                normals = normals.with_oriented_faces();
+            }
+            Some(normals)
+        } else {
+            None
+        };
 
         Config {
             screen_width,
@@ -101,6 +174,10 @@ impl Config {
             high_dpi,
             image_mode,
             single_threaded,
+            spectral,
+            exposure,
+            subdivision,
+            normals,
         }
     }
 }
@@ -116,16 +193,62 @@ fn main() {
         "teapot" => Scene::new_teapot(),
         "specular" => Scene::new_specular(),
         "diffuse" => Scene::new_diffuse(),
+        "rough" => Scene::new_rough(),
+        "procedural" => Scene::new_procedural(),
+        "metals" => Scene::new_metals(),
+        "dispersion" => Scene::new_dispersion(),
+        "lamps" => Scene::new_lamps(),
+        "luminaires" => Scene::new_luminaires(),
+        "fog" => Scene::new_fog(),
+        "smoke" => Scene::new_smoke(),
+        "subsurface" => Scene::new_subsurface(),
+        "shapes" => Scene::new_shapes(),
+        "instances" => Scene::new_instances(),
         "triangle" => Scene::new_triangle(),
+        path if [".gltf", ".glb"]
+            .iter()
+            .any(|extension| path.to_lowercase().ends_with(extension)) =>
+        {
+            match Scene::load_gltf(path) {
+                Ok(scene) => scene,
+                Err(e) => {
+                    eprintln!("Couldn't load '{}': {}, using specular", path, e);
+                    Scene::new_specular()
+                }
+            }
+        }
+        path if path.to_lowercase().ends_with(".stl") => {
+            match Scene::new_stl_part(path, config.normals.as_ref()) {
+                Ok(scene) => scene,
+                Err(e) => {
+                    eprintln!("Couldn't load '{}': {}, using specular", path, e);
+                    Scene::new_specular()
+                }
+            }
+        }
+        path if path.to_lowercase().ends_with(".ply") => {
+            match Scene::new_ply_model(path, config.normals.as_ref()) {
+                Ok(scene) => scene,
+                Err(e) => {
+                    eprintln!("Couldn't load '{}': {}, using specular", path, e);
+                    Scene::new_specular()
+                }
+            }
+        }
+        path if path.to_lowercase().ends_with(".obj") => {
+            Scene::new_obj_model(path, config.normals.as_ref(), config.subdivision.as_ref())
+        }
         _ => {
             eprintln!("Unknown scene '{}', using specular", scene_name);
             Scene::new_specular()
         }
     };
     println!(
-        "Scene '{}' loaded in {:.3}s",
+        "Scene '{}' loaded in {:.3}s: {} triangles, {:.1} MiB of geometry",
         scene_name,
-        load_start.elapsed().as_secs_f64()
+        load_start.elapsed().as_secs_f64(),
+        scene.triangle_count(),
+        scene.memory_usage() as f64 / (1024.0 * 1024.0)
     );
     let raytracer = Raytracer::new(config, scene);
     raytracer.start();
@@ -1,6 +1,9 @@
 use crate::canvas::Canvas;
 use crate::common::{weighted_coin_flip, Spectrum};
-use crate::scene::{Point, Ray, RayIntersection, Scene, Vector};
+use crate::scene::{
+    Camera, FreeFlight, Medium, Point, Ray, RayIntersection, Scene, SurfaceInteraction, Vector,
+};
+use crate::spectral::Wavelengths;
 use crate::Config;
 use rayon::prelude::*;
 
@@ -9,6 +12,7 @@ use std::sync::{Arc, Mutex, RwLock};
 use std::thread;
 
 const RUSSIAN_ROULETTE_PROBABILITY: f32 = 0.7;
+const IES_PLOT_SAMPLES: usize = 90;
 
 pub struct Raytracer {
     pub inner: Arc<RaytracerInner>,
@@ -70,7 +74,7 @@ pub struct RaytracerInner {
     config: RwLock<RenderConfig>,
     pub canvas: Canvas,
     scene: RwLock<Scene>,
-    pub camera_position: Mutex<Point>,
+    pub camera: Mutex<Camera>,
     pub rendering_mode: Mutex<RenderingMode>,
     // Flag to interrupt rendering
     interrupt: AtomicBool,
@@ -93,6 +97,18 @@ pub struct RenderConfig {
     pub light_samples: u32,
     pub bounces: u32,
     pub single_threaded: bool,
+    /// Trace sampled wavelengths rather than RGB channels.
+    pub spectral: bool,
+    /// Scale applied to radiance before display.
+    pub exposure: f32,
+}
+
+/// A point inside a medium where a ray scattered.
+struct MediumInteraction<'a> {
+    point: Point,
+    /// Direction the ray was travelling in.
+    direction: Vector,
+    medium: &'a Medium,
 }
 
 /// Precomputed values for screen_to_world that only depend on screen size and FOV
@@ -104,22 +120,27 @@ struct ScreenParams {
     total: f32,
     aspect_ratio: f32,
     z: f32,
+    /// Angle subtended by one pixel, the spread of primary ray cones
+    pixel_spread: f32,
 }
 
 impl ScreenParams {
-    fn from_config(config: &RenderConfig) -> Self {
+    fn new(config: &RenderConfig, camera: &Camera) -> Self {
         let w = config.screen_width as f32;
         let h = config.screen_height as f32;
         let half_fov = config.fov * 0.5;
         let start = f32::sin(-half_fov);
         let total = -2.0 * start;
+        // a camera with its own field of view moves the image plane to match it
+        let z = camera.fov.map_or(1.7, |fov| -start / f32::tan(fov * 0.5));
         ScreenParams {
             inv_w: 1.0 / w,
             inv_h: 1.0 / h,
             start,
             total,
             aspect_ratio: w / h,
-            z: 1.7,
+            z,
+            pixel_spread: total / (h * z),
         }
     }
 
@@ -143,6 +164,8 @@ impl From<&Config> for RenderConfig {
             light_samples: config.light_samples,
             bounces: config.bounces,
             single_threaded: config.single_threaded,
+            spectral: config.spectral,
+            exposure: config.exposure,
         }
     }
 }
@@ -171,10 +194,10 @@ impl RaytracerInner {
         self.render_progress.store(0, Ordering::SeqCst);
 
         // Get camera position and config once at the beginning
-        let camera_pos = *self.camera_position.lock().unwrap();
+        let camera = *self.camera.lock().unwrap();
         let config = self.config.read().unwrap().clone();
         let scene = self.scene.read().unwrap();
-        let screen_params = ScreenParams::from_config(&config);
+        let screen_params = ScreenParams::new(&config, &camera);
 
         let total_rows = config.screen_width;
 
@@ -184,8 +207,7 @@ impl RaytracerInner {
                     if self.interrupt.load(Ordering::Relaxed) {
                         break 'outer;
                     }
-                    let color =
-                        self.render_helper(i, j, camera_pos, &config, &scene, &screen_params);
+                    let color = self.render_helper(i, j, &camera, &config, &scene, &screen_params);
                     self.pixel_buffer.set_pixel(i, j, color);
                 }
                 let progress = ((i + 1) as f32 / total_rows as f32 * 100.0) as u32;
@@ -205,7 +227,7 @@ impl RaytracerInner {
                             break;
                         }
                         let color =
-                            self.render_helper(i, j, camera_pos, &config, &scene, &screen_params);
+                            self.render_helper(i, j, &camera, &config, &scene, &screen_params);
                         self.pixel_buffer.set_pixel(i, j, color);
                     }
 
@@ -225,25 +247,43 @@ impl RaytracerInner {
         &self,
         i: u32,
         j: u32,
-        camera_pos: Point,
+        camera: &Camera,
         config: &RenderConfig,
         scene: &Scene,
         screen_params: &ScreenParams,
     ) -> Spectrum {
-        let vector = screen_params.screen_to_world(i, j);
-        let ray = Ray::new_prenormalized(camera_pos, vector); // already normalized by screen_to_world
+        let vector = camera.to_world(screen_params.screen_to_world(i, j));
+        let ray = Ray::new_prenormalized(camera.position, vector) // already normalized by to_world
+            .with_cone(0.0, screen_params.pixel_spread);
         let mut color = Spectrum::black();
         for _ in 0..config.samples_per_pixel {
-            color += self.cast_ray(ray, config.bounces, config, scene);
+            let wavelengths = if config.spectral {
+                Wavelengths::sample()
+            } else {
+                Wavelengths::rgb()
+            };
+            let radiance = self.cast_ray(
+                ray,
+                config.bounces,
+                config,
+                scene,
+                &wavelengths,
+                scene.fog(),
+            );
+            color += wavelengths.to_rgb(radiance);
         }
-        color = color * (1.0 / config.samples_per_pixel as f32);
+        color = color * (config.exposure / config.samples_per_pixel as f32);
         color
     }
 
     /// Radiance from immediate scene intersections.  Should only paint lights.
     #[inline(always)]
-    fn zero_bounce_radiance(&self, intersection: &RayIntersection) -> Spectrum {
-        intersection.object().material().emittance
+    fn zero_bounce_radiance(
+        &self,
+        intersection: &RayIntersection,
+        wavelengths: &Wavelengths,
+    ) -> Spectrum {
+        intersection.emittance(wavelengths)
     }
 
     /// One bounce radiance using light-source importance sampling.
@@ -251,37 +291,62 @@ impl RaytracerInner {
     fn one_bounce_radiance_importance(
         &self,
         intersection: &RayIntersection,
-        intersection_point: Point,
-        normal: Vector,
+        si: &SurfaceInteraction,
         config: &RenderConfig,
         scene: &Scene,
+        medium: Option<&Medium>,
     ) -> Spectrum {
         let mut l = Spectrum::black();
-        let object = intersection.object();
+        let material = intersection.material();
         let wo = intersection.ray().direction;
+        let (intersection_point, normal) = (si.point, si.normal());
         let num_light_samples = config.light_samples;
         let inv_light_samples = 1.0 / num_light_samples as f32;
 
         // Iterate lights without allocating a Vec
         for &light_idx in scene.light_indexes() {
             let light = scene.get_object(light_idx);
-            let light_emittance = &light.material().emittance;
             let mut color = Spectrum::black();
             for _ in 0..num_light_samples {
                 let sample = light.sample_l(intersection_point);
                 let (pdf, wi) = (sample.pdf, sample.wi);
+                if !si.same_side(wo, wi) {
+                    continue;
+                }
 
                 // Shadow ray: check if path to light sample point is blocked
-                let shadow_ray = Ray::new_prenormalized(intersection_point, wi);
-                if !scene.is_occluded(&shadow_ray, sample.distance) {
-                    let reflected = object.bsdf(wi, wo);
+                let shadow_ray = Ray::new_prenormalized(si.spawn_point(wi), wi);
+                let transmittance =
+                    scene.transmittance(&shadow_ray, sample.distance, medium, &si.ctx.wavelengths);
+                if !transmittance.is_black() {
+                    let reflected = material.bsdf(wi, wo, normal, &si.ctx);
                     let cos_theta = f32::abs(wi.dot(normal));
-                    color += *light_emittance * reflected * cos_theta * pdf;
+                    let light_emittance = light.emittance(sample.point, -wi, &si.ctx.wavelengths);
+                    color += light_emittance * reflected * transmittance * cos_theta * pdf;
                 }
             }
             l += color * inv_light_samples;
         }
-        l += self.zero_bounce_radiance(intersection);
+
+        // point lights are delta distributions, one sample is exact
+        for light in scene.point_lights() {
+            let to_light = light.position() - intersection_point;
+            let distance = to_light.norm();
+            let wi = to_light * (1.0 / distance);
+            if !si.same_side(wo, wi) {
+                continue;
+            }
+            let shadow_ray = Ray::new_prenormalized(si.spawn_point(wi), wi);
+            let transmittance =
+                scene.transmittance(&shadow_ray, distance, medium, &si.ctx.wavelengths);
+            if !transmittance.is_black() {
+                let intensity = light.intensity(-wi, &si.ctx.wavelengths);
+                let reflected = material.bsdf(wi, wo, normal, &si.ctx);
+                let cos_theta = f32::abs(wi.dot(normal));
+                l += intensity * reflected * transmittance * (cos_theta / (distance * distance));
+            }
+        }
+        l += self.zero_bounce_radiance(intersection, &si.ctx.wavelengths);
         l
     }
 
@@ -292,18 +357,14 @@ impl RaytracerInner {
         bounces_left: u32,
         config: &RenderConfig,
         scene: &Scene,
+        wavelengths: &Wavelengths,
+        medium: Option<&Medium>,
     ) -> Spectrum {
-        let object = intersection.object();
-        let intersection_point = intersection.point();
-        let normal = intersection.normal();
-
-        let mut l = self.one_bounce_radiance_importance(
-            intersection,
-            intersection_point,
-            normal,
-            config,
-            scene,
-        );
+        let material = intersection.material();
+        let si = intersection.surface_interaction(wavelengths);
+        let normal = si.normal();
+
+        let mut l = self.one_bounce_radiance_importance(intersection, &si, config, scene, medium);
 
         // russian roulette for "infinite bounces"
         if !weighted_coin_flip(RUSSIAN_ROULETTE_PROBABILITY) {
@@ -311,11 +372,41 @@ impl RaytracerInner {
         }
 
         let wo = intersection.ray().direction;
-        let sample = object.sample_bsdf(wo, normal);
+        let sample = material.sample_bsdf(wo, &si);
         let (wi, pdf, reflected) = (sample.wi, sample.pdf, sample.reflected);
+        // reflections have to stay on the viewer's side of the actual surface, refractions
+        // have to cross it
+        if si.same_side(wo, wi) == sample.transmitted {
+            return l;
+        }
 
-        let bounced_ray = Ray::new(intersection_point, wi);
-        let mut color = self.cast_ray(bounced_ray, bounces_left - 1, config, scene);
+        // mirrors keep the ray cone going, anything blurrier gets integrated by sampling anyway
+        let bounced_ray = Ray::new(si.spawn_point(wi), wi);
+        let bounced_ray = if sample.specular {
+            let ray = intersection.ray();
+            bounced_ray.with_cone(ray.cone_width_at(intersection.distance()), ray.cone_spread)
+        } else {
+            bounced_ray
+        };
+        // refractions cross into or out of the object's interior
+        let medium = if sample.transmitted {
+            scene.medium_across(intersection)
+        } else {
+            medium
+        };
+        let wavelengths = if sample.dispersed {
+            wavelengths.without_secondary()
+        } else {
+            *wavelengths
+        };
+        let mut color = self.cast_ray(
+            bounced_ray,
+            bounces_left - 1,
+            config,
+            scene,
+            &wavelengths,
+            medium,
+        );
 
         if !color.is_black() {
             let cos_theta = f32::abs(wi.dot(normal));
@@ -325,7 +416,75 @@ impl RaytracerInner {
         l
     }
 
-    /// Where the magic happens.
+    /// Radiance scattered towards the viewer at a point inside a medium: light sampled through
+    /// the phase function, then another bounce in a direction sampled from it.
+    fn medium_radiance(
+        &self,
+        mi: &MediumInteraction,
+        bounces_left: u32,
+        config: &RenderConfig,
+        scene: &Scene,
+        wavelengths: &Wavelengths,
+    ) -> Spectrum {
+        if bounces_left == 0 {
+            return Spectrum::black();
+        }
+        let mut l = Spectrum::black();
+        let phase = mi.medium.phase();
+        let inv_light_samples = 1.0 / config.light_samples as f32;
+
+        for &light_idx in scene.light_indexes() {
+            let light = scene.get_object(light_idx);
+            let mut color = Spectrum::black();
+            for _ in 0..config.light_samples {
+                let sample = light.sample_l(mi.point);
+                let wi = sample.wi;
+                let shadow_ray = Ray::new_prenormalized(mi.point, wi);
+                let transmittance =
+                    scene.transmittance(&shadow_ray, sample.distance, Some(mi.medium), wavelengths);
+                if !transmittance.is_black() {
+                    let light_emittance = light.emittance(sample.point, -wi, wavelengths);
+                    color += light_emittance
+                        * transmittance
+                        * (phase.evaluate(mi.direction, wi) * sample.pdf);
+                }
+            }
+            l += color * inv_light_samples;
+        }
+
+        for light in scene.point_lights() {
+            let to_light = light.position() - mi.point;
+            let distance = to_light.norm();
+            let wi = to_light * (1.0 / distance);
+            let shadow_ray = Ray::new_prenormalized(mi.point, wi);
+            let transmittance =
+                scene.transmittance(&shadow_ray, distance, Some(mi.medium), wavelengths);
+            if !transmittance.is_black() {
+                l += light.intensity(-wi, wavelengths)
+                    * transmittance
+                    * (phase.evaluate(mi.direction, wi) / (distance * distance));
+            }
+        }
+
+        if bounces_left > 1
+            && (!mi.medium.allows_russian_roulette()
+                || weighted_coin_flip(RUSSIAN_ROULETTE_PROBABILITY))
+        {
+            // sampled proportionally to the phase function, which cancels out
+            let wi = phase.sample(mi.direction);
+            l += self.cast_ray(
+                Ray::new(mi.point, wi),
+                bounces_left - 1,
+                config,
+                scene,
+                wavelengths,
+                Some(mi.medium),
+            );
+        }
+        l
+    }
+
+    /// Where the magic happens. `medium` is what the ray travels through.
     #[inline(always)]
     fn cast_ray(
         &self,
@@ -333,20 +492,74 @@ impl RaytracerInner {
         bounces_left: u32,
         config: &RenderConfig,
         scene: &Scene,
+        wavelengths: &Wavelengths,
+        medium: Option<&Medium>,
     ) -> Spectrum {
-        if let Some(ray_intersection) = scene.intersect(ray) {
-            match bounces_left {
-                0 => self.zero_bounce_radiance(&ray_intersection),
-                1 => {
-                    let pt = ray_intersection.point();
-                    let n = ray_intersection.normal();
-                    self.one_bounce_radiance_importance(&ray_intersection, pt, n, config, scene)
+        let intersection = scene.intersect(ray);
+
+        // the ray may scatter in the medium before getting to the surface
+        let mut weight = Spectrum::white();
+        if let Some(medium) = medium {
+            let max_distance = intersection
+                .as_ref()
+                .map_or(f32::INFINITY, |intersection| intersection.distance());
+            match medium.sample_free_flight(&ray, max_distance, wavelengths) {
+                FreeFlight::Scatter { distance, weight } => {
+                    let mi = MediumInteraction {
+                        point: ray.origin + ray.direction * distance,
+                        direction: ray.direction,
+                        medium,
+                    };
+                    return weight
+                        * self.medium_radiance(&mi, bounces_left, config, scene, wavelengths);
                 }
-                _ => self.global_illumination(&ray_intersection, bounces_left, config, scene),
+                FreeFlight::Pass { weight: pass } => weight = pass,
             }
-        } else {
-            Spectrum::black()
         }
+
+        let ray_intersection = match intersection {
+            Some(ray_intersection) => ray_intersection,
+            None => return Spectrum::black(),
+        };
+        if weight.is_black() {
+            return weight;
+        }
+
+        // media boundaries are invisible, crossing them doesn't count as a bounce
+        if ray_intersection.is_medium_boundary() {
+            let medium = scene.medium_across(&ray_intersection);
+            return weight
+                * self.cast_ray(
+                    ray_intersection.continue_through(),
+                    bounces_left,
+                    config,
+                    scene,
+                    wavelengths,
+                    medium,
+                );
+        }
+
+        // the back of a one-sided surface can only emit
+        if !ray_intersection.material().two_sided && ray_intersection.is_back_face() {
+            return self.zero_bounce_radiance(&ray_intersection, wavelengths) * weight;
+        }
+
+        let l = match bounces_left {
+            0 => self.zero_bounce_radiance(&ray_intersection, wavelengths),
+            1 => {
+                let si = ray_intersection.surface_interaction(wavelengths);
+                self.one_bounce_radiance_importance(&ray_intersection, &si, config, scene, medium)
+            }
+            _ => self.global_illumination(
+                &ray_intersection,
+                bounces_left,
+                config,
+                scene,
+                wavelengths,
+                medium,
+            ),
+        };
+        l * weight
     }
 
     /// Renderer that paints grey for intersections, and black otherwise
@@ -354,10 +567,10 @@ impl RaytracerInner {
         self.interrupt.store(false, Ordering::SeqCst);
         self.is_rendering.store(true, Ordering::SeqCst);
 
-        let camera_pos = *self.camera_position.lock().unwrap();
+        let camera = *self.camera.lock().unwrap();
         let config = self.config.read().unwrap().clone();
         let scene = self.scene.read().unwrap();
-        let screen_params = ScreenParams::from_config(&config);
+        let screen_params = ScreenParams::new(&config, &camera);
 
         if config.single_threaded {
             'outer: for i in 0..config.screen_width {
@@ -365,7 +578,7 @@ impl RaytracerInner {
                     if self.interrupt.load(Ordering::Relaxed) {
                         break 'outer;
                     }
-                    let color = self.debug_render_helper(i, j, camera_pos, &scene, &screen_params);
+                    let color = self.debug_render_helper(i, j, &camera, &scene, &screen_params);
                     self.pixel_buffer.set_pixel(i, j, color);
                 }
             }
@@ -379,8 +592,7 @@ impl RaytracerInner {
                         if self.interrupt.load(Ordering::Relaxed) {
                             break;
                         }
-                        let color =
-                            self.debug_render_helper(i, j, camera_pos, &scene, &screen_params);
+                        let color = self.debug_render_helper(i, j, &camera, &scene, &screen_params);
                         self.pixel_buffer.set_pixel(i, j, color);
                     }
                 });
@@ -395,12 +607,12 @@ impl RaytracerInner {
         &self,
         i: u32,
         j: u32,
-        camera_pos: Point,
+        camera: &Camera,
         scene: &Scene,
         screen_params: &ScreenParams,
     ) -> Spectrum {
-        let vector = screen_params.screen_to_world(i, j);
-        let ray = Ray::new_prenormalized(camera_pos, vector); // already normalized
+        let vector = camera.to_world(screen_params.screen_to_world(i, j));
+        let ray = Ray::new_prenormalized(camera.position, vector); // already normalized
 
         if let Some(ri) = scene.intersect(ray) {
             let max_distance: f32 = 100.0;
@@ -415,15 +627,23 @@ impl RaytracerInner {
         }
     }
 
+    /// Polar plot of the current scene's photometric profile, if it has one.
+    pub fn ies_plot(&self) -> Option<Vec<(f32, f32)>> {
+        let scene = self.scene.read().unwrap();
+        scene
+            .ies_profile()
+            .map(|profile| profile.polar_curve(IES_PLOT_SAMPLES))
+    }
+
     /// Helpful function to test a pixel's behavior.
     pub fn test(&self, i: u32, j: u32) {
-        let camera_pos = *self.camera_position.lock().unwrap();
+        let camera = *self.camera.lock().unwrap();
         let config = self.config.read().unwrap().clone();
         let scene = self.scene.read().unwrap();
-        let screen_params = ScreenParams::from_config(&config);
+        let screen_params = ScreenParams::new(&config, &camera);
         println!(
             "{:?}",
-            self.debug_render_helper(i, j, camera_pos, &scene, &screen_params)
+            self.debug_render_helper(i, j, &camera, &scene, &screen_params)
         );
     }
 }
@@ -444,6 +664,7 @@ impl Raytracer {
 
         let rendering_mode = RenderingMode::Debug;
         let render_config = RenderConfig::from(&config);
+        let camera = scene.camera();
 
         // Build the thread pool once, reuse for all renders
         let thread_pool = rayon::ThreadPoolBuilder::new().build().unwrap();
@@ -453,7 +674,7 @@ impl Raytracer {
                 config: RwLock::new(render_config),
                 canvas,
                 scene: RwLock::new(scene),
-                camera_position: Mutex::new(Point::new(0.0, 0.0, 0.0)),
+                camera: Mutex::new(camera),
                 rendering_mode: Mutex::new(rendering_mode),
                 interrupt: AtomicBool::new(false),
                 is_rendering: AtomicBool::new(false),
@@ -474,14 +695,16 @@ impl Raytracer {
         self.inner.canvas.start(self.inner.clone());
     }
 
+    /// Moves the camera along `direction`, given relative to where it looks.
     pub fn move_camera(&self, direction: Vector) {
-        let mut camera_pos = self.inner.camera_position.lock().unwrap();
-        *camera_pos = *camera_pos + direction * CAMERA_SPEED;
+        let mut camera = self.inner.camera.lock().unwrap();
+        camera.position = camera.position + camera.to_world(direction) * CAMERA_SPEED;
     }
 
+    /// Puts the camera back where the scene starts out.
     pub fn reset_camera(&self) {
-        let mut camera_pos = self.inner.camera_position.lock().unwrap();
-        *camera_pos = Point::new(0.0, 0.0, 0.0);
+        let camera = self.inner.scene.read().unwrap().camera();
+        *self.inner.camera.lock().unwrap() = camera;
     }
 
     pub fn toggle_rendering_mode(&self) {
@@ -513,6 +736,7 @@ impl Raytracer {
     /// Set a new scene
     pub fn set_scene(&self, scene: Scene) {
         self.interrupt_render();
+        *self.inner.camera.lock().unwrap() = scene.camera();
         let mut current_scene = self.inner.scene.write().unwrap();
         *current_scene = scene;
     }
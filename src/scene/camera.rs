@@ -0,0 +1,46 @@
+use super::{Point, Transform, Vector};
+
+/// Where a scene is seen from. Like OpenGL and glTF cameras it looks down its local -z axis,
+/// with y up and x to the right.
+#[derive(Clone, Copy, Debug)]
+pub struct Camera {
+    pub position: Point,
+    /// Takes directions from camera space to the world. Only its linear part is used.
+    pub orientation: Transform,
+    /// Vertical field of view in radians, if the scene asks for one. Otherwise the render
+    /// settings decide.
+    pub fov: Option<f32>,
+}
+
+impl Camera {
+    /// A camera looking down -z without a field of view of its own.
+    pub fn new(position: Point) -> Camera {
+        Camera {
+            position,
+            orientation: Transform::identity(),
+            fov: None,
+        }
+    }
+
+    pub fn with_orientation(mut self, orientation: Transform) -> Camera {
+        self.orientation = orientation;
+        self
+    }
+
+    pub fn with_fov(mut self, fov: f32) -> Camera {
+        self.fov = Some(fov);
+        self
+    }
+
+    /// World direction of `v`, given in camera space.
+    #[inline(always)]
+    pub fn to_world(self, v: Vector) -> Vector {
+        self.orientation.vector(v).normalized()
+    }
+}
+
+impl Default for Camera {
+    fn default() -> Camera {
+        Camera::new(Point::origin())
+    }
+}
@@ -2,31 +2,50 @@ extern crate nalgebra as na;
 
 use std::f32::consts::PI;
 use std::fmt;
-use std::ops::{Add, Mul, Sub};
+use std::ops::{Add, Mul, Neg, Sub};
 
-use na::base::Vector3;
+use na::base::{Matrix3, Vector3};
 use na::geometry::Point3;
 
 #[derive(Clone, Copy, Debug)]
 pub struct Ray {
     pub origin: Point,
     pub direction: Vector,
+    /// Width of the ray cone at the origin, used for texture filtering.
+    pub cone_width: f32,
+    /// Angle (radians) by which the ray cone widens per unit of distance travelled.
+    pub cone_spread: f32,
 }
 
 impl Ray {
     // normalizes the direction vector
     #[inline(always)]
     pub fn new(origin: Point, direction: Vector) -> Ray {
+        Ray::new_prenormalized(origin, direction.normalized())
+    }
+
+    /// Create a ray with an already-normalized direction (skip redundant normalize)
+    #[inline(always)]
+    pub fn new_prenormalized(origin: Point, direction: Vector) -> Ray {
         Ray {
             origin,
-            direction: direction.normalized(),
+            direction,
+            cone_width: 0.0,
+            cone_spread: 0.0,
         }
     }
 
-    /// Create a ray with an already-normalized direction (skip redundant normalize)
     #[inline(always)]
-    pub fn new_prenormalized(origin: Point, direction: Vector) -> Ray {
-        Ray { origin, direction }
+    pub fn with_cone(mut self, cone_width: f32, cone_spread: f32) -> Ray {
+        self.cone_width = cone_width;
+        self.cone_spread = cone_spread;
+        self
+    }
+
+    /// Width of the ray cone after travelling `distance`.
+    #[inline(always)]
+    pub fn cone_width_at(&self, distance: f32) -> f32 {
+        self.cone_width + self.cone_spread * distance
     }
 }
 
@@ -113,22 +132,25 @@ impl Vector {
         Vector::new(xs, ys, zs)
     }
 
+    /// Cosine-weighted hemisphere sampling (Malley's method: project a uniform disk sample up).
+    /// The pdf of the returned direction is `cos_theta / PI`.
+    pub fn random_cosine_hemisphere() -> Vector {
+        let xi1 = fastrand::f32();
+        let xi2 = fastrand::f32();
+
+        let r = f32::sqrt(xi1);
+        let phi = 2.0 * PI * xi2;
+        let (sin_phi, cos_phi) = f32::sin_cos(phi);
+        let xs = r * cos_phi;
+        let ys = r * sin_phi;
+        let zs = f32::sqrt(f32::max(0.0, 1.0 - xi1));
+
+        Vector::new(xs, ys, zs)
+    }
+
     /// Transform local hemisphere sample to world space using an ONB built from normal.
-    /// Uses Frisvad's method - no normalize calls needed for the basis vectors.
     pub fn to_coord_space(&self, normal: Vector) -> Vector {
-        let n = normal.v;
-        let (t, b) = if n.z < -0.9999999 {
-            // Handle singularity when normal points straight down
-            (Vector3::new(0.0, -1.0, 0.0), Vector3::new(-1.0, 0.0, 0.0))
-        } else {
-            let a = 1.0 / (1.0 + n.z);
-            let b_val = -n.x * n.y * a;
-            (
-                Vector3::new(1.0 - n.x * n.x * a, b_val, -n.x),
-                Vector3::new(b_val, 1.0 - n.y * n.y * a, -n.y),
-            )
-        };
-        Vector::new_from_na(t * self.v.x + b * self.v.y + n * self.v.z)
+        Frame::from_normal(normal).to_world(*self)
     }
 
     /// Samples uniformly on a unit sphere
@@ -180,6 +202,191 @@ impl Vector {
     }
 }
 
+/// How far ray origins are pushed off a surface, relative to the largest coordinate of the point.
+/// Hit points are only exact to a few ulps of their coordinates, so this clears the rounding
+/// error with a wide margin.
+const ORIGIN_OFFSET: f32 = 128.0 * f32::EPSILON;
+
+/// Origin for a ray leaving the surface at `point` along `direction`: moved along the
+/// geometric `normal` to the side the ray goes, just far enough that it can't hit the surface
+/// it starts on again.
+#[inline(always)]
+pub fn offset_ray_origin(point: Point, normal: Vector, direction: Vector) -> Point {
+    let magnitude = point.x().abs().max(point.y().abs()).max(point.z().abs());
+    let offset = normal * (ORIGIN_OFFSET * magnitude.max(1.0));
+    if direction.dot(normal) < 0.0 {
+        point - offset
+    } else {
+        point + offset
+    }
+}
+
+/// Orthonormal basis used for shading; local z is the normal.
+#[derive(Clone, Copy, Debug)]
+pub struct Frame {
+    pub tangent: Vector,
+    pub bitangent: Vector,
+    pub normal: Vector,
+}
+
+impl Frame {
+    /// Builds an arbitrary frame around a unit normal.
+    /// Uses Frisvad's method - no normalize calls needed for the basis vectors.
+    pub fn from_normal(normal: Vector) -> Frame {
+        let n = normal.v;
+        let (t, b) = if n.z < -0.9999999 {
+            // Handle singularity when normal points straight down
+            (Vector3::new(0.0, -1.0, 0.0), Vector3::new(-1.0, 0.0, 0.0))
+        } else {
+            let a = 1.0 / (1.0 + n.z);
+            let b_val = -n.x * n.y * a;
+            (
+                Vector3::new(1.0 - n.x * n.x * a, b_val, -n.x),
+                Vector3::new(b_val, 1.0 - n.y * n.y * a, -n.y),
+            )
+        };
+        Frame {
+            tangent: Vector::new_from_na(t),
+            bitangent: Vector::new_from_na(b),
+            normal,
+        }
+    }
+
+    /// Builds a frame around a unit normal whose tangent follows `tangent` as closely as
+    /// possible (Gram-Schmidt), falling back to an arbitrary one if they are parallel.
+    pub fn from_normal_tangent(normal: Vector, tangent: Vector) -> Frame {
+        let tangent = tangent - normal * normal.dot(tangent);
+        let length = tangent.norm();
+        if length <= 1e-6 {
+            return Frame::from_normal(normal);
+        }
+        let tangent = tangent * (1.0 / length);
+        Frame {
+            tangent,
+            bitangent: normal.cross(tangent),
+            normal,
+        }
+    }
+
+    #[inline(always)]
+    pub fn to_world(self, v: Vector) -> Vector {
+        self.tangent * v.x() + self.bitangent * v.y() + self.normal * v.z()
+    }
+
+    #[inline(always)]
+    pub fn to_local(self, v: Vector) -> Vector {
+        Vector::new(
+            v.dot(self.tangent),
+            v.dot(self.bitangent),
+            v.dot(self.normal),
+        )
+    }
+}
+
+/// Affine map from an object's own space to the world: a linear part (rotation, scale, shear)
+/// followed by a translation. The inverse is kept alongside to bring rays into object space.
+#[derive(Clone, Copy, Debug)]
+pub struct Transform {
+    linear: Matrix3<f32>,
+    inverse: Matrix3<f32>,
+    translation: Vector3<f32>,
+}
+
+impl Transform {
+    pub fn identity() -> Transform {
+        Transform::from_linear(Matrix3::identity(), Vector3::zeros())
+    }
+
+    pub fn translation(offset: Vector) -> Transform {
+        Transform::from_linear(Matrix3::identity(), offset.v)
+    }
+
+    /// Rotation by `angle` radians counterclockwise around `axis`.
+    pub fn rotation(axis: Vector, angle: f32) -> Transform {
+        let axis = na::Unit::new_normalize(axis.v);
+        let rotation = na::Rotation3::from_axis_angle(&axis, angle);
+        Transform::from_linear(*rotation.matrix(), Vector3::zeros())
+    }
+
+    /// Scale along each axis. None of the factors may be zero.
+    pub fn scale(x: f32, y: f32, z: f32) -> Transform {
+        Transform::from_linear(
+            Matrix3::from_diagonal(&Vector3::new(x, y, z)),
+            Vector3::zeros(),
+        )
+    }
+
+    /// Rotation by the unit quaternion `[x, y, z, w]`.
+    pub fn from_quaternion(q: [f32; 4]) -> Transform {
+        let [x, y, z, w] = q;
+        let rotation = na::UnitQuaternion::from_quaternion(na::Quaternion::new(w, x, y, z));
+        Transform::from_linear(*rotation.to_rotation_matrix().matrix(), Vector3::zeros())
+    }
+
+    /// The affine part of a 4x4 matrix stored column by column, as OpenGL and glTF do.
+    /// `None` if it collapses space and so can't be inverted.
+    pub fn from_column_major(m: &[f32; 16]) -> Option<Transform> {
+        let linear = Matrix3::new(m[0], m[4], m[8], m[1], m[5], m[9], m[2], m[6], m[10]);
+        let inverse = linear.try_inverse()?;
+        Some(Transform {
+            linear,
+            inverse,
+            translation: Vector3::new(m[12], m[13], m[14]),
+        })
+    }
+
+    fn from_linear(linear: Matrix3<f32>, translation: Vector3<f32>) -> Transform {
+        let inverse = linear
+            .try_inverse()
+            .expect("transforms have to be invertible");
+        Transform {
+            linear,
+            inverse,
+            translation,
+        }
+    }
+
+    /// `self` followed by `next`.
+    pub fn then(&self, next: &Transform) -> Transform {
+        Transform::from_linear(
+            next.linear * self.linear,
+            next.linear * self.translation + next.translation,
+        )
+    }
+
+    /// Whether the transform mirrors space, turning counterclockwise triangles clockwise.
+    pub fn flips_handedness(&self) -> bool {
+        self.linear.determinant() < 0.0
+    }
+
+    #[inline(always)]
+    pub fn point(&self, p: Point) -> Point {
+        Point::new_from_na((self.linear * p.p.coords + self.translation).into())
+    }
+
+    #[inline(always)]
+    pub fn vector(&self, v: Vector) -> Vector {
+        Vector::new_from_na(self.linear * v.v)
+    }
+
+    /// Normals transform by the inverse transpose to stay perpendicular to the surface.
+    /// The result isn't normalized.
+    #[inline(always)]
+    pub fn normal(&self, n: Vector) -> Vector {
+        Vector::new_from_na(self.inverse.transpose() * n.v)
+    }
+
+    #[inline(always)]
+    pub fn inverse_point(&self, p: Point) -> Point {
+        Point::new_from_na((self.inverse * (p.p.coords - self.translation)).into())
+    }
+
+    #[inline(always)]
+    pub fn inverse_vector(&self, v: Vector) -> Vector {
+        Vector::new_from_na(self.inverse * v.v)
+    }
+}
+
 impl fmt::Display for Vector {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "v({} {} {})", self.x(), self.y(), self.z())
@@ -233,3 +440,11 @@ impl Mul<f32> for Vector {
         Vector::new_from_na(self.v * other)
     }
 }
+
+impl Neg for Vector {
+    type Output = Vector;
+    #[inline(always)]
+    fn neg(self) -> Vector {
+        Vector::new_from_na(-self.v)
+    }
+}
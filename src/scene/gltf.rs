@@ -0,0 +1,1122 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::f32::consts::FRAC_PI_4;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use super::camera::Camera;
+use super::instance::{Instance, Mesh};
+use super::json::Json;
+use super::lights::PointLight;
+use super::objects::{Material, BSDF};
+use super::texture::{FilterMode, ImageTexture, Texture, WrapMode};
+use super::triangle_mesh::TriangleMesh;
+use super::{parse_error, LoadError, Point, Transform, Vector};
+use crate::common::Spectrum;
+use crate::spectral::Ior;
+
+/// What a glTF scene turns into. Meshes placed by a single node are moved into world space
+/// so emissive ones can be sampled as lights, meshes placed several times are instanced.
+pub struct GltfScene {
+    pub meshes: Vec<TriangleMesh>,
+    pub instances: Vec<Instance>,
+    pub point_lights: Vec<PointLight>,
+    /// The first camera found walking the node tree.
+    pub camera: Option<Camera>,
+}
+
+/// Node trees deeper than this are taken to have a cycle.
+const MAX_NODE_DEPTH: usize = 256;
+/// Sharpest Phong lobe a glTF roughness is turned into.
+const MAX_PHONG_EXPONENT: f32 = 10000.0;
+/// Accessors without a buffer view are all zeros, so nothing in the file bounds their count.
+const MAX_ZERO_ACCESSOR_COUNT: usize = 1 << 20;
+
+const GLB_MAGIC: &[u8] = b"glTF";
+const GLB_JSON_CHUNK: u32 = 0x4E4F_534A;
+const GLB_BIN_CHUNK: u32 = 0x004E_4942;
+
+/// Loads a `.gltf` file, with its buffers and images next to it or embedded as data URIs,
+/// or a binary `.glb` file.
+pub fn open(filename: &str) -> Result<GltfScene, LoadError> {
+    let directory = Path::new(filename)
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+    parse(&fs::read(filename)?, directory)
+}
+
+/// Reads glTF 2.0 from memory, resolving relative URIs against `directory`:
+/// - the default scene's node tree, with matrix or translation/rotation/scale transforms,
+/// - triangle, strip and fan primitives with their positions, normals, first uvs and colours,
+/// - metallic-roughness materials with base colour, emissive and normal textures, plus the
+///   transmission, ior and emissive strength extensions,
+/// - the first perspective camera,
+/// - point and spot lights from `KHR_lights_punctual`.
+///
+/// Materials are approximated with our BSDFs, and textures only vary the base colour, the
+/// emission and the normal, plus the opacity of blended and masked materials through the
+/// base colour's alpha. Sparse accessors, orthographic cameras and directional lights
+/// aren't supported.
+pub fn parse(bytes: &[u8], directory: PathBuf) -> Result<GltfScene, LoadError> {
+    let (json, bin) = if bytes.starts_with(GLB_MAGIC) {
+        split_glb(bytes)?
+    } else {
+        (bytes, None)
+    };
+    let json = Json::parse(&String::from_utf8_lossy(json)).map_err(LoadError::Parse)?;
+    let version = json
+        .get("asset")
+        .and_then(|asset| asset.get("version"))
+        .and_then(Json::as_str);
+    if !version.is_some_and(|v| v.starts_with("2.")) {
+        return parse_error(format!("only glTF 2.0 is supported, not {:?}", version));
+    }
+
+    let mut document = Document {
+        buffers: Vec::new(),
+        directory,
+        textures: HashMap::new(),
+        materials: HashMap::new(),
+        json,
+    };
+    document.buffers = document.load_buffers(bin)?;
+    document.load_scene()
+}
+
+/// The JSON and binary chunks of a `.glb` file.
+fn split_glb(bytes: &[u8]) -> Result<(&[u8], Option<&[u8]>), LoadError> {
+    let word = |offset: usize| {
+        bytes
+            .get(offset..offset + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    };
+    if word(4) != Some(2) {
+        return parse_error("only version 2 GLB files are supported".to_string());
+    }
+    let mut chunks = Vec::new();
+    let mut offset = 12;
+    while let (Some(length), Some(kind)) = (word(offset), word(offset + 4)) {
+        let start = offset + 8;
+        let end = start + length as usize;
+        match bytes.get(start..end) {
+            Some(data) => chunks.push((kind, data)),
+            None => return parse_error("truncated GLB chunk".to_string()),
+        }
+        offset = end;
+    }
+    match chunks.as_slice() {
+        [(GLB_JSON_CHUNK, json), rest @ ..] => Ok((
+            json,
+            rest.first()
+                .filter(|(kind, _)| *kind == GLB_BIN_CHUNK)
+                .map(|(_, bin)| *bin),
+        )),
+        _ => parse_error("GLB file doesn't start with a JSON chunk".to_string()),
+    }
+}
+
+struct Document {
+    json: Json,
+    buffers: Vec<Vec<u8>>,
+    directory: PathBuf,
+    /// Loaded once per texture and colour space, `None` if loading failed.
+    textures: HashMap<(usize, bool), Option<Arc<ImageTexture>>>,
+    /// By material index and whether the primitive has vertex colours.
+    materials: HashMap<(Option<usize>, bool), Material>,
+}
+
+/// Values of an accessor, `components` per element.
+struct Accessor {
+    values: Vec<f64>,
+    components: usize,
+}
+
+impl Accessor {
+    fn element(&self, i: usize) -> &[f64] {
+        &self.values[i * self.components..(i + 1) * self.components]
+    }
+
+    fn count(&self) -> usize {
+        self.values.len() / self.components
+    }
+}
+
+impl Document {
+    fn array(&self, name: &str) -> &[Json] {
+        self.json.get(name).map_or(&[], Json::elements)
+    }
+
+    fn item(&self, array: &str, index: usize) -> Result<&Json, LoadError> {
+        self.array(array)
+            .get(index)
+            .ok_or_else(|| LoadError::Parse(format!("{} {} doesn't exist", array, index)))
+    }
+
+    /// Contents of `uri`, either a base64 data URI or a path relative to the file.
+    fn read_uri(&self, uri: &str) -> Result<Vec<u8>, LoadError> {
+        if let Some(data) = uri.strip_prefix("data:") {
+            return match data.split_once(";base64,") {
+                Some((_, encoded)) => decode_base64(encoded),
+                None => parse_error("only base64 data URIs are supported".to_string()),
+            };
+        }
+        Ok(fs::read(self.directory.join(percent_decode(uri)))?)
+    }
+
+    fn load_buffers(&self, mut bin: Option<&[u8]>) -> Result<Vec<Vec<u8>>, LoadError> {
+        self.array("buffers")
+            .iter()
+            .map(|buffer| match buffer.get("uri").and_then(Json::as_str) {
+                Some(uri) => self.read_uri(uri),
+                // only the first buffer may refer to the GLB binary chunk
+                None => bin
+                    .take()
+                    .map(<[u8]>::to_vec)
+                    .ok_or_else(|| LoadError::Parse("buffer without data".to_string())),
+            })
+            .collect()
+    }
+
+    /// The bytes of a buffer view, and its stride if it has one.
+    fn buffer_view(&self, index: usize) -> Result<(&[u8], Option<usize>), LoadError> {
+        let view = self.item("bufferViews", index)?;
+        let buffer = view
+            .get("buffer")
+            .and_then(Json::as_usize)
+            .and_then(|i| self.buffers.get(i))
+            .ok_or_else(|| LoadError::Parse(format!("buffer view {} has no buffer", index)))?;
+        let offset = view.get("byteOffset").and_then(Json::as_usize).unwrap_or(0);
+        let length = view.get("byteLength").and_then(Json::as_usize).unwrap_or(0);
+        let bytes = offset
+            .checked_add(length)
+            .and_then(|end| buffer.get(offset..end))
+            .ok_or_else(|| {
+                LoadError::Parse(format!("buffer view {} is out of its buffer", index))
+            })?;
+        Ok((bytes, view.get("byteStride").and_then(Json::as_usize)))
+    }
+
+    fn accessor(&self, index: usize) -> Result<Accessor, LoadError> {
+        let accessor = self.item("accessors", index)?;
+        if accessor.get("sparse").is_some() {
+            return parse_error("sparse accessors aren't supported".to_string());
+        }
+        let count = accessor.get("count").and_then(Json::as_usize).unwrap_or(0);
+        let components = match accessor.get("type").and_then(Json::as_str) {
+            Some("SCALAR") => 1,
+            Some("VEC2") => 2,
+            Some("VEC3") => 3,
+            Some("VEC4") => 4,
+            other => return parse_error(format!("unsupported accessor type {:?}", other)),
+        };
+        let component_type = accessor
+            .get("componentType")
+            .and_then(Json::as_usize)
+            .unwrap_or(0);
+        let size = match component_type {
+            5120 | 5121 => 1,
+            5122 | 5123 => 2,
+            5125 | 5126 => 4,
+            _ => return parse_error(format!("unknown component type {}", component_type)),
+        };
+        let normalized = accessor
+            .get("normalized")
+            .and_then(Json::as_bool)
+            .unwrap_or(false);
+
+        let view = match accessor.get("bufferView").and_then(Json::as_usize) {
+            Some(view) => view,
+            // no data means all zeros
+            None => {
+                if count > MAX_ZERO_ACCESSOR_COUNT {
+                    return parse_error(format!("accessor {} is too large", index));
+                }
+                return Ok(Accessor {
+                    values: vec![0.0; count * components],
+                    components,
+                });
+            }
+        };
+        let (bytes, stride) = self.buffer_view(view)?;
+        let element_size = size * components;
+        let stride = match stride {
+            None => element_size,
+            Some(stride) if stride >= element_size && stride % 4 == 0 => stride,
+            Some(stride) => {
+                return parse_error(format!(
+                    "buffer view {} has a stride of {} for accessor {}",
+                    view, stride, index
+                ))
+            }
+        };
+        let offset = accessor
+            .get("byteOffset")
+            .and_then(Json::as_usize)
+            .unwrap_or(0);
+        // counts and offsets come straight from the file, so they mustn't overflow
+        let end = match count.checked_sub(1) {
+            Some(last) => last
+                .checked_mul(stride)
+                .and_then(|start| start.checked_add(offset))
+                .and_then(|start| start.checked_add(element_size)),
+            None => Some(0),
+        };
+        if end.is_none_or(|end| end > bytes.len()) {
+            return parse_error(format!("accessor {} is out of its buffer view", index));
+        }
+
+        let mut values = Vec::with_capacity(count * components);
+        for i in 0..count {
+            for c in 0..components {
+                let at = offset + i * stride + c * size;
+                let b = &bytes[at..at + size];
+                let value = match component_type {
+                    5120 => b[0] as i8 as f64,
+                    5121 => b[0] as f64,
+                    5122 => i16::from_le_bytes([b[0], b[1]]) as f64,
+                    5123 => u16::from_le_bytes([b[0], b[1]]) as f64,
+                    5125 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    _ => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                };
+                values.push(if normalized {
+                    match component_type {
+                        5120 => f64::max(value / 127.0, -1.0),
+                        5121 => value / 255.0,
+                        5122 => f64::max(value / 32767.0, -1.0),
+                        5123 => value / 65535.0,
+                        _ => value,
+                    }
+                } else {
+                    value
+                });
+            }
+        }
+        Ok(Accessor { values, components })
+    }
+
+    /// A failed load is reported once and leaves the parameter untextured.
+    fn texture(&mut self, info: Option<&Json>, srgb: bool) -> Option<Texture> {
+        let index = info?.get("index").and_then(Json::as_usize)?;
+        if let Some(texture) = self.textures.get(&(index, srgb)) {
+            return texture.clone().map(Texture::Image);
+        }
+        let texture = match self.load_texture(index, srgb) {
+            Ok(texture) => Some(Arc::new(texture)),
+            Err(e) => {
+                eprintln!("Couldn't load glTF texture {}: {}", index, e);
+                None
+            }
+        };
+        self.textures.insert((index, srgb), texture.clone());
+        texture.map(Texture::Image)
+    }
+
+    fn load_texture(&self, index: usize, srgb: bool) -> Result<ImageTexture, String> {
+        let texture = self.item("textures", index).map_err(|e| e.to_string())?;
+        let wrap = match texture
+            .get("sampler")
+            .and_then(Json::as_usize)
+            .and_then(|sampler| self.array("samplers").get(sampler))
+            .and_then(|sampler| sampler.get("wrapS"))
+            .and_then(Json::as_usize)
+        {
+            Some(33071) => WrapMode::Clamp,
+            Some(33648) => WrapMode::Mirror,
+            _ => WrapMode::Repeat,
+        };
+        let image = texture
+            .get("source")
+            .and_then(Json::as_usize)
+            .and_then(|source| self.array("images").get(source))
+            .ok_or_else(|| "texture without an image".to_string())?;
+        let bytes = match (
+            image.get("uri").and_then(Json::as_str),
+            image.get("bufferView").and_then(Json::as_usize),
+        ) {
+            (Some(uri), _) if !uri.starts_with("data:") => {
+                let path = self.directory.join(percent_decode(uri));
+                return ImageTexture::open(
+                    &path.to_string_lossy(),
+                    wrap,
+                    FilterMode::Trilinear,
+                    srgb,
+                )
+                .map_err(|e| e.to_string());
+            }
+            (Some(uri), _) => self.read_uri(uri).map_err(|e| e.to_string())?,
+            (None, Some(view)) => self
+                .buffer_view(view)
+                .map_err(|e| e.to_string())?
+                .0
+                .to_vec(),
+            (None, None) => return Err("image without data".to_string()),
+        };
+        ImageTexture::from_memory(&bytes, wrap, FilterMode::Trilinear, srgb)
+            .map_err(|e| e.to_string())
+    }
+
+    /// Maps a metallic-roughness material onto our BSDFs: transmissive materials become glass,
+    /// metals mirrors or tinted glossy lobes, and dielectrics glossy when smooth and Burley
+    /// diffuse when rough. Vertex colours stand in for the base colour when there's no texture.
+    fn material(&mut self, index: Option<usize>, vertex_colors: bool) -> Material {
+        if let Some(material) = self.materials.get(&(index, vertex_colors)) {
+            return material.clone();
+        }
+        let json = index
+            .and_then(|i| self.array("materials").get(i))
+            .cloned()
+            .unwrap_or(Json::Null);
+        let pbr = json.get("pbrMetallicRoughness");
+        let factor = |name: &str, default: f32| {
+            pbr.and_then(|pbr| pbr.get(name))
+                .and_then(Json::as_f32)
+                .unwrap_or(default)
+        };
+        let (metallic, roughness) = (
+            factor("metallicFactor", 1.0),
+            factor("roughnessFactor", 1.0),
+        );
+        let base_rgba = pbr
+            .and_then(|pbr| pbr.get("baseColorFactor"))
+            .and_then(Json::as_floats)
+            .filter(|c| c.len() == 4)
+            .unwrap_or_else(|| vec![1.0; 4]);
+        let base_color = Spectrum::new_f(base_rgba[0], base_rgba[1], base_rgba[2]);
+        let extension = |name: &str, field: &str| {
+            json.get("extensions")
+                .and_then(|e| e.get(name))
+                .and_then(|e| e.get(field))
+                .and_then(Json::as_f32)
+        };
+        let transmission =
+            extension("KHR_materials_transmission", "transmissionFactor").unwrap_or(0.0);
+        let ior = extension("KHR_materials_ior", "ior").unwrap_or(1.5);
+        let emissive_strength =
+            extension("KHR_materials_emissive_strength", "emissiveStrength").unwrap_or(1.0);
+        let emissive = json
+            .get("emissiveFactor")
+            .and_then(Json::as_floats)
+            .filter(|c| c.len() == 3)
+            .map_or_else(Spectrum::black, |c| {
+                Spectrum::new_f(c[0], c[1], c[2]) * emissive_strength
+            });
+
+        // Phong exponent with roughly the highlight of a GGX lobe with alpha = roughness^2
+        let alpha = roughness * roughness;
+        let exponent = (2.0 / (alpha * alpha).max(1e-6) - 2.0).clamp(1.0, MAX_PHONG_EXPONENT);
+        let (bsdf, reflectance, specular) = if transmission > 0.5 {
+            let ior = Ior::Constant(ior);
+            (BSDF::Dielectric { ior }, base_color, None)
+        } else if metallic >= 0.5 && roughness < 0.05 {
+            (BSDF::Specular, base_color, None)
+        } else if metallic >= 0.5 {
+            (
+                BSDF::Glossy { exponent },
+                Spectrum::black(),
+                Some(base_color),
+            )
+        } else if roughness < 0.5 {
+            let f0 = Spectrum::new_f(0.04, 0.04, 0.04);
+            (BSDF::Glossy { exponent }, base_color, Some(f0))
+        } else {
+            (BSDF::RetroDiffuse { roughness }, base_color, None)
+        };
+
+        let base_texture = self
+            .texture(pbr.and_then(|pbr| pbr.get("baseColorTexture")), true)
+            .or(if vertex_colors {
+                Some(Texture::VertexColor)
+            } else {
+                None
+            });
+        let mut material = Material::new(bsdf, reflectance, emissive);
+        material = match (specular, base_texture) {
+            // metals are coloured by their specular lobe
+            (Some(_), Some(texture)) if reflectance.is_black() => material.with_specular(texture),
+            (Some(specular), Some(texture)) => material
+                .with_reflectance(texture)
+                .with_specular(specular.into()),
+            (Some(specular), None) => material.with_specular(specular.into()),
+            (None, Some(texture)) => material.with_reflectance(texture),
+            (None, None) => material,
+        };
+        if !emissive.is_black() {
+            if let Some(texture) = self.texture(json.get("emissiveTexture"), true) {
+                material = material.with_emittance(texture);
+            }
+        }
+        if let Some(texture) = self.texture(json.get("normalTexture"), false) {
+            material = material.with_normal_map(texture);
+        }
+        // the base colour's alpha, from its texture times its factor, is the opacity
+        let alpha_mode = json.get("alphaMode").and_then(Json::as_str);
+        if alpha_mode == Some("BLEND") || alpha_mode == Some("MASK") {
+            let alpha = base_rgba[3];
+            let alpha_texture = self
+                .texture(pbr.and_then(|pbr| pbr.get("baseColorTexture")), true)
+                .filter(|texture| matches!(texture, Texture::Image(image) if image.has_alpha()));
+            let opacity = match alpha_texture {
+                Some(texture) if alpha < 1.0 => Some(Texture::Scaled(Arc::new(texture), alpha)),
+                Some(texture) => Some(texture),
+                None if alpha < 1.0 => Some(Spectrum::new_f(alpha, alpha, alpha).into()),
+                None => None,
+            };
+            if let Some(opacity) = opacity {
+                material = material.with_opacity(opacity);
+                if alpha_mode == Some("MASK") {
+                    let cutoff = json.get("alphaCutoff").and_then(Json::as_f32);
+                    material = material.with_opacity_cutoff(cutoff.unwrap_or(0.5));
+                }
+            }
+        }
+
+        self.materials
+            .insert((index, vertex_colors), material.clone());
+        material
+    }
+
+    /// All triangle primitives of a mesh merged together, `None` if there are none.
+    fn mesh(&mut self, index: usize) -> Result<Option<TriangleMesh>, LoadError> {
+        let primitives = self.item("meshes", index)?.get("primitives").cloned();
+        let mut mesh = TriangleMesh::empty();
+        for primitive in primitives.as_ref().map_or(&[][..], Json::elements) {
+            let attribute = |name: &str| {
+                primitive
+                    .get("attributes")
+                    .and_then(|a| a.get(name))
+                    .and_then(Json::as_usize)
+            };
+            let positions = match attribute("POSITION") {
+                Some(accessor) => self.accessor(accessor)?,
+                None => continue,
+            };
+            let vertex_count = positions.count();
+            let indices: Vec<u32> = match primitive.get("indices").and_then(Json::as_usize) {
+                Some(accessor) => self
+                    .accessor(accessor)?
+                    .values
+                    .iter()
+                    .map(|&i| i as u32)
+                    .collect(),
+                None => (0..vertex_count as u32).collect(),
+            };
+            let faces: Vec<[u32; 3]> = match primitive.get("mode").and_then(Json::as_usize) {
+                None | Some(4) => indices
+                    .chunks_exact(3)
+                    .map(|f| [f[0], f[1], f[2]])
+                    .collect(),
+                // strips alternate their winding
+                Some(5) => (2..indices.len())
+                    .map(|i| match i % 2 {
+                        0 => [indices[i - 2], indices[i - 1], indices[i]],
+                        _ => [indices[i - 1], indices[i - 2], indices[i]],
+                    })
+                    .collect(),
+                Some(6) => (2..indices.len())
+                    .map(|i| [indices[0], indices[i - 1], indices[i]])
+                    .collect(),
+                Some(mode) => {
+                    eprintln!("Skipping glTF primitive of mode {}, not triangles", mode);
+                    continue;
+                }
+            };
+            if let Some(&i) = indices.iter().find(|&&i| i as usize >= vertex_count) {
+                return parse_error(format!("index {} out of range in mesh {}", i, index));
+            }
+
+            let colors = match attribute("COLOR_0") {
+                Some(accessor) => Some(self.accessor(accessor)?),
+                None => None,
+            };
+            let material_index = primitive.get("material").and_then(Json::as_usize);
+            let material = self.material(material_index, colors.is_some());
+            let point = |p: &[f64]| Point::new(p[0] as f32, p[1] as f32, p[2] as f32);
+            let mut part = TriangleMesh::new(
+                (0..vertex_count)
+                    .map(|i| point(positions.element(i)))
+                    .collect(),
+                faces,
+                material,
+            );
+            if let Some(accessor) = attribute("NORMAL") {
+                let normals = self.accessor(accessor)?;
+                if normals.count() == vertex_count {
+                    part = part.with_normals(
+                        (0..vertex_count)
+                            .map(|i| point(normals.element(i)) - Point::origin())
+                            .collect(),
+                    );
+                }
+            }
+            if let Some(accessor) = attribute("TEXCOORD_0") {
+                let uvs = self.accessor(accessor)?;
+                if uvs.count() == vertex_count {
+                    // glTF puts the origin of uv space at the top left of images
+                    part = part.with_uvs(
+                        (0..vertex_count)
+                            .map(|i| (uvs.element(i)[0] as f32, 1.0 - uvs.element(i)[1] as f32))
+                            .collect(),
+                    );
+                }
+            }
+            if let Some(colors) = colors.filter(|c| c.count() == vertex_count && c.components >= 3)
+            {
+                part = part.with_colors(
+                    (0..vertex_count)
+                        .map(|i| {
+                            let c = colors.element(i);
+                            Spectrum::new_f(c[0] as f32, c[1] as f32, c[2] as f32)
+                        })
+                        .collect(),
+                );
+            }
+            mesh.append(part);
+        }
+        Ok(if mesh.is_empty() { None } else { Some(mesh) })
+    }
+
+    /// The node's own transform, `None` if it scales something to nothing.
+    fn node_transform(node: &Json) -> Option<Transform> {
+        if let Some(matrix) = node.get("matrix").and_then(Json::as_floats) {
+            let mut m = [0.0; 16];
+            m.copy_from_slice(matrix.get(..16)?);
+            return Transform::from_column_major(&m);
+        }
+        let vector = |name: &str, length: usize| {
+            node.get(name)
+                .and_then(Json::as_floats)
+                .filter(|v| v.len() == length)
+        };
+        let scale = vector("scale", 3).unwrap_or_else(|| vec![1.0; 3]);
+        if scale.contains(&0.0) {
+            return None;
+        }
+        let rotation = vector("rotation", 4).unwrap_or_else(|| vec![0.0, 0.0, 0.0, 1.0]);
+        let translation = vector("translation", 3).unwrap_or_else(|| vec![0.0; 3]);
+        Some(
+            Transform::scale(scale[0], scale[1], scale[2])
+                .then(&Transform::from_quaternion([
+                    rotation[0],
+                    rotation[1],
+                    rotation[2],
+                    rotation[3],
+                ]))
+                .then(&Transform::translation(Vector::new(
+                    translation[0],
+                    translation[1],
+                    translation[2],
+                ))),
+        )
+    }
+
+    /// Collects the world transforms of the node's meshes, and its camera and light.
+    fn visit(
+        &self,
+        index: usize,
+        parent: &Transform,
+        depth: usize,
+        placements: &mut Vec<(usize, Transform)>,
+        scene: &mut GltfScene,
+    ) -> Result<(), LoadError> {
+        if depth > MAX_NODE_DEPTH {
+            return parse_error("the node hierarchy has a cycle".to_string());
+        }
+        let node = self.item("nodes", index)?;
+        let transform = match Document::node_transform(node) {
+            Some(local) => local.then(parent),
+            // everything below is scaled away
+            None => return Ok(()),
+        };
+        if let Some(mesh) = node.get("mesh").and_then(Json::as_usize) {
+            placements.push((mesh, transform));
+        }
+        if let Some(camera) = node.get("camera").and_then(Json::as_usize) {
+            if scene.camera.is_none() {
+                scene.camera = self.camera(camera, &transform);
+            }
+        }
+        let light = node
+            .get("extensions")
+            .and_then(|e| e.get("KHR_lights_punctual"))
+            .and_then(|e| e.get("light"))
+            .and_then(Json::as_usize);
+        if let Some(light) = light {
+            scene.point_lights.extend(self.light(light, &transform));
+        }
+        for child in node.get("children").map_or(&[][..], Json::elements) {
+            let child = child
+                .as_usize()
+                .ok_or_else(|| LoadError::Parse(format!("bad child of node {}", index)))?;
+            self.visit(child, &transform, depth + 1, placements, scene)?;
+        }
+        Ok(())
+    }
+
+    fn camera(&self, index: usize, transform: &Transform) -> Option<Camera> {
+        let camera = self.array("cameras").get(index)?;
+        let fov = camera
+            .get("perspective")
+            .and_then(|p| p.get("yfov"))
+            .and_then(Json::as_f32);
+        match fov {
+            Some(fov) => Some(
+                Camera::new(transform.point(Point::origin()))
+                    .with_orientation(*transform)
+                    .with_fov(fov),
+            ),
+            None => {
+                eprintln!(
+                    "Skipping glTF camera {}, only perspective ones are supported",
+                    index
+                );
+                None
+            }
+        }
+    }
+
+    /// Intensities are in candela like ours, so they are taken as they are.
+    fn light(&self, index: usize, transform: &Transform) -> Option<PointLight> {
+        let light = self
+            .json
+            .get("extensions")
+            .and_then(|e| e.get("KHR_lights_punctual"))
+            .and_then(|e| e.get("lights"))
+            .map(Json::elements)
+            .and_then(|lights| lights.get(index))?;
+        let color = light
+            .get("color")
+            .and_then(Json::as_floats)
+            .filter(|c| c.len() == 3)
+            .map_or_else(Spectrum::white, |c| Spectrum::new_f(c[0], c[1], c[2]));
+        let intensity = color * light.get("intensity").and_then(Json::as_f32).unwrap_or(1.0);
+        let position = transform.point(Point::origin());
+        match light.get("type").and_then(Json::as_str) {
+            Some("point") => Some(PointLight::new(position, intensity)),
+            Some("spot") => {
+                let spot = light.get("spot");
+                let angle = |name: &str, default: f32| {
+                    spot.and_then(|s| s.get(name))
+                        .and_then(Json::as_f32)
+                        .unwrap_or(default)
+                };
+                Some(PointLight::spot(
+                    position,
+                    transform.vector(Vector::new(0.0, 0.0, -1.0)),
+                    intensity,
+                    angle("innerConeAngle", 0.0),
+                    angle("outerConeAngle", FRAC_PI_4),
+                ))
+            }
+            other => {
+                eprintln!("Skipping glTF light of type {:?}", other);
+                None
+            }
+        }
+    }
+
+    fn load_scene(&mut self) -> Result<GltfScene, LoadError> {
+        let roots: Vec<usize> = match self.json.get("scene").and_then(Json::as_usize).or(
+            if self.array("scenes").is_empty() {
+                None
+            } else {
+                Some(0)
+            },
+        ) {
+            Some(scene) => self
+                .item("scenes", scene)?
+                .get("nodes")
+                .map_or(&[][..], Json::elements)
+                .iter()
+                .filter_map(Json::as_usize)
+                .collect(),
+            // without scenes, every node that isn't a child is a root
+            None => {
+                let children: Vec<usize> = self
+                    .array("nodes")
+                    .iter()
+                    .flat_map(|node| node.get("children").map_or(&[][..], Json::elements))
+                    .filter_map(Json::as_usize)
+                    .collect();
+                (0..self.array("nodes").len())
+                    .filter(|i| !children.contains(i))
+                    .collect()
+            }
+        };
+
+        let mut scene = GltfScene {
+            meshes: Vec::new(),
+            instances: Vec::new(),
+            point_lights: Vec::new(),
+            camera: None,
+        };
+        let mut placements = Vec::new();
+        for root in roots {
+            self.visit(root, &Transform::identity(), 0, &mut placements, &mut scene)?;
+        }
+
+        let mut uses: HashMap<usize, usize> = HashMap::new();
+        for (index, _) in &placements {
+            *uses.entry(*index).or_insert(0) += 1;
+        }
+        let mut meshes: HashMap<usize, Option<Arc<Mesh>>> = HashMap::new();
+        for &(index, transform) in &placements {
+            if uses[&index] == 1 {
+                if let Some(mesh) = self.mesh(index)? {
+                    scene.meshes.push(bake(mesh, &transform));
+                }
+                continue;
+            }
+            let mesh = match meshes.entry(index) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    entry.insert(self.mesh(index)?.map(|mesh| Arc::new(Mesh::new(mesh))))
+                }
+            };
+            if let Some(mesh) = mesh {
+                scene.instances.push(Instance::new(mesh.clone(), transform));
+            }
+        }
+        Ok(scene)
+    }
+}
+
+/// Moves `mesh` into world space. Mirroring transforms get their winding flipped back, so the
+/// front stays where the normals point.
+fn bake(mut mesh: TriangleMesh, transform: &Transform) -> TriangleMesh {
+    for p in &mut mesh.positions {
+        *p = transform.point(*p);
+    }
+    for n in &mut mesh.normals {
+        *n = transform.normal(*n).normalized();
+    }
+    if transform.flips_handedness() {
+        for face in &mut mesh.faces {
+            face.swap(1, 2);
+        }
+    }
+    mesh
+}
+
+fn decode_base64(encoded: &str) -> Result<Vec<u8>, LoadError> {
+    let value = |c: u8| match c {
+        b'A'..=b'Z' => Some(c - b'A'),
+        b'a'..=b'z' => Some(c - b'a' + 26),
+        b'0'..=b'9' => Some(c - b'0' + 52),
+        b'+' | b'-' => Some(62),
+        b'/' | b'_' => Some(63),
+        _ => None,
+    };
+    let mut bytes = Vec::with_capacity(encoded.len() * 3 / 4);
+    let (mut bits, mut bit_count) = (0u32, 0);
+    for c in encoded
+        .bytes()
+        .filter(|c| !c.is_ascii_whitespace() && *c != b'=')
+    {
+        let v = value(c).ok_or_else(|| LoadError::Parse("invalid base64 data".to_string()))?;
+        bits = (bits << 6) | v as u32;
+        bit_count += 6;
+        if bit_count >= 8 {
+            bit_count -= 8;
+            bytes.push((bits >> bit_count) as u8);
+        }
+    }
+    Ok(bytes)
+}
+
+/// URIs escape spaces and other characters as %XX.
+fn percent_decode(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Ray, Scene};
+    use super::*;
+
+    fn base64(bytes: &[u8]) -> String {
+        const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        bytes
+            .chunks(3)
+            .flat_map(|chunk| {
+                let b = [
+                    chunk[0],
+                    *chunk.get(1).unwrap_or(&0),
+                    *chunk.get(2).unwrap_or(&0),
+                ];
+                let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+                (0..4).map(move |i| {
+                    if i <= chunk.len() {
+                        ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char
+                    } else {
+                        '='
+                    }
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn nodes_place_meshes_lights_and_the_camera() {
+        // one triangle, placed by two nodes so it's instanced, and by a third under a parent
+        let mut data = Vec::new();
+        for v in [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0].iter() {
+            data.extend_from_slice(&v.to_le_bytes());
+        }
+        for i in [0u16, 1, 2].iter() {
+            data.extend_from_slice(&i.to_le_bytes());
+        }
+        let gltf = format!(
+            r#"{{
+                "asset": {{"version": "2.0"}},
+                "buffers": [{{"byteLength": {}, "uri": "data:application/octet-stream;base64,{}"}}],
+                "bufferViews": [
+                    {{"buffer": 0, "byteOffset": 0, "byteLength": 36}},
+                    {{"buffer": 0, "byteOffset": 36, "byteLength": 6}}
+                ],
+                "accessors": [
+                    {{"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3"}},
+                    {{"bufferView": 1, "componentType": 5123, "count": 3, "type": "SCALAR"}}
+                ],
+                "materials": [{{
+                    "pbrMetallicRoughness": {{"baseColorFactor": [1, 0, 0, 1], "metallicFactor": 0}},
+                    "emissiveFactor": [1, 1, 1]
+                }}],
+                "meshes": [
+                    {{"primitives": [{{"attributes": {{"POSITION": 0}}, "indices": 1, "material": 0}}]}},
+                    {{"primitives": [{{"attributes": {{"POSITION": 0}}}}]}}
+                ],
+                "cameras": [{{"type": "perspective", "perspective": {{"yfov": 0.5, "znear": 0.1}}}}],
+                "extensions": {{"KHR_lights_punctual": {{"lights": [
+                    {{"type": "spot", "intensity": 10}}, {{"type": "directional"}}
+                ]}}}},
+                "scene": 0,
+                "scenes": [{{"nodes": [0, 1, 2, 4]}}],
+                "nodes": [
+                    {{"mesh": 0, "translation": [0, 0, -5]}},
+                    {{"mesh": 0, "scale": [-1, 1, 1]}},
+                    {{"translation": [0, 10, 0], "children": [3]}},
+                    {{"mesh": 1, "rotation": [0, 0, 0.7071068, 0.7071068],
+                      "extensions": {{"KHR_lights_punctual": {{"light": 0}}}}}},
+                    {{"camera": 0, "matrix": [1,0,0,0, 0,1,0,0, 0,0,1,0, 1,2,3,1],
+                      "extensions": {{"KHR_lights_punctual": {{"light": 1}}}}}}
+                ]
+            }}"#,
+            data.len(),
+            base64(&data)
+        );
+        let scene = parse(gltf.as_bytes(), PathBuf::new()).unwrap();
+
+        assert_eq!(scene.instances.len(), 2);
+        assert_eq!(scene.meshes.len(), 1);
+        // rotated a quarter around z, then moved up
+        let baked = &scene.meshes[0];
+        let p = baked.positions[1];
+        assert!(p.x().abs() < 1e-5 && (p.y() - 11.0).abs() < 1e-5);
+        assert!(baked.materials[0].emittance.is_black());
+
+        let instanced = &scene.instances[0].mesh.triangles;
+        assert!(!instanced.materials[0].emittance.is_black());
+        assert!(scene.instances[1].transform().flips_handedness());
+
+        assert_eq!(scene.point_lights.len(), 1);
+        assert!((scene.point_lights[0].position().y() - 10.0).abs() < 1e-5);
+
+        let camera = scene.camera.unwrap();
+        assert_eq!(camera.fov, Some(0.5));
+        assert_eq!(camera.position.z(), 3.0);
+
+        // only instances, so the scene has no objects of its own
+        let scene = Scene::new(Vec::new(), Vec::new(), Vec::new()).with_instances(scene.instances);
+        let down = Vector::new(0.0, 0.0, -1.0);
+        let hit = scene.intersect(Ray::new(Point::new(0.2, 0.2, 1.0), down));
+        assert!((hit.unwrap().distance() - 6.0).abs() < 1e-4);
+
+        assert!(parse(b"{\"asset\": {\"version\": \"1.0\"}}", PathBuf::new()).is_err());
+    }
+
+    #[test]
+    fn masked_materials_cut_out_the_base_colour_alpha() {
+        // a unit quad whose u follows x, textured with a mostly transparent left texel and a
+        // mostly opaque right one
+        let mut data = Vec::new();
+        let corners = [(0.0f32, 0.0f32), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)];
+        for &(x, y) in corners.iter() {
+            for v in [x, y, 0.0].iter() {
+                data.extend_from_slice(&v.to_le_bytes());
+            }
+        }
+        for &(u, v) in corners.iter() {
+            data.extend_from_slice(&u.to_le_bytes());
+            data.extend_from_slice(&(1.0 - v).to_le_bytes());
+        }
+        for i in [0u16, 1, 2, 0, 2, 3].iter() {
+            data.extend_from_slice(&i.to_le_bytes());
+        }
+        let mut png = Vec::new();
+        let pixels = vec![255, 255, 255, 100, 255, 255, 255, 150];
+        image::DynamicImage::ImageRgba8(image::RgbaImage::from_raw(2, 1, pixels).unwrap())
+            .write_to(&mut png, image::ImageFormat::Png)
+            .unwrap();
+        let gltf = |alpha_mode: &str| {
+            format!(
+                r#"{{
+                    "asset": {{"version": "2.0"}},
+                    "buffers": [{{"byteLength": {}, "uri": "data:application/octet-stream;base64,{}"}}],
+                    "bufferViews": [
+                        {{"buffer": 0, "byteOffset": 0, "byteLength": 48}},
+                        {{"buffer": 0, "byteOffset": 48, "byteLength": 32}},
+                        {{"buffer": 0, "byteOffset": 80, "byteLength": 12}}
+                    ],
+                    "accessors": [
+                        {{"bufferView": 0, "componentType": 5126, "count": 4, "type": "VEC3"}},
+                        {{"bufferView": 1, "componentType": 5126, "count": 4, "type": "VEC2"}},
+                        {{"bufferView": 2, "componentType": 5123, "count": 6, "type": "SCALAR"}}
+                    ],
+                    "images": [{{"uri": "data:image/png;base64,{}"}}],
+                    "samplers": [{{"wrapS": 33071}}],
+                    "textures": [{{"source": 0, "sampler": 0}}],
+                    "materials": [{{
+                        "pbrMetallicRoughness": {{
+                            "baseColorTexture": {{"index": 0}},
+                            "baseColorFactor": [1, 1, 1, 0.9]
+                        }},
+                        {}
+                    }}],
+                    "meshes": [{{"primitives": [{{
+                        "attributes": {{"POSITION": 0, "TEXCOORD_0": 1}}, "indices": 2, "material": 0
+                    }}]}}],
+                    "scenes": [{{"nodes": [0]}}],
+                    "nodes": [{{"mesh": 0}}]
+                }}"#,
+                data.len(),
+                base64(&data),
+                base64(&png),
+                alpha_mode
+            )
+        };
+        let hits = |alpha_mode: &str, x: f32| {
+            let parsed = parse(gltf(alpha_mode).as_bytes(), PathBuf::new()).unwrap();
+            let scene = Scene::new(parsed.meshes, Vec::new(), Vec::new());
+            let ray = Ray::new(Point::new(x, 0.5, 1.0), Vector::new(0.0, 0.0, -1.0));
+            scene.intersect(ray).is_some()
+        };
+
+        // texel alphas of 0.39 and 0.59, times the factor, against the cutoff
+        let mask = r#""alphaMode": "MASK", "alphaCutoff": 0.45"#;
+        assert!(!hits(mask, 0.25));
+        assert!(hits(mask, 0.75));
+        assert!(!hits(r#""alphaMode": "MASK", "alphaCutoff": 0.6"#, 0.75));
+        // opaque materials ignore the alpha
+        assert!(hits(r#""alphaMode": "OPAQUE""#, 0.25));
+    }
+
+    /// A triangle whose positions are read through `accessor`, given the JSON of its only
+    /// buffer view and accessor.
+    fn parse_triangle(view: &str, accessor: &str) -> Result<GltfScene, LoadError> {
+        let mut data = Vec::new();
+        for v in [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0].iter() {
+            data.extend_from_slice(&v.to_le_bytes());
+        }
+        let gltf = format!(
+            r#"{{
+                "asset": {{"version": "2.0"}},
+                "buffers": [{{"byteLength": 36, "uri": "data:application/octet-stream;base64,{}"}}],
+                "bufferViews": [{}],
+                "accessors": [{}],
+                "meshes": [{{"primitives": [{{"attributes": {{"POSITION": 0}}}}]}}],
+                "scenes": [{{"nodes": [0]}}],
+                "nodes": [{{"mesh": 0}}]
+            }}"#,
+            base64(&data),
+            view,
+            accessor
+        );
+        parse(gltf.as_bytes(), PathBuf::new())
+    }
+
+    const VIEW: &str = r#"{"buffer": 0, "byteLength": 36}"#;
+
+    #[test]
+    fn accessor_counts_are_checked_against_their_buffer_view() {
+        let accessor = |count: &str| {
+            format!(
+                r#"{{"bufferView": 0, "componentType": 5126, "count": {}, "type": "VEC3"}}"#,
+                count
+            )
+        };
+        assert!(parse_triangle(VIEW, &accessor("3")).is_ok());
+        assert!(parse_triangle(VIEW, &accessor("4")).is_err());
+        // a count this large overflows the accessor's end instead of pointing past it
+        assert!(parse_triangle(VIEW, &accessor("10000000000000000000")).is_err());
+    }
+
+    #[test]
+    fn accessors_without_data_are_capped() {
+        let accessor = |count: &str| {
+            format!(
+                r#"{{"componentType": 5126, "count": {}, "type": "VEC3"}}"#,
+                count
+            )
+        };
+        assert!(parse_triangle(VIEW, &accessor("3")).is_ok());
+        assert!(parse_triangle(VIEW, &accessor("1e18")).is_err());
+    }
+
+    #[test]
+    fn strides_must_fit_an_element_and_be_aligned() {
+        let view = |stride: usize| {
+            format!(
+                r#"{{"buffer": 0, "byteLength": 36, "byteStride": {}}}"#,
+                stride
+            )
+        };
+        let accessor = |count: &str| {
+            format!(
+                r#"{{"bufferView": 0, "componentType": 5126, "count": {}, "type": "VEC3"}}"#,
+                count
+            )
+        };
+        assert!(parse_triangle(&view(12), &accessor("3")).is_ok());
+        // a zero stride would read the first element over and over, as often as it's told to
+        assert!(parse_triangle(&view(0), &accessor("1e18")).is_err());
+        assert!(parse_triangle(&view(8), &accessor("3")).is_err());
+        assert!(parse_triangle(&view(14), &accessor("2")).is_err());
+    }
+
+    #[test]
+    fn buffer_views_are_checked_against_their_buffer() {
+        let accessor = r#"{"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3"}"#;
+        let view = |offset: &str, length: &str| {
+            format!(
+                r#"{{"buffer": 0, "byteOffset": {}, "byteLength": {}}}"#,
+                offset, length
+            )
+        };
+        assert!(parse_triangle(&view("0", "36"), accessor).is_ok());
+        assert!(parse_triangle(&view("4", "36"), accessor).is_err());
+        // an end past what a usize holds mustn't wrap around
+        assert!(parse_triangle(&view("18446744073709551615", "36"), accessor).is_err());
+    }
+}
@@ -0,0 +1,247 @@
+use std::f32::consts::PI;
+use std::fs;
+
+use super::{LoadError, Vector};
+
+/// Angular intensity distribution of a luminaire, from an IESNA LM-63 file with type C
+/// photometry. In the luminaire's local frame, +z points at the nadir (vertical angle 0) and
+/// +x is the horizontal angle 0.
+#[derive(Debug)]
+pub struct IesProfile {
+    /// Degrees from the nadir, increasing.
+    vertical_angles: Vec<f32>,
+    /// Degrees around the nadir, increasing. Their range tells the symmetry of the data.
+    horizontal_angles: Vec<f32>,
+    /// Candela, one row of vertical samples per horizontal angle.
+    candela: Vec<Vec<f32>>,
+    max_candela: f32,
+    lumens: f32,
+}
+
+impl IesProfile {
+    pub fn open(filename: &str) -> Result<IesProfile, LoadError> {
+        IesProfile::parse(&fs::read_to_string(filename)?)
+    }
+
+    pub fn parse(text: &str) -> Result<IesProfile, LoadError> {
+        let mut lines = text.lines();
+        // keywords and free text come before the TILT line
+        let tilt = loop {
+            match lines.next() {
+                Some(line) if line.trim_start().starts_with("TILT=") => {
+                    break line.trim_start()["TILT=".len()..].trim().to_string();
+                }
+                Some(_) => continue,
+                None => return Err(LoadError::Parse("missing TILT line".to_string())),
+            }
+        };
+
+        let rest: Vec<&str> = lines.collect();
+        let mut numbers = rest
+            .iter()
+            .flat_map(|line| line.split(|c: char| c.is_whitespace() || c == ','))
+            .filter(|token| !token.is_empty())
+            .map(|token| {
+                token
+                    .parse::<f32>()
+                    .map_err(|_| LoadError::Parse(format!("not a number: {}", token)))
+            });
+        let mut next = move || {
+            numbers
+                .next()
+                .unwrap_or_else(|| Err(LoadError::Parse("unexpected end of file".to_string())))
+        };
+
+        if tilt == "INCLUDE" {
+            // lamp-to-luminaire geometry, then pairs of angles and multipliers we don't use
+            next()?;
+            let pairs = next()? as usize;
+            for _ in 0..2 * pairs {
+                next()?;
+            }
+        }
+
+        let lamps = next()?;
+        let lumens_per_lamp = next()?;
+        let multiplier = next()?;
+        let vertical_count = next()? as usize;
+        let horizontal_count = next()? as usize;
+        let photometric_type = next()? as u32;
+        // units, width, length, height, ballast factor, ballast-lamp factor, input watts
+        for _ in 0..7 {
+            next()?;
+        }
+        if photometric_type != 1 {
+            return Err(LoadError::Parse(format!(
+                "only type C photometry is supported, not type {}",
+                photometric_type
+            )));
+        }
+        if vertical_count < 2 || horizontal_count == 0 {
+            return Err(LoadError::Parse("not enough angles".to_string()));
+        }
+
+        let vertical_angles = (0..vertical_count)
+            .map(|_| next())
+            .collect::<Result<Vec<_>, _>>()?;
+        let horizontal_angles = (0..horizontal_count)
+            .map(|_| next())
+            .collect::<Result<Vec<_>, _>>()?;
+        let candela = (0..horizontal_count)
+            .map(|_| {
+                (0..vertical_count)
+                    .map(|_| next().map(|c| c * multiplier))
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let max_candela = candela
+            .iter()
+            .flatten()
+            .fold(0.0, |max: f32, &c| max.max(c));
+
+        Ok(IesProfile {
+            vertical_angles,
+            horizontal_angles,
+            candela,
+            max_candela,
+            lumens: lamps * lumens_per_lamp,
+        })
+    }
+
+    /// Peak intensity in candela.
+    pub fn max_intensity(&self) -> f32 {
+        self.max_candela
+    }
+
+    /// Rated lumens of the lamps, or a negative value for absolute photometry.
+    pub fn lumens(&self) -> f32 {
+        self.lumens
+    }
+
+    /// Intensity in candela towards `direction`, given in the luminaire's local frame.
+    pub fn intensity(&self, direction: Vector) -> f32 {
+        let vertical = f32::acos(direction.z().clamp(-1.0, 1.0)).to_degrees();
+        let horizontal = f32::atan2(direction.y(), direction.x())
+            .to_degrees()
+            .rem_euclid(360.0);
+        self.intensity_at(vertical, horizontal)
+    }
+
+    /// Intensity towards `direction` relative to the peak, in [0, 1].
+    pub fn relative_intensity(&self, direction: Vector) -> f32 {
+        if self.max_candela > 0.0 {
+            self.intensity(direction) / self.max_candela
+        } else {
+            0.0
+        }
+    }
+
+    /// Relative intensity around the vertical plane through horizontal angles 0 and 180, as
+    /// (angle from the nadir going through 0 first, intensity) pairs for plotting.
+    pub fn polar_curve(&self, samples: usize) -> Vec<(f32, f32)> {
+        (0..=samples)
+            .map(|i| {
+                let angle = 2.0 * PI * i as f32 / samples as f32;
+                let (vertical, horizontal) = if angle <= PI {
+                    (angle.to_degrees(), 0.0)
+                } else {
+                    ((2.0 * PI - angle).to_degrees(), 180.0)
+                };
+                let intensity = if self.max_candela > 0.0 {
+                    self.intensity_at(vertical, horizontal) / self.max_candela
+                } else {
+                    0.0
+                };
+                (angle, intensity)
+            })
+            .collect()
+    }
+
+    /// Bilinear lookup, with the horizontal angle folded into the range the data covers.
+    fn intensity_at(&self, vertical: f32, horizontal: f32) -> f32 {
+        let last_horizontal = *self.horizontal_angles.last().unwrap();
+        let horizontal = if self.horizontal_angles.len() == 1 {
+            // rotationally symmetric
+            self.horizontal_angles[0]
+        } else if last_horizontal <= 90.0 {
+            // the same in each quadrant
+            let h = horizontal % 180.0;
+            if h > 90.0 {
+                180.0 - h
+            } else {
+                h
+            }
+        } else if last_horizontal <= 180.0 {
+            // symmetric about the 0-180 plane
+            if horizontal > 180.0 {
+                360.0 - horizontal
+            } else {
+                horizontal
+            }
+        } else {
+            horizontal
+        };
+
+        let first_vertical = self.vertical_angles[0];
+        let last_vertical = *self.vertical_angles.last().unwrap();
+        if vertical < first_vertical || vertical > last_vertical {
+            return 0.0;
+        }
+        let (h0, h1, th) = bracket(&self.horizontal_angles, horizontal);
+        let (v0, v1, tv) = bracket(&self.vertical_angles, vertical);
+        let row = |h: usize| self.candela[h][v0] * (1.0 - tv) + self.candela[h][v1] * tv;
+        row(h0) * (1.0 - th) + row(h1) * th
+    }
+}
+
+/// Indices of the samples around `x` in the sorted `angles` and how far between them it is,
+/// clamped to the ends.
+fn bracket(angles: &[f32], x: f32) -> (usize, usize, f32) {
+    let last = angles.len() - 1;
+    if last == 0 || x <= angles[0] {
+        return (0, 0, 0.0);
+    }
+    if x >= angles[last] {
+        return (last, last, 0.0);
+    }
+    let i = angles.partition_point(|&a| a <= x) - 1;
+    let span = angles[i + 1] - angles[i];
+    let t = if span > 0.0 {
+        (x - angles[i]) / span
+    } else {
+        0.0
+    };
+    (i, i + 1, t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DOWNLIGHT: &str = "IESNA:LM-63-2002
+[TEST] downlight
+TILT=NONE
+1 1000 1
+5 1 1 1 0 0 0
+1 1 50
+0 22.5 45 67.5 90
+0
+800 700 400 100 0
+";
+
+    #[test]
+    fn symmetric_downlight() {
+        let profile = IesProfile::parse(DOWNLIGHT).unwrap();
+        assert_eq!(profile.max_intensity(), 800.0);
+        assert_eq!(profile.lumens(), 1000.0);
+
+        let nadir = Vector::new(0.0, 0.0, 1.0);
+        assert_eq!(profile.intensity(nadir), 800.0);
+        // halfway between the 22.5 and 45 degree samples, in any horizontal direction
+        let angle = 33.75f32.to_radians();
+        let sideways = Vector::new(0.0, angle.sin(), angle.cos());
+        assert!((profile.intensity(sideways) - 550.0).abs() < 1e-2);
+        // nothing goes up
+        assert_eq!(profile.intensity(Vector::new(0.0, 0.0, -1.0)), 0.0);
+    }
+}
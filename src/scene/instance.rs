@@ -0,0 +1,193 @@
+use bvh::aabb::{Bounded, AABB};
+use bvh::bounding_hierarchy::BHShape;
+use bvh::bvh::{BVHNode, BVH};
+
+use std::mem::size_of;
+use std::sync::Arc;
+
+use super::objects::{Material, Object, Triangle};
+use super::triangle_mesh::TriangleMesh;
+use super::{Point, Ray, Transform};
+
+/// Triangles in their own object space with their own BVH, shared by any number of instances.
+pub struct Mesh {
+    pub(super) triangles: Arc<TriangleMesh>,
+    pub(super) objects: Vec<Object>,
+    pub(super) bvh: BVH,
+    aabb: AABB,
+}
+
+impl Mesh {
+    pub fn new(triangles: TriangleMesh) -> Mesh {
+        assert!(!triangles.is_empty(), "a mesh needs at least one triangle");
+        let triangles = Arc::new(triangles);
+        let mut objects: Vec<Object> = Triangle::from_mesh(&triangles)
+            .map(Object::Triangle)
+            .collect();
+        let aabb = objects
+            .iter()
+            .fold(AABB::empty(), |aabb, object| aabb.join(&object.aabb()));
+        let bvh = BVH::build(&mut objects);
+        Mesh {
+            triangles,
+            objects,
+            bvh,
+            aabb,
+        }
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.objects.len()
+    }
+
+    /// Bytes taken by the triangles, their BVH and the shared vertex data.
+    pub fn memory_usage(&self) -> usize {
+        self.objects.capacity() * size_of::<Object>()
+            + self.bvh.nodes.capacity() * size_of::<BVHNode>()
+            + self.triangles.memory_usage()
+    }
+}
+
+/// A mesh placed in the world by a transform, optionally with a material replacing the
+/// mesh's own. Emissive instances show up when bounced into, but aren't sampled as lights.
+pub struct Instance {
+    pub(super) mesh: Arc<Mesh>,
+    transform: Transform,
+    material: Option<Material>,
+    node_index: usize,
+}
+
+impl Instance {
+    pub fn new(mesh: Arc<Mesh>, transform: Transform) -> Instance {
+        Instance {
+            mesh,
+            transform,
+            material: None,
+            node_index: 0,
+        }
+    }
+
+    pub fn with_material(mut self, material: Material) -> Instance {
+        self.material = Some(material);
+        self
+    }
+
+    #[inline(always)]
+    pub fn transform(&self) -> &Transform {
+        &self.transform
+    }
+
+    /// Material of `object`, one of the mesh's triangles, on this instance.
+    #[inline(always)]
+    pub fn material<'a>(&'a self, object: &'a Object) -> &'a Material {
+        self.material.as_ref().unwrap_or_else(|| object.material())
+    }
+
+    /// The material replacing the mesh's own, if any.
+    #[inline(always)]
+    pub fn material_override(&self) -> Option<&Material> {
+        self.material.as_ref()
+    }
+
+    /// Whether any triangle bounds an interior medium.
+    pub fn has_interior(&self) -> bool {
+        match &self.material {
+            Some(material) => material.interior.is_some(),
+            None => self
+                .mesh
+                .objects
+                .iter()
+                .any(|object| object.material().interior.is_some()),
+        }
+    }
+
+    /// `ray` brought into object space, and the object space length of one world unit along
+    /// it. Distances along the ray are multiplied by that scale going into object space.
+    #[inline(always)]
+    pub fn object_ray(&self, ray: &Ray) -> (Ray, f32) {
+        let direction = self.transform.inverse_vector(ray.direction);
+        let scale = direction.norm();
+        let object_ray = Ray::new_prenormalized(
+            self.transform.inverse_point(ray.origin),
+            direction * (1.0 / scale),
+        )
+        .with_cone(ray.cone_width * scale, ray.cone_spread);
+        (object_ray, scale)
+    }
+}
+
+impl Bounded for Instance {
+    fn aabb(&self) -> AABB {
+        let (min, max) = (self.mesh.aabb.min, self.mesh.aabb.max);
+        let mut aabb = AABB::empty();
+        for &x in [min.x, max.x].iter() {
+            for &y in [min.y, max.y].iter() {
+                for &z in [min.z, max.z].iter() {
+                    let corner = self.transform.point(Point::new(x, y, z));
+                    aabb.grow_mut(&bvh::nalgebra::Point3::new(
+                        corner.x(),
+                        corner.y(),
+                        corner.z(),
+                    ));
+                }
+            }
+        }
+        aabb
+    }
+}
+
+impl BHShape for Instance {
+    fn set_bh_node_index(&mut self, index: usize) {
+        self.node_index = index;
+    }
+
+    fn bh_node_index(&self) -> usize {
+        self.node_index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::objects::BSDF;
+    use super::super::{Scene, Sphere, Vector};
+    use super::*;
+    use crate::common::Spectrum;
+
+    #[test]
+    fn rays_hit_instances_in_world_space() {
+        let material = Material::new(BSDF::Diffuse, Spectrum::grey(), Spectrum::black());
+        let mesh = Arc::new(Mesh::new(TriangleMesh::new(
+            vec![
+                Point::new(0.0, 0.0, 0.0),
+                Point::new(1.0, 0.0, 0.0),
+                Point::new(0.0, 1.0, 0.0),
+            ],
+            vec![[0, 1, 2]],
+            material.clone(),
+        )));
+        // stretched, turned a quarter around z so x becomes y, and pushed back
+        let transform = Transform::scale(2.0, 4.0, 1.0)
+            .then(&Transform::rotation(
+                Vector::new(0.0, 0.0, 1.0),
+                std::f32::consts::FRAC_PI_2,
+            ))
+            .then(&Transform::translation(Vector::new(0.0, 0.0, -10.0)));
+        let far_away = Sphere::new(Point::new(0.0, 100.0, 0.0), 1.0, material);
+        let scene = Scene::new(Vec::new(), vec![far_away], Vec::new())
+            .with_instances(vec![Instance::new(mesh, transform)]);
+
+        let down = Vector::new(0.0, 0.0, -1.0);
+        let hit = scene
+            .intersect(Ray::new(Point::new(-1.0, 0.5, 0.0), down))
+            .unwrap();
+        assert!((hit.distance() - 10.0).abs() < 1e-4);
+        assert!((hit.geometric_normal().z() - 1.0).abs() < 1e-4);
+        assert!(scene.is_occluded(&Ray::new(Point::new(-1.0, 0.5, 0.0), down), 20.0));
+        assert!(!scene.is_occluded(&Ray::new(Point::new(-1.0, 0.5, 0.0), down), 5.0));
+
+        // where the triangle would be without the rotation
+        assert!(scene
+            .intersect(Ray::new(Point::new(1.0, 0.5, 0.0), down))
+            .is_none());
+    }
+}
@@ -0,0 +1,99 @@
+use std::sync::Arc;
+
+use super::ies::IesProfile;
+use super::{Frame, Point, Vector};
+use crate::common::Spectrum;
+use crate::spectral::Wavelengths;
+
+/// A light without area. It can't be hit by rays, only reached through light sampling.
+pub struct PointLight {
+    position: Point,
+    /// Intensity in candela, or a tint of the profile's candela when there is one.
+    intensity: Spectrum,
+    /// Local z is where the light points.
+    frame: Frame,
+    /// Cosines of the inner and outer cone half-angles of a spotlight.
+    cone: Option<(f32, f32)>,
+    profile: Option<Arc<IesProfile>>,
+}
+
+impl PointLight {
+    /// A light shining equally in every direction.
+    pub fn new(position: Point, intensity: Spectrum) -> PointLight {
+        PointLight {
+            position,
+            intensity,
+            frame: Frame::from_normal(Vector::new(0.0, -1.0, 0.0)),
+            cone: None,
+            profile: None,
+        }
+    }
+
+    /// A light shining towards `direction`, at full intensity up to `inner_angle` from it and
+    /// fading out by `outer_angle` (both in radians).
+    pub fn spot(
+        position: Point,
+        direction: Vector,
+        intensity: Spectrum,
+        inner_angle: f32,
+        outer_angle: f32,
+    ) -> PointLight {
+        PointLight {
+            position,
+            intensity,
+            frame: Frame::from_normal(direction.normalized()),
+            cone: Some((f32::cos(inner_angle), f32::cos(outer_angle))),
+            profile: None,
+        }
+    }
+
+    /// A luminaire with a measured distribution, its nadir pointing towards `direction`.
+    /// `tint` multiplies the profile's candela values.
+    pub fn with_profile(
+        position: Point,
+        direction: Vector,
+        tint: Spectrum,
+        profile: Arc<IesProfile>,
+    ) -> PointLight {
+        PointLight {
+            position,
+            intensity: tint,
+            frame: Frame::from_normal(direction.normalized()),
+            cone: None,
+            profile: Some(profile),
+        }
+    }
+
+    pub fn position(&self) -> Point {
+        self.position
+    }
+
+    pub fn profile(&self) -> Option<&Arc<IesProfile>> {
+        self.profile.as_ref()
+    }
+
+    /// Intensity emitted towards `direction` (normalized, pointing away from the light).
+    pub fn intensity(&self, direction: Vector, wavelengths: &Wavelengths) -> Spectrum {
+        let local = self.frame.to_local(direction);
+        let mut scale = 1.0;
+        if let Some((cos_inner, cos_outer)) = self.cone {
+            scale *= smoothstep(cos_outer, cos_inner, local.z());
+        }
+        if let Some(profile) = &self.profile {
+            scale *= profile.intensity(local);
+        }
+        if scale <= 0.0 {
+            return Spectrum::black();
+        }
+        wavelengths.upsample(self.intensity) * scale
+    }
+}
+
+#[inline(always)]
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    if edge1 <= edge0 {
+        return if x >= edge1 { 1.0 } else { 0.0 };
+    }
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
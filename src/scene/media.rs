@@ -0,0 +1,412 @@
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+use super::volume::VoxelGrid;
+use super::{Frame, Point, Ray, Vector};
+use crate::common::Spectrum;
+use crate::spectral::Wavelengths;
+
+/// Henyey-Greenstein phase function. `g` in (-1, 1) is the mean cosine of the scattering
+/// angle: positive scatters forward, negative backward, 0 is isotropic.
+#[derive(Clone, Copy, Debug)]
+pub struct HenyeyGreenstein {
+    g: f32,
+}
+
+impl HenyeyGreenstein {
+    pub fn new(g: f32) -> HenyeyGreenstein {
+        HenyeyGreenstein {
+            g: g.clamp(-0.99, 0.99),
+        }
+    }
+
+    /// Density of scattering from travelling along `direction` to travelling along `wi`.
+    #[inline(always)]
+    pub fn evaluate(&self, direction: Vector, wi: Vector) -> f32 {
+        let g = self.g;
+        let cos_theta = direction.dot(wi);
+        let denominator = 1.0 + g * g - 2.0 * g * cos_theta;
+        (1.0 - g * g) / (4.0 * PI * denominator * f32::sqrt(denominator))
+    }
+
+    /// Samples a new direction proportionally to the phase function, so its weight is 1.
+    pub fn sample(&self, direction: Vector) -> Vector {
+        let g = self.g;
+        let u = fastrand::f32();
+        let cos_theta = if g.abs() < 1e-3 {
+            1.0 - 2.0 * u
+        } else {
+            let sq = (1.0 - g * g) / (1.0 - g + 2.0 * g * u);
+            (1.0 + g * g - sq * sq) / (2.0 * g)
+        };
+        let sin_theta = f32::sqrt(f32::max(0.0, 1.0 - cos_theta * cos_theta));
+        let (sin_phi, cos_phi) = f32::sin_cos(2.0 * PI * fastrand::f32());
+        Frame::from_normal(direction).to_world(Vector::new(
+            sin_theta * cos_phi,
+            sin_theta * sin_phi,
+            cos_theta,
+        ))
+    }
+}
+
+/// Outcome of sampling how far a ray gets through a medium.
+pub enum FreeFlight {
+    /// The ray scatters `distance` along, and its radiance is weighted by `weight`.
+    Scatter { distance: f32, weight: Spectrum },
+    /// The ray makes it to the next surface, weighted by `weight`.
+    Pass { weight: Spectrum },
+}
+
+/// A medium with the same properties everywhere, like fog or murky water. Coefficients are
+/// per scene unit.
+#[derive(Clone, Debug)]
+pub struct HomogeneousMedium {
+    sigma_a: Spectrum,
+    sigma_s: Spectrum,
+    phase: HenyeyGreenstein,
+    /// Made for a random walk under a surface by `from_albedo`.
+    subsurface: bool,
+}
+
+impl HomogeneousMedium {
+    pub fn new(sigma_a: Spectrum, sigma_s: Spectrum, g: f32) -> HomogeneousMedium {
+        HomogeneousMedium {
+            sigma_a,
+            sigma_s,
+            phase: HenyeyGreenstein::new(g),
+            subsurface: false,
+        }
+    }
+
+    /// A dense medium scattering light back out with the given overall `albedo`, after it
+    /// travels `mean_free_path` on average between events, for random-walk subsurface
+    /// scattering. Single-scattering albedos come from the inversion in Chiang et al.'s
+    /// "Practical and Controllable Subsurface Scattering for Production Path Tracing".
+    pub fn from_albedo(albedo: Spectrum, mean_free_path: Spectrum, g: f32) -> HomogeneousMedium {
+        let [a_r, a_g, a_b] = albedo.rgb();
+        let [d_r, d_g, d_b] = mean_free_path.rgb();
+        let single_scattering = |albedo: f32| {
+            let albedo = albedo.clamp(0.0, 1.0);
+            let x = 4.09712 + 4.20863 * albedo
+                - f32::sqrt(9.59217 + 41.6808 * albedo + 17.7126 * albedo * albedo);
+            (1.0 - x * x).clamp(0.0, 1.0)
+        };
+        // absorption and scattering coefficients of one channel
+        let channel = |albedo: f32, distance: f32| {
+            let sigma_t = 1.0 / f32::max(distance, 1e-4);
+            let sigma_s = single_scattering(albedo) * sigma_t;
+            (sigma_t - sigma_s, sigma_s)
+        };
+        let (sigma_a_r, sigma_s_r) = channel(a_r, d_r);
+        let (sigma_a_g, sigma_s_g) = channel(a_g, d_g);
+        let (sigma_a_b, sigma_s_b) = channel(a_b, d_b);
+        HomogeneousMedium {
+            subsurface: true,
+            ..HomogeneousMedium::new(
+                Spectrum::new_f(sigma_a_r, sigma_a_g, sigma_a_b),
+                Spectrum::new_f(sigma_s_r, sigma_s_g, sigma_s_b),
+                g,
+            )
+        }
+    }
+
+    pub fn phase(&self) -> &HenyeyGreenstein {
+        &self.phase
+    }
+
+    #[inline(always)]
+    fn coefficients(&self, wavelengths: &Wavelengths) -> (Spectrum, Spectrum) {
+        let sigma_s = wavelengths.upsample(self.sigma_s);
+        (wavelengths.upsample(self.sigma_a) + sigma_s, sigma_s)
+    }
+
+    /// Fraction of light making it `distance` through the medium.
+    pub fn transmittance(&self, distance: f32, wavelengths: &Wavelengths) -> Spectrum {
+        let (sigma_t, _) = self.coefficients(wavelengths);
+        beer_lambert(sigma_t, distance)
+    }
+
+    /// Picks where a ray travelling up to `max_distance` (possibly infinite) through the
+    /// medium interacts. Distances are sampled for one randomly chosen channel, and weights
+    /// use the density averaged over all of them so every channel stays unbiased.
+    pub fn sample_free_flight(&self, max_distance: f32, wavelengths: &Wavelengths) -> FreeFlight {
+        let (sigma_t, sigma_s) = self.coefficients(wavelengths);
+        let channel_sigma_t = sigma_t.rgb()[fastrand::usize(0..3)];
+        let distance = if channel_sigma_t > 0.0 {
+            -f32::ln(1.0 - fastrand::f32()) / channel_sigma_t
+        } else {
+            f32::INFINITY
+        };
+
+        if distance < max_distance {
+            let transmittance = beer_lambert(sigma_t, distance);
+            let pdf = (sigma_t * transmittance).average();
+            FreeFlight::Scatter {
+                distance,
+                weight: sigma_s * transmittance * (1.0 / pdf),
+            }
+        } else {
+            let transmittance = beer_lambert(sigma_t, max_distance);
+            let pdf = transmittance.average();
+            let weight = if pdf > 0.0 {
+                transmittance * (1.0 / pdf)
+            } else {
+                Spectrum::black()
+            };
+            FreeFlight::Pass { weight }
+        }
+    }
+}
+
+/// A medium whose density comes from a voxel grid stretched over an axis-aligned box, like
+/// smoke from a simulation. Extinction is the same at every wavelength, `albedo` colours
+/// what gets scattered.
+#[derive(Debug)]
+pub struct GridMedium {
+    grid: Arc<VoxelGrid>,
+    min: Point,
+    max: Point,
+    /// Extinction coefficient per scene unit at density 1.
+    sigma_t: f32,
+    albedo: Spectrum,
+    phase: HenyeyGreenstein,
+    /// Upper bound of the extinction anywhere in the box.
+    majorant: f32,
+}
+
+impl GridMedium {
+    pub fn new(
+        grid: Arc<VoxelGrid>,
+        min: Point,
+        max: Point,
+        sigma_t: f32,
+        albedo: Spectrum,
+        g: f32,
+    ) -> GridMedium {
+        let majorant = grid.max_density() * sigma_t;
+        GridMedium {
+            grid,
+            min,
+            max,
+            sigma_t,
+            albedo,
+            phase: HenyeyGreenstein::new(g),
+            majorant,
+        }
+    }
+
+    /// Extinction coefficient at a point inside the box.
+    #[inline(always)]
+    fn extinction(&self, point: Point) -> f32 {
+        let u = |p: f32, min: f32, max: f32| (p - min) / (max - min);
+        let uvw = [
+            u(point.x(), self.min.x(), self.max.x()),
+            u(point.y(), self.min.y(), self.max.y()),
+            u(point.z(), self.min.z(), self.max.z()),
+        ];
+        self.grid.density(uvw) * self.sigma_t
+    }
+
+    /// The part of the ray up to `max_distance` that's inside the box, if any.
+    fn clip(&self, ray: &Ray, max_distance: f32) -> Option<(f32, f32)> {
+        let mut near = 0.0f32;
+        let mut far = max_distance;
+        let axes = [
+            (
+                ray.origin.x(),
+                ray.direction.x(),
+                self.min.x(),
+                self.max.x(),
+            ),
+            (
+                ray.origin.y(),
+                ray.direction.y(),
+                self.min.y(),
+                self.max.y(),
+            ),
+            (
+                ray.origin.z(),
+                ray.direction.z(),
+                self.min.z(),
+                self.max.z(),
+            ),
+        ];
+        for &(origin, direction, min, max) in axes.iter() {
+            let inv = 1.0 / direction;
+            let (t0, t1) = ((min - origin) * inv, (max - origin) * inv);
+            let (t0, t1) = if t0 < t1 { (t0, t1) } else { (t1, t0) };
+            near = near.max(t0);
+            far = far.min(t1);
+        }
+        if near < far {
+            Some((near, far))
+        } else {
+            None
+        }
+    }
+
+    /// Delta tracking: tentative collisions at the majorant rate, real with probability of
+    /// the actual extinction over it.
+    fn sample_free_flight(
+        &self,
+        ray: &Ray,
+        max_distance: f32,
+        wavelengths: &Wavelengths,
+    ) -> FreeFlight {
+        let pass = FreeFlight::Pass {
+            weight: Spectrum::white(),
+        };
+        let (mut t, far) = match self.clip(ray, max_distance) {
+            Some(span) if self.majorant > 0.0 => span,
+            _ => return pass,
+        };
+        loop {
+            t -= f32::ln(1.0 - fastrand::f32()) / self.majorant;
+            if t >= far {
+                return pass;
+            }
+            let point = ray.origin + ray.direction * t;
+            if fastrand::f32() * self.majorant < self.extinction(point) {
+                return FreeFlight::Scatter {
+                    distance: t,
+                    weight: wavelengths.upsample(self.albedo),
+                };
+            }
+        }
+    }
+
+    /// Ratio tracking: an unbiased estimate that steps through the same tentative collisions
+    /// as delta tracking, multiplying in the chance of each being a null one.
+    fn transmittance(&self, ray: &Ray, distance: f32) -> Spectrum {
+        let (mut t, far) = match self.clip(ray, distance) {
+            Some(span) if self.majorant > 0.0 => span,
+            _ => return Spectrum::white(),
+        };
+        let mut transmittance = 1.0;
+        loop {
+            t -= f32::ln(1.0 - fastrand::f32()) / self.majorant;
+            if t >= far {
+                return Spectrum::white() * transmittance;
+            }
+            let point = ray.origin + ray.direction * t;
+            transmittance *= 1.0 - self.extinction(point) / self.majorant;
+        }
+    }
+}
+
+/// Anything light can travel through and scatter in.
+#[derive(Debug)]
+pub enum Medium {
+    Homogeneous(HomogeneousMedium),
+    Grid(GridMedium),
+}
+
+impl Medium {
+    /// Whether paths may be ended at random where they scatter in the medium. Random walks
+    /// under a surface take many short steps, and the albedo in their free flight weights
+    /// already fades them out.
+    pub fn allows_russian_roulette(&self) -> bool {
+        match self {
+            Medium::Homogeneous(medium) => !medium.subsurface,
+            Medium::Grid(_) => true,
+        }
+    }
+
+    pub fn phase(&self) -> &HenyeyGreenstein {
+        match self {
+            Medium::Homogeneous(medium) => medium.phase(),
+            Medium::Grid(medium) => &medium.phase,
+        }
+    }
+
+    /// Fraction of light making it `distance` along `ray`.
+    pub fn transmittance(&self, ray: &Ray, distance: f32, wavelengths: &Wavelengths) -> Spectrum {
+        match self {
+            Medium::Homogeneous(medium) => medium.transmittance(distance, wavelengths),
+            Medium::Grid(medium) => medium.transmittance(ray, distance),
+        }
+    }
+
+    /// Picks where `ray` interacts with the medium before `max_distance` (possibly infinite).
+    pub fn sample_free_flight(
+        &self,
+        ray: &Ray,
+        max_distance: f32,
+        wavelengths: &Wavelengths,
+    ) -> FreeFlight {
+        match self {
+            Medium::Homogeneous(medium) => medium.sample_free_flight(max_distance, wavelengths),
+            Medium::Grid(medium) => medium.sample_free_flight(ray, max_distance, wavelengths),
+        }
+    }
+}
+
+impl From<HomogeneousMedium> for Medium {
+    fn from(medium: HomogeneousMedium) -> Medium {
+        Medium::Homogeneous(medium)
+    }
+}
+
+impl From<GridMedium> for Medium {
+    fn from(medium: GridMedium) -> Medium {
+        Medium::Grid(medium)
+    }
+}
+
+#[inline(always)]
+fn beer_lambert(sigma_t: Spectrum, distance: f32) -> Spectrum {
+    let channel = |sigma: f32| {
+        if sigma > 0.0 {
+            f32::exp(-sigma * distance)
+        } else {
+            1.0
+        }
+    };
+    let [r, g, b] = sigma_t.rgb();
+    Spectrum::new_f(channel(r), channel(g), channel(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn henyey_greenstein_integrates_to_one() {
+        let phase = HenyeyGreenstein::new(0.7);
+        let forward = Vector::new(0.0, 0.0, 1.0);
+        // integrate over the sphere in cos theta, the function doesn't depend on phi
+        let n = 10000;
+        let integral: f32 = (0..n)
+            .map(|i| {
+                let cos_theta = -1.0 + 2.0 * (i as f32 + 0.5) / n as f32;
+                let wi = Vector::new(f32::sqrt(1.0 - cos_theta * cos_theta), 0.0, cos_theta);
+                phase.evaluate(forward, wi) * 2.0 * PI * (2.0 / n as f32)
+            })
+            .sum();
+        assert!((integral - 1.0).abs() < 1e-2, "{}", integral);
+
+        // forward scattering media mostly keep going
+        let mean_cos: f32 = (0..n).map(|_| phase.sample(forward).z()).sum::<f32>() / n as f32;
+        assert!((mean_cos - 0.7).abs() < 0.05, "{}", mean_cos);
+    }
+
+    #[test]
+    fn subsurface_albedo_inversion() {
+        let medium = HomogeneousMedium::from_albedo(
+            Spectrum::new_f(0.0, 0.5, 1.0),
+            Spectrum::new_f(1.0, 2.0, 0.5),
+            0.0,
+        );
+        let [a_r, a_g, a_b] = medium.sigma_a.rgb();
+        let [s_r, s_g, s_b] = medium.sigma_s.rgb();
+        // extinction is one over the mean free path
+        assert!((a_r + s_r - 1.0).abs() < 1e-4);
+        assert!((a_g + s_g - 0.5).abs() < 1e-4);
+        assert!((a_b + s_b - 2.0).abs() < 1e-4);
+        // black absorbs everything, white nothing, and it takes a high single-scattering
+        // albedo for half the light to make it back out
+        assert!(s_r < 1e-3);
+        assert!(a_b < 1e-3);
+        assert!(s_g / 0.5 > 0.85);
+    }
+}
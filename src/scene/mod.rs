@@ -1,12 +1,50 @@
 use bvh::bvh::{BVHNode, BVH};
 
+mod camera;
 mod geo;
+mod gltf;
+mod ies;
+mod instance;
+mod json;
+mod lights;
+mod media;
+mod model;
+mod mtl;
 mod objects;
+mod ply;
+mod procedural;
+mod shapes;
+mod stl;
+mod subdivision;
+mod texture;
+mod triangle_mesh;
+mod volume;
 
-pub use geo::{Point, Ray, Vector};
-use objects::{Material, Object, Sphere, Triangle, BSDF};
+pub use camera::Camera;
+pub use geo::{offset_ray_origin, Frame, Point, Ray, Transform, Vector};
+pub use ies::IesProfile;
+pub use instance::{Instance, Mesh};
+use lights::PointLight;
+pub use media::{FreeFlight, GridMedium, HomogeneousMedium, Medium};
+pub use model::Model;
+use mtl::MtlConverter;
+use objects::{LightColor, LightPower, Material, Object, Sphere, Triangle, BSDF};
+use procedural::{ColorRamp, Pattern, ProceduralTexture, TextureSpace};
+use shapes::Shape;
+pub use subdivision::{PolygonMesh, Scheme, Subdivision};
+use texture::Texture;
+pub use texture::TextureContext;
+pub use triangle_mesh::{NormalOptions, TriangleMesh};
+use volume::VoxelGrid;
 
-use crate::common::{Spectrum, EPS};
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::mem::size_of;
+use std::sync::Arc;
+
+use crate::common::Spectrum;
+use crate::spectral::{Ior, Metal, Wavelengths};
 
 use std::cell::RefCell;
 
@@ -14,18 +52,62 @@ use std::cell::RefCell;
 thread_local! {
     static BVH_STACK: RefCell<Vec<usize>> = RefCell::new(Vec::with_capacity(64));
     static BVH_SHADOW_STACK: RefCell<Vec<usize>> = RefCell::new(Vec::with_capacity(64));
+    static INSTANCE_STACK: RefCell<Vec<usize>> = RefCell::new(Vec::with_capacity(64));
+    static MESH_STACK: RefCell<Vec<usize>> = RefCell::new(Vec::with_capacity(64));
+}
+
+/// Why a file couldn't be loaded, for every format read from disk.
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    /// The file is malformed or uses something that isn't supported.
+    Parse(String),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io(e) => write!(f, "{}", e),
+            LoadError::Parse(message) => write!(f, "invalid file: {}", message),
+        }
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(e: io::Error) -> LoadError {
+        LoadError::Io(e)
+    }
+}
+
+fn parse_error<T>(message: String) -> Result<T, LoadError> {
+    Err(LoadError::Parse(message))
 }
 
 /// The Scene is static. Please don't change it unless you update the acceleration structures!
 pub struct Scene {
     objects: Vec<Object>,
     bvh: BVH,
+    /// Vertex data of the triangles among `objects`.
+    meshes: Vec<Arc<TriangleMesh>>,
+    /// Placed meshes, with a BVH over their world space boxes on top of each mesh's own.
+    instances: Vec<Instance>,
+    instance_bvh: Option<BVH>,
     light_indexes: Vec<usize>,
+    point_lights: Vec<PointLight>,
+    /// Medium filling the space outside of every object.
+    fog: Option<Medium>,
+    /// Whether any surface bounds an interior medium, so shadow rays have to look for them.
+    has_interiors: bool,
+    /// Where renders start out looking from.
+    camera: Camera,
 }
 
 pub struct RayIntersection<'a> {
     distance: f32,
     object: &'a Object,
+    /// The instance `object` belongs to, if it's part of a mesh. The object is then in the
+    /// mesh's object space while the ray and distance stay in world space.
+    instance: Option<&'a Instance>,
     ray: Ray,
 }
 
@@ -35,6 +117,7 @@ impl<'a> RayIntersection<'a> {
         RayIntersection {
             distance,
             object,
+            instance: None,
             ray,
         }
     }
@@ -49,26 +132,195 @@ impl<'a> RayIntersection<'a> {
         self.object
     }
 
+    /// Material of the surface hit, which an instance may have replaced.
+    #[inline(always)]
+    pub fn material(&self) -> &'a Material {
+        match self.instance {
+            Some(instance) => instance.material(self.object),
+            None => self.object.material(),
+        }
+    }
+
+    /// Whether the surface is only there to bound a medium, and rays go straight through it.
+    #[inline(always)]
+    pub fn is_medium_boundary(&self) -> bool {
+        self.material().is_medium_boundary()
+    }
+
     #[inline(always)]
     pub fn ray(&self) -> &Ray {
         &self.ray
     }
 
+    /// Where the ray meets the surface. Rays leaving from here have to be moved off the
+    /// surface first, see `offset_ray_origin`.
     #[inline(always)]
     pub fn point(&self) -> Point {
-        let min_dist = self.distance - EPS;
-        let scaled_vector = self.ray.direction * min_dist;
-        self.ray.origin + scaled_vector
+        self.ray.origin + self.ray.direction * self.distance
+    }
+
+    /// The hit point in the object's own space.
+    #[inline(always)]
+    fn object_point(&self) -> Point {
+        match self.instance {
+            Some(instance) => instance.transform().inverse_point(self.point()),
+            None => self.point(),
+        }
     }
 
+    /// Brings a normal of the object into world space.
+    #[inline(always)]
+    fn world_normal(&self, normal: Vector) -> Vector {
+        match self.instance {
+            Some(instance) => instance.transform().normal(normal).normalized(),
+            None => normal,
+        }
+    }
+
+    /// Shading normal, flipped if needed to be on the same side as the geometric normal.
     #[inline(always)]
     pub fn normal(&self) -> Vector {
-        self.object.surface_normal(self.point())
+        let point = self.object_point();
+        let normal = self.world_normal(self.object.surface_normal(point));
+        if normal.dot(self.geometric_normal()) < 0.0 {
+            -normal
+        } else {
+            normal
+        }
+    }
+
+    /// Normal of the actual surface. Its side is the front of the surface: for triangles it's
+    /// given by the winding of the vertices, for spheres it points out.
+    #[inline(always)]
+    pub fn geometric_normal(&self) -> Vector {
+        self.world_normal(self.object.geometric_normal(self.object_point()))
+    }
+
+    /// Radiance the surface emits back along the ray.
+    #[inline(always)]
+    pub fn emittance(&self, wavelengths: &Wavelengths) -> Spectrum {
+        let direction = -self.ray.direction;
+        match self.instance {
+            Some(instance) => self.object.emittance_with(
+                instance.material(self.object),
+                self.object_point(),
+                instance.transform().inverse_vector(direction),
+                wavelengths,
+            ),
+            None => self.object.emittance(self.point(), direction, wavelengths),
+        }
+    }
+
+    /// Whether the ray hit the back of the surface.
+    #[inline(always)]
+    pub fn is_back_face(&self) -> bool {
+        self.ray.direction.dot(self.geometric_normal()) > 0.0
+    }
+
+    /// Whether the ray goes into the object rather than out of it. Spheres tell by where the
+    /// ray starts, which holds up at grazing angles where the sign of the normal doesn't.
+    #[inline(always)]
+    pub fn is_entering(&self) -> bool {
+        match self.object {
+            Object::Sphere(sphere) => !sphere.contains(self.ray.origin),
+            _ => !self.is_back_face(),
+        }
+    }
+
+    /// The ray carrying on unchanged from the other side of the surface.
+    #[inline(always)]
+    pub fn continue_through(&self) -> Ray {
+        let direction = self.ray.direction;
+        let origin = offset_ray_origin(self.point(), self.geometric_normal(), direction);
+        Ray::new_prenormalized(origin, direction)
+            .with_cone(self.ray.cone_width_at(self.distance), self.ray.cone_spread)
+    }
+
+    /// Computes everything needed to shade the hit at the given wavelengths. Texture lookups
+    /// get a footprint from the ray cone.
+    pub fn surface_interaction(&self, wavelengths: &Wavelengths) -> SurfaceInteraction {
+        let point = self.point();
+        let object_point = self.object_point();
+        let mut footprint = self.ray.cone_width_at(self.distance);
+        if let Some(instance) = self.instance {
+            footprint *= instance.object_ray(&self.ray).1;
+        }
+        let mut ctx = self.object.texture_context(object_point, footprint);
+        ctx.point = point;
+        ctx.wavelengths = *wavelengths;
+        let geometric_normal = self.geometric_normal();
+        let mut frame = self
+            .object
+            .shading_frame(self.material(), object_point, &ctx);
+        if let Some(instance) = self.instance {
+            let transform = instance.transform();
+            let bitangent = transform.vector(frame.bitangent);
+            frame = Frame::from_normal_tangent(
+                transform.normal(frame.normal).normalized(),
+                transform.vector(frame.tangent),
+            );
+            if frame.bitangent.dot(bitangent) < 0.0 {
+                frame.bitangent = -frame.bitangent;
+            }
+        }
+        // vertex normals may disagree with the winding, the geometric normal decides the front
+        if frame.normal.dot(geometric_normal) < 0.0 {
+            frame = Frame::from_normal_tangent(-frame.normal, frame.tangent);
+        }
+
+        // A shading normal on the other side of the viewer than the actual surface would let
+        // light leak through it, so fall back to the geometric normal.
+        let wo = self.ray.direction;
+        if frame.normal.dot(wo) * geometric_normal.dot(wo) < 0.0 {
+            frame = Frame::from_normal_tangent(geometric_normal, frame.tangent);
+        }
+
+        SurfaceInteraction {
+            point,
+            geometric_normal,
+            frame,
+            ctx,
+            entering: self.is_entering(),
+        }
+    }
+}
+
+/// Shading information at a hit, shared by light sampling and bounce sampling.
+pub struct SurfaceInteraction {
+    pub point: Point,
+    pub geometric_normal: Vector,
+    /// Shading frame, possibly perturbed by normal or bump maps.
+    pub frame: Frame,
+    pub ctx: TextureContext,
+    /// Whether the incoming ray goes into the object, for refraction.
+    pub entering: bool,
+}
+
+impl SurfaceInteraction {
+    #[inline(always)]
+    pub fn normal(&self) -> Vector {
+        self.frame.normal
+    }
+
+    /// Origin for a ray leaving the surface along `direction`.
+    #[inline(always)]
+    pub fn spawn_point(&self, direction: Vector) -> Point {
+        offset_ray_origin(self.point, self.geometric_normal, direction)
+    }
+
+    /// Whether `wi` leaves the actual surface on the side the incoming ray `wo` arrived from.
+    /// Shading normals can disagree, and trusting them would leak light through surfaces.
+    #[inline(always)]
+    pub fn same_side(&self, wo: Vector, wi: Vector) -> bool {
+        wi.dot(self.geometric_normal) * wo.dot(self.geometric_normal) < 0.0
     }
 }
 
+/// Scale of the Cornell box for scenes lit in physical units: it's four meters wide.
+const CORNELL_BOX_METERS_PER_UNIT: f32 = 0.1;
+
 struct CornellBox {
-    triangles: Vec<Triangle>,
+    walls: Vec<Shape>,
     half_length: f32,
     box_z_offset: f32,
     red_diffuse_material: Material,
@@ -78,14 +330,42 @@ struct CornellBox {
     sphere_light: Sphere,
 }
 
+impl CornellBox {
+    /// Scales `mesh` to half the box's width, whatever units it was modelled in, and stands
+    /// it in the middle of the floor.
+    fn stand_on_floor(&self, mesh: &mut TriangleMesh) {
+        let (min, max) = mesh.bounds();
+        let size = max - min;
+        let extent = size.x().max(size.y()).max(size.z());
+        let scale = if extent > 0.0 {
+            self.half_length / extent
+        } else {
+            1.0
+        };
+        let center = min + size * 0.5;
+        mesh.transform(
+            scale,
+            Vector::new(
+                -center.x() * scale,
+                -self.half_length - min.y() * scale,
+                self.box_z_offset - center.z() * scale,
+            ),
+        );
+    }
+}
+
 impl Scene {
-    fn new(triangles: Vec<Triangle>, spheres: Vec<Sphere>) -> Scene {
+    fn new(meshes: Vec<TriangleMesh>, spheres: Vec<Sphere>, shapes: Vec<Shape>) -> Scene {
+        let meshes: Vec<Arc<TriangleMesh>> = meshes.into_iter().map(Arc::new).collect();
         let mut objects = Vec::new();
-        for triangle in triangles {
-            objects.push(Object::Triangle(triangle));
+        for mesh in &meshes {
+            objects.extend(Triangle::from_mesh(mesh).map(Object::Triangle));
         }
         for sphere in spheres {
-            objects.push(Object::Sphere(sphere));
+            objects.push(Object::Sphere(Box::new(sphere)));
+        }
+        for shape in shapes {
+            objects.push(Object::Shape(Box::new(shape)));
         }
 
         let mut light_indexes = Vec::new();
@@ -96,13 +376,53 @@ impl Scene {
             }
         }
 
-        let bvh = BVH::build(&mut objects);
+        let has_interiors = objects
+            .iter()
+            .any(|object| object.material().interior.is_some());
+
+        // building over nothing recurses forever, scenes of only instances have no objects
+        let bvh = if objects.is_empty() {
+            BVH { nodes: Vec::new() }
+        } else {
+            BVH::build(&mut objects)
+        };
 
         Scene {
             objects,
             bvh,
+            meshes,
+            instances: Vec::new(),
+            instance_bvh: None,
             light_indexes,
+            point_lights: Vec::new(),
+            fog: None,
+            has_interiors,
+            camera: Camera::default(),
+        }
+    }
+
+    fn with_point_lights(mut self, point_lights: Vec<PointLight>) -> Scene {
+        self.point_lights = point_lights;
+        self
+    }
+
+    fn with_fog(mut self, fog: Medium) -> Scene {
+        self.fog = Some(fog);
+        self
+    }
+
+    fn with_camera(mut self, camera: Camera) -> Scene {
+        self.camera = camera;
+        self
+    }
+
+    fn with_instances(mut self, mut instances: Vec<Instance>) -> Scene {
+        if !instances.is_empty() {
+            self.has_interiors |= instances.iter().any(|instance| instance.has_interior());
+            self.instance_bvh = Some(BVH::build(&mut instances));
         }
+        self.instances = instances;
+        self
     }
 
     pub fn new_triangle() -> Scene {
@@ -114,91 +434,238 @@ impl Scene {
             Material::new(BSDF::Diffuse, Spectrum::black(), Spectrum::white()),
         );
 
-        let (p1, p2, p3) = (
-            Point::new(-5.0, -5.0, -20.0),
-            Point::new(5.0, -5.0, -20.0),
-            Point::new(5.0, 5.0, -20.0),
-        );
-        let triangle = Triangle::new(
-            p1,
-            p2,
-            p3,
+        let triangle = TriangleMesh::new(
+            vec![
+                Point::new(-5.0, -5.0, -20.0),
+                Point::new(5.0, -5.0, -20.0),
+                Point::new(5.0, 5.0, -20.0),
+            ],
+            vec![[0, 1, 2]],
+            material,
+        )
+        .with_normals(vec![
             Vector::new_normalized(-0.4, 0.0, 1.0),
             Vector::new_normalized(0.4, 0.0, 1.0),
             Vector::new_normalized(0.0, 0.0, 1.0),
-            material,
-        );
+        ]);
 
-        Scene::new(vec![triangle], vec![light])
+        Scene::new(vec![triangle], vec![light], Vec::new())
     }
 
-    fn load_obj(filename: &str, scale: f32, offset: Point, material: Material) -> Vec<Triangle> {
-        let (models, _) = tobj::load_obj(filename, true).unwrap();
-        let m = &models[0];
-        let mesh = &m.mesh;
+    /// Loads every object and group of an OBJ file as its own `Model`, in file order. Faces
+    /// use the material the MTL file assigns them, or `material` when they have none. Models
+    /// are refined by `subdivision` if given, then their normals are fixed up as `normals`
+    /// asks.
+    fn load_obj_models(
+        filename: &str,
+        scale: f32,
+        offset: Point,
+        material: Material,
+        normals: &NormalOptions,
+        subdivision: Option<&Subdivision>,
+    ) -> Vec<Model> {
+        // faces are kept whole for subdivision, and split into triangles here otherwise
+        let (meshes, mtl_materials) = tobj::load_obj(filename, false).unwrap();
+        let mut converter = MtlConverter::new(filename);
+        let materials: Vec<Material> = mtl_materials
+            .iter()
+            .map(|mtl| converter.convert(mtl))
+            .collect();
 
-        let points: Vec<Point> = (0..mesh.positions.len() / 3)
-            .map(|v| {
-                let v = Vector::new(
-                    mesh.positions[3 * v],
-                    mesh.positions[3 * v + 1],
-                    mesh.positions[3 * v + 2],
-                );
-                offset + (v * scale)
+        // tobj splits an object into one mesh per `usemtl`, merge them back by name
+        let mut groups: Vec<(&str, Vec<&tobj::Mesh>)> = Vec::new();
+        for m in &meshes {
+            match groups.iter_mut().find(|(name, _)| *name == m.name) {
+                Some((_, group)) => group.push(&m.mesh),
+                None => groups.push((&m.name, vec![&m.mesh])),
+            }
+        }
+        let material_of =
+            |mesh: &tobj::Mesh| mesh.material_id.map_or(&material, |id| &materials[id]);
+        let models: Vec<Model> = groups
+            .iter()
+            .map(|(name, group)| {
+                let mut mesh = match subdivision {
+                    Some(subdivision) => {
+                        let mut polygons =
+                            Scene::load_polygons(group[0], scale, offset, material_of(group[0]));
+                        for m in &group[1..] {
+                            polygons.append(Scene::load_polygons(m, scale, offset, material_of(m)));
+                        }
+                        subdivision.apply(polygons)
+                    }
+                    None => {
+                        let mut mesh = TriangleMesh::empty();
+                        for m in group {
+                            mesh.append(Scene::load_mesh(m, scale, offset, material_of(m)));
+                        }
+                        mesh
+                    }
+                };
+                mesh.fix_normals(normals);
+                Model::new(name.to_string(), mesh)
             })
             .collect();
-        let vn: Option<Vec<Vector>> = if !mesh.normals.is_empty() {
-            Some(
-                (0..mesh.positions.len() / 3)
-                    .map(|i| {
-                        Vector::new(
-                            mesh.normals[3 * i],
-                            mesh.normals[3 * i + 1],
-                            mesh.normals[3 * i + 2],
-                        )
-                    })
-                    .collect(),
-            )
-        } else {
-            None
-        };
+        println!(
+            "Loaded {} model(s) from {}: {}",
+            models.len(),
+            filename,
+            models
+                .iter()
+                .map(|model| model.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        models
+    }
 
-        let mut triangles = Vec::new();
-        let mut next_face = 0;
-        for f in 0..mesh.num_face_indices.len() {
-            let end = next_face + mesh.num_face_indices[f] as usize;
-            let face_indices: Vec<_> = mesh.indices[next_face..end].iter().collect();
+    /// Loads all models of an OBJ file into a single mesh.
+    fn load_obj(
+        filename: &str,
+        scale: f32,
+        offset: Point,
+        material: Material,
+        normals: &NormalOptions,
+    ) -> TriangleMesh {
+        Model::into_mesh(Scene::load_obj_models(
+            filename, scale, offset, material, normals, None,
+        ))
+    }
 
-            let (i1, i2, i3) = (
-                *face_indices[0] as usize,
-                *face_indices[1] as usize,
-                *face_indices[2] as usize,
-            );
-            let (p1, p2, p3) = (points[i1], points[i2], points[i3]);
+    /// Loads a PLY file, ASCII or binary, as scanned models usually come. Vertex colours are
+    /// kept on the mesh for materials using `Texture::VertexColor`.
+    pub fn load_ply(
+        filename: &str,
+        scale: f32,
+        offset: Point,
+        material: Material,
+        normals: &NormalOptions,
+    ) -> Result<TriangleMesh, LoadError> {
+        let mut mesh = ply::open(filename, material)?;
+        mesh.transform(scale, offset - Point::origin());
+        mesh.fix_normals(normals);
+        println!(
+            "Loaded {} triangles from {}",
+            mesh.triangle_count(),
+            filename
+        );
+        Ok(mesh)
+    }
 
-            triangles.push(if let Some(ref vn) = vn {
-                let (vn1, vn2, vn3) = (vn[i1], vn[i2], vn[i3]);
-                Triangle::new(p1, p2, p3, vn1, vn2, vn3, material)
-            } else {
-                Triangle::new_without_vn(p1, p2, p3, material)
-            });
+    /// Loads an ASCII or binary STL file, as CAD tools export parts. STL has no normals, so
+    /// the part is flat shaded unless `normals` gives a crease angle to smooth it with.
+    pub fn load_stl(
+        filename: &str,
+        scale: f32,
+        offset: Point,
+        material: Material,
+        normals: &NormalOptions,
+    ) -> Result<TriangleMesh, LoadError> {
+        let mut mesh = stl::open(filename, material)?;
+        mesh.transform(scale, offset - Point::origin());
+        mesh.fix_normals(normals);
+        println!(
+            "Loaded {} triangles from {}",
+            mesh.triangle_count(),
+            filename
+        );
+        Ok(mesh)
+    }
+
+    /// Loads a `.gltf` or `.glb` file as a scene of its own, with its lights and camera.
+    pub fn load_gltf(filename: &str) -> Result<Scene, LoadError> {
+        let gltf = gltf::open(filename)?;
+        let scene = Scene::new(gltf.meshes, Vec::new(), Vec::new())
+            .with_instances(gltf.instances)
+            .with_point_lights(gltf.point_lights)
+            .with_camera(gltf.camera.unwrap_or_default());
+        println!(
+            "Loaded {} triangles, {} instances and {} lights from {}",
+            scene.triangle_count(),
+            scene.instances.len(),
+            scene.point_lights.len(),
+            filename
+        );
+        Ok(scene)
+    }
 
-            next_face = end
+    fn load_mesh(
+        mesh: &tobj::Mesh,
+        scale: f32,
+        offset: Point,
+        material: &Material,
+    ) -> TriangleMesh {
+        let points: Vec<Point> = mesh
+            .positions
+            .chunks(3)
+            .map(|p| offset + Vector::new(p[0], p[1], p[2]) * scale)
+            .collect();
+        let faces: Vec<[u32; 3]> = Scene::obj_polygons(mesh)
+            .iter()
+            .flat_map(|polygon| {
+                (2..polygon.len()).map(move |i| [polygon[0], polygon[i - 1], polygon[i]])
+            })
+            .collect();
+
+        let mut triangles = TriangleMesh::new(points, faces, material.clone());
+        if !mesh.normals.is_empty() {
+            let normals = mesh
+                .normals
+                .chunks(3)
+                .map(|n| Vector::new(n[0], n[1], n[2]))
+                .collect();
+            triangles = triangles.with_normals(normals);
+        }
+        if !mesh.texcoords.is_empty() {
+            let uvs = mesh.texcoords.chunks(2).map(|uv| (uv[0], uv[1])).collect();
+            triangles = triangles.with_uvs(uvs);
         }
         triangles
     }
 
+    /// The corners of each face of an OBJ mesh.
+    fn obj_polygons(mesh: &tobj::Mesh) -> Vec<Vec<u32>> {
+        if mesh.num_face_indices.is_empty() {
+            return mesh.indices.chunks(3).map(|face| face.to_vec()).collect();
+        }
+        let mut start = 0;
+        mesh.num_face_indices
+            .iter()
+            .map(|&count| {
+                let face = mesh.indices[start..start + count as usize].to_vec();
+                start += count as usize;
+                face
+            })
+            .collect()
+    }
+
+    /// Like `load_mesh`, keeping the faces whole and leaving out the normals.
+    fn load_polygons(
+        mesh: &tobj::Mesh,
+        scale: f32,
+        offset: Point,
+        material: &Material,
+    ) -> PolygonMesh {
+        let points: Vec<Point> = mesh
+            .positions
+            .chunks(3)
+            .map(|p| offset + Vector::new(p[0], p[1], p[2]) * scale)
+            .collect();
+        let polygons = PolygonMesh::new(points, Scene::obj_polygons(mesh), material.clone());
+        if mesh.texcoords.is_empty() {
+            polygons
+        } else {
+            polygons.with_uvs(mesh.texcoords.chunks(2).map(|uv| (uv[0], uv[1])).collect())
+        }
+    }
+
     pub fn new_dragon() -> Scene {
         let cb = Scene::cornell_box();
-        let (half_length, box_z_offset, red_diffuse_material, mut triangles) = (
-            cb.half_length,
-            cb.box_z_offset,
-            cb.red_diffuse_material,
-            cb.triangles,
-        );
+        let (half_length, box_z_offset, red_diffuse_material) =
+            (cb.half_length, cb.box_z_offset, cb.red_diffuse_material);
         let material = Material::new(BSDF::Diffuse, Spectrum::grey(), Spectrum::black());
         let dragon_scale = 2.0;
-        triangles.extend(Scene::load_obj(
+        let dragon = Scene::load_obj(
             "obj/dragon.obj",
             dragon_scale,
             Point::new(
@@ -207,7 +674,8 @@ impl Scene {
                 box_z_offset - 2.0 * half_length / 3.0,
             ),
             material,
-        ));
+            &NormalOptions::default(),
+        );
 
         let sphere_radius = 6.0;
         let spheres = vec![
@@ -223,20 +691,16 @@ impl Scene {
             ),
         ];
 
-        Scene::new(triangles, spheres)
+        Scene::new(vec![dragon], spheres, cb.walls)
     }
 
     pub fn new_teapot() -> Scene {
         let cb = Scene::cornell_box();
-        let (half_length, box_z_offset, red_diffuse_material, mut triangles) = (
-            cb.half_length,
-            cb.box_z_offset,
-            cb.red_diffuse_material,
-            cb.triangles,
-        );
+        let (half_length, box_z_offset, red_diffuse_material) =
+            (cb.half_length, cb.box_z_offset, cb.red_diffuse_material);
         let material = Material::new(BSDF::Diffuse, Spectrum::grey(), Spectrum::black());
         let teapot_scale = 0.13;
-        triangles.extend(Scene::load_obj(
+        let teapot = Scene::load_obj(
             "obj/teapot.obj",
             teapot_scale,
             Point::new(
@@ -245,7 +709,8 @@ impl Scene {
                 box_z_offset - 2.5 * half_length / 3.0,
             ),
             material,
-        ));
+            &NormalOptions::default(),
+        );
         let sphere_radius = 6.0;
         let spheres = vec![
             cb.sphere_light,
@@ -260,7 +725,84 @@ impl Scene {
             ),
         ];
 
-        Scene::new(triangles, spheres)
+        Scene::new(vec![teapot], spheres, cb.walls)
+    }
+
+    /// A CAD part in the Cornell box, as grey plastic. Whatever units it was modelled in, it's
+    /// scaled to half the box's width and stood on the floor, and smoothed except along edges
+    /// sharper than 30 degrees. Exporters don't always agree on the winding, so it's fixed
+    /// first. `normals` replaces both if given.
+    pub fn new_stl_part(
+        filename: &str,
+        normals: Option<&NormalOptions>,
+    ) -> Result<Scene, LoadError> {
+        let cb = Scene::cornell_box();
+        let material = Material::new(
+            BSDF::Glossy { exponent: 200.0 },
+            Spectrum::grey(),
+            Spectrum::black(),
+        )
+        .with_specular(Spectrum::new_f(0.04, 0.04, 0.04).into());
+        let mut part = Scene::load_stl(
+            filename,
+            1.0,
+            Point::origin(),
+            material,
+            &normals.copied().unwrap_or_else(|| {
+                NormalOptions::flat()
+                    .with_crease_angle(f32::to_radians(30.0))
+                    .with_oriented_faces()
+            }),
+        )?;
+        cb.stand_on_floor(&mut part);
+        Ok(Scene::new(vec![part], vec![cb.sphere_light], cb.walls))
+    }
+
+    /// A PLY model in the Cornell box, fitted and stood on the floor like `new_stl_part`.
+    /// Scans usually come with vertex colours, and then it's painted with them instead of grey.
+    /// Normals are fixed up as `normals` asks, or as `NormalOptions::default` does.
+    pub fn new_ply_model(
+        filename: &str,
+        normals: Option<&NormalOptions>,
+    ) -> Result<Scene, LoadError> {
+        let cb = Scene::cornell_box();
+        let mut model = Scene::load_ply(
+            filename,
+            1.0,
+            Point::origin(),
+            cb.grey_diffuse_material.clone(),
+            &normals.copied().unwrap_or_default(),
+        )?;
+        if !model.colors.is_empty() {
+            model.set_material(
+                cb.grey_diffuse_material
+                    .clone()
+                    .with_reflectance(Texture::VertexColor),
+            );
+        }
+        cb.stand_on_floor(&mut model);
+        Ok(Scene::new(vec![model], vec![cb.sphere_light], cb.walls))
+    }
+
+    /// An OBJ model in the Cornell box, fitted and stood on the floor like `new_stl_part`.
+    /// Faces are refined by `subdivision` if given. Normals are fixed up as `normals` asks, by
+    /// default smoothing anything without them except along sharp edges.
+    pub fn new_obj_model(
+        filename: &str,
+        normals: Option<&NormalOptions>,
+        subdivision: Option<&Subdivision>,
+    ) -> Scene {
+        let cb = Scene::cornell_box();
+        let mut model = Model::into_mesh(Scene::load_obj_models(
+            filename,
+            1.0,
+            Point::origin(),
+            cb.grey_diffuse_material.clone(),
+            &normals.copied().unwrap_or_default(),
+            subdivision,
+        ));
+        cb.stand_on_floor(&mut model);
+        Scene::new(vec![model], vec![cb.sphere_light], cb.walls)
     }
 
     fn cornell_box() -> CornellBox {
@@ -286,40 +828,56 @@ impl Scene {
             white_light_material,
         );
 
-        let z = box_z_offset - half_length;
-        let p0 = Point::new(-half_length, -half_length, 1.0);
-        let p1 = Point::new(-half_length, -half_length, z);
-        let p2 = Point::new(half_length, -half_length, z);
-        let p3 = Point::new(half_length, -half_length, 1.0);
-        let p4 = Point::new(-half_length, half_length, z);
-        let p5 = Point::new(half_length, half_length, z);
-        let p6 = Point::new(-half_length, half_length, 1.0);
-        let p7 = Point::new(half_length, half_length, 1.0);
-        let p8 = Point::new(-half_length, half_length, 1.0);
-        let p9 = Point::new(-half_length, half_length, z);
-        let p10 = Point::new(half_length, half_length, z);
-        let p11 = Point::new(half_length, half_length, 1.0);
-
-        let triangles = vec![
+        // the box is open towards the camera, at z = 1
+        let back = box_z_offset - half_length;
+        let half_depth = (1.0 - back) / 2.0;
+        let middle = back + half_depth;
+        let (across, up, deep) = (
+            Vector::new(half_length, 0.0, 0.0),
+            Vector::new(0.0, half_length, 0.0),
+            Vector::new(0.0, 0.0, half_depth),
+        );
+        // every wall faces into the box
+        let walls = vec![
             // bottom wall
-            Triangle::new_without_vn(p1, p0, p2, grey_diffuse_material),
-            Triangle::new_without_vn(p3, p2, p0, grey_diffuse_material),
+            Shape::quad(
+                Point::new(0.0, -half_length, middle),
+                across,
+                -deep,
+                grey_diffuse_material.clone(),
+            ),
             // top wall
-            Triangle::new_without_vn(p4, p5, p6, grey_diffuse_material),
-            Triangle::new_without_vn(p7, p6, p5, grey_diffuse_material),
+            Shape::quad(
+                Point::new(0.0, half_length, middle),
+                across,
+                deep,
+                grey_diffuse_material.clone(),
+            ),
             // back wall
-            Triangle::new_without_vn(p4, p1, p2, green_diffuse_material),
-            Triangle::new_without_vn(p2, p5, p4, green_diffuse_material),
+            Shape::quad(
+                Point::new(0.0, 0.0, back),
+                across,
+                up,
+                green_diffuse_material.clone(),
+            ),
             // left wall
-            Triangle::new_without_vn(p8, p0, p9, red_diffuse_material),
-            Triangle::new_without_vn(p1, p9, p0, red_diffuse_material),
+            Shape::quad(
+                Point::new(-half_length, 0.0, middle),
+                up,
+                deep,
+                red_diffuse_material.clone(),
+            ),
             // right wall
-            Triangle::new_without_vn(p3, p11, p2, blue_diffuse_material),
-            Triangle::new_without_vn(p10, p2, p11, blue_diffuse_material),
+            Shape::quad(
+                Point::new(half_length, 0.0, middle),
+                deep,
+                up,
+                blue_diffuse_material.clone(),
+            ),
         ];
 
         CornellBox {
-            triangles,
+            walls,
             sphere_light,
             half_length,
             box_z_offset,
@@ -332,12 +890,8 @@ impl Scene {
 
     pub fn new_specular() -> Scene {
         let cb = Scene::cornell_box();
-        let (half_length, box_z_offset, red_diffuse_material, triangles) = (
-            cb.half_length,
-            cb.box_z_offset,
-            cb.red_diffuse_material,
-            cb.triangles,
-        );
+        let (half_length, box_z_offset, red_diffuse_material) =
+            (cb.half_length, cb.box_z_offset, cb.red_diffuse_material);
         let mirror_material = Material::new(BSDF::Specular, Spectrum::white(), Spectrum::black());
         let sphere_radius = 6.0;
         let spheres = vec![
@@ -362,17 +916,16 @@ impl Scene {
             ),
         ];
 
-        Scene::new(triangles, spheres)
+        Scene::new(Vec::new(), spheres, cb.walls)
     }
 
     pub fn new_diffuse() -> Scene {
         let cb = Scene::cornell_box();
-        let (half_length, box_z_offset, grey_diffuse_material, red_diffuse_material, triangles) = (
+        let (half_length, box_z_offset, grey_diffuse_material, red_diffuse_material) = (
             cb.half_length,
             cb.box_z_offset,
             cb.grey_diffuse_material,
             cb.red_diffuse_material,
-            cb.triangles,
         );
         let sphere_radius = 6.0;
         let spheres = vec![
@@ -397,97 +950,781 @@ impl Scene {
             ),
         ];
 
-        Scene::new(triangles, spheres)
+        Scene::new(Vec::new(), spheres, cb.walls)
+    }
+
+    pub fn new_rough() -> Scene {
+        let cb = Scene::cornell_box();
+        let (half_length, box_z_offset) = (cb.half_length, cb.box_z_offset);
+        let clay_material = Material::new(
+            BSDF::OrenNayar { sigma: 0.5 },
+            Spectrum::new(190, 120, 90),
+            Spectrum::black(),
+        );
+        let fabric_material = Material::new(
+            BSDF::RetroDiffuse { roughness: 1.0 },
+            Spectrum::new(70, 90, 160),
+            Spectrum::black(),
+        );
+        let sphere_radius = 6.0;
+        let spheres = vec![
+            cb.sphere_light,
+            Sphere::new(
+                Point::new(
+                    -half_length / 3.0,
+                    -half_length + sphere_radius,
+                    box_z_offset - 2.0 * half_length / 3.0,
+                ),
+                sphere_radius,
+                clay_material,
+            ),
+            Sphere::new(
+                Point::new(
+                    half_length / 3.0,
+                    -half_length + sphere_radius,
+                    box_z_offset - half_length / 3.0,
+                ),
+                sphere_radius,
+                fabric_material,
+            ),
+        ];
+
+        Scene::new(Vec::new(), spheres, cb.walls)
+    }
+
+    pub fn new_procedural() -> Scene {
+        let cb = Scene::cornell_box();
+        let (half_length, box_z_offset) = (cb.half_length, cb.box_z_offset);
+        let marble = ProceduralTexture::new(
+            Pattern::Marble {
+                stripes: 1.5,
+                distortion: 6.0,
+            },
+            TextureSpace::Object,
+            0.5,
+            ColorRamp::new(vec![
+                (0.0, Spectrum::new(60, 60, 70)),
+                (0.4, Spectrum::new(200, 200, 205)),
+                (1.0, Spectrum::white()),
+            ]),
+        );
+        let wood = ProceduralTexture::new(
+            Pattern::Wood {
+                rings: 1.2,
+                distortion: 0.8,
+            },
+            TextureSpace::Object,
+            1.0,
+            ColorRamp::two_colors(Spectrum::new(150, 95, 50), Spectrum::new(90, 50, 25)),
+        );
+        let marble_material = Material::new(BSDF::Diffuse, Spectrum::white(), Spectrum::black())
+            .with_reflectance(Texture::Procedural(Arc::new(marble)));
+        let wood_material = Material::new(BSDF::Diffuse, Spectrum::white(), Spectrum::black())
+            .with_reflectance(Texture::Procedural(Arc::new(wood)));
+        let sphere_radius = 6.0;
+        let spheres = vec![
+            cb.sphere_light,
+            Sphere::new(
+                Point::new(
+                    -half_length / 3.0,
+                    -half_length + sphere_radius,
+                    box_z_offset - 2.0 * half_length / 3.0,
+                ),
+                sphere_radius,
+                marble_material,
+            ),
+            Sphere::new(
+                Point::new(
+                    half_length / 3.0,
+                    -half_length + sphere_radius,
+                    box_z_offset - half_length / 3.0,
+                ),
+                sphere_radius,
+                wood_material,
+            ),
+        ];
+
+        Scene::new(Vec::new(), spheres, cb.walls)
+    }
+
+    /// Gold, copper and aluminium spheres, best rendered in spectral mode.
+    pub fn new_metals() -> Scene {
+        let cb = Scene::cornell_box();
+        let (half_length, box_z_offset) = (cb.half_length, cb.box_z_offset);
+        let metal = |metal| {
+            Material::new(
+                BSDF::Conductor { metal },
+                Spectrum::white(),
+                Spectrum::black(),
+            )
+        };
+        let sphere_radius = 5.0;
+        let spheres = vec![
+            cb.sphere_light,
+            Sphere::new(
+                Point::new(
+                    -half_length / 2.0,
+                    -half_length + sphere_radius,
+                    box_z_offset - 2.0 * half_length / 3.0,
+                ),
+                sphere_radius,
+                metal(Metal::Gold),
+            ),
+            Sphere::new(
+                Point::new(
+                    0.0,
+                    -half_length + sphere_radius,
+                    box_z_offset - half_length / 3.0,
+                ),
+                sphere_radius,
+                metal(Metal::Copper),
+            ),
+            Sphere::new(
+                Point::new(
+                    half_length / 2.0,
+                    -half_length + sphere_radius,
+                    box_z_offset - 2.0 * half_length / 3.0,
+                ),
+                sphere_radius,
+                metal(Metal::Aluminium),
+            ),
+        ];
+
+        Scene::new(Vec::new(), spheres, cb.walls)
+    }
+
+    /// A flint glass prism and a diamond sphere. Dispersion only shows in spectral mode.
+    pub fn new_dispersion() -> Scene {
+        let cb = Scene::cornell_box();
+        let (half_length, box_z_offset) = (cb.half_length, cb.box_z_offset);
+        let flint = Material::new(
+            BSDF::Dielectric { ior: Ior::flint() },
+            Spectrum::white(),
+            Spectrum::black(),
+        );
+        let diamond = Material::new(
+            BSDF::Dielectric {
+                ior: Ior::diamond(),
+            },
+            Spectrum::white(),
+            Spectrum::black(),
+        );
+
+        // triangular prism lying along z
+        let (x, y, z) = (
+            -half_length / 3.0,
+            -half_length,
+            box_z_offset - 2.0 * half_length / 3.0,
+        );
+        let (half_width, height, half_depth) = (6.0, 10.0, 8.0);
+        let profile = [
+            Vector::new(-half_width, 0.0, 0.0),
+            Vector::new(half_width, 0.0, 0.0),
+            Vector::new(0.0, height, 0.0),
+        ];
+        let corner = |i: usize, front: bool| {
+            let depth = if front { half_depth } else { -half_depth };
+            Point::new(x, y, z) + profile[i] + Vector::new(0.0, 0.0, depth)
+        };
+        let center = Point::new(x, y + height / 3.0, z);
+        let mut faces = vec![
+            [corner(0, true), corner(1, true), corner(2, true)],
+            [corner(0, false), corner(1, false), corner(2, false)],
+        ];
+        for i in 0..3 {
+            let j = (i + 1) % 3;
+            faces.push([corner(i, true), corner(j, true), corner(j, false)]);
+            faces.push([corner(i, true), corner(j, false), corner(i, false)]);
+        }
+        // flat faces, so corners aren't shared
+        let mut positions = Vec::new();
+        for [p1, p2, p3] in faces {
+            // wind every face so its normal points out of the prism
+            let outward = (p2 - p1).cross(p3 - p1).dot(p1 - center) > 0.0;
+            let (p2, p3) = if outward { (p2, p3) } else { (p3, p2) };
+            positions.extend_from_slice(&[p1, p2, p3]);
+        }
+        let indices = (0..positions.len() as u32 / 3)
+            .map(|i| [3 * i, 3 * i + 1, 3 * i + 2])
+            .collect();
+        let prism = TriangleMesh::new(positions, indices, flint);
+
+        let sphere_radius = 6.0;
+        let spheres = vec![
+            cb.sphere_light,
+            Sphere::new(
+                Point::new(
+                    half_length / 3.0,
+                    -half_length + sphere_radius,
+                    box_z_offset - half_length / 3.0,
+                ),
+                sphere_radius,
+                diamond,
+            ),
+        ];
+
+        Scene::new(vec![prism], spheres, cb.walls)
+    }
+
+    /// The Cornell box lit by a warm incandescent globe and a cool LED globe, specified in
+    /// real units. Scene units are taken to be decimeters; render with an exposure of about 0.2.
+    pub fn new_lamps() -> Scene {
+        let cb = Scene::cornell_box();
+        let (half_length, box_z_offset) = (cb.half_length, cb.box_z_offset);
+        let lamp_radius = 1.5;
+        let lamp = |x: f32, color, power| {
+            let mut sphere = Sphere::new(
+                Point::new(x, half_length / 2.0, box_z_offset - half_length / 2.0),
+                lamp_radius,
+                Material::new(BSDF::Diffuse, Spectrum::black(), Spectrum::black()),
+            );
+            let area = sphere.area() * CORNELL_BOX_METERS_PER_UNIT * CORNELL_BOX_METERS_PER_UNIT;
+            sphere.set_material(Material::light(color, power, area));
+            sphere
+        };
+        let sphere_radius = 6.0;
+        let spheres = vec![
+            lamp(
+                -half_length / 2.0,
+                LightColor::Kelvin(2700.0),
+                LightPower::Watts(25.0),
+            ),
+            lamp(
+                half_length / 2.0,
+                LightColor::Kelvin(6500.0),
+                LightPower::Lumens(250.0),
+            ),
+            Sphere::new(
+                Point::new(
+                    0.0,
+                    -half_length + sphere_radius,
+                    box_z_offset - half_length / 2.0,
+                ),
+                sphere_radius,
+                cb.grey_diffuse_material,
+            ),
+        ];
+
+        Scene::new(Vec::new(), spheres, cb.walls)
+    }
+
+    /// The Cornell box lit by a measured downlight, a spotlight and a bare bulb, in the same
+    /// units as `new_lamps`. Render with an exposure of about 0.05.
+    pub fn new_luminaires() -> Scene {
+        let cb = Scene::cornell_box();
+        let (half_length, box_z_offset) = (cb.half_length, cb.box_z_offset);
+        // candela are lumens per steradian at distances in meters
+        let per_square_unit = 1.0 / (CORNELL_BOX_METERS_PER_UNIT * CORNELL_BOX_METERS_PER_UNIT);
+        let ceiling = half_length - 0.5;
+        let down = Vector::new(0.0, -1.0, 0.0);
+
+        let mut point_lights = vec![PointLight::spot(
+            Point::new(half_length / 2.0, ceiling, box_z_offset - half_length / 2.0),
+            Vector::new(0.3, -1.0, -0.2),
+            Spectrum::new_f(1.0, 0.9, 0.7) * (300.0 * per_square_unit),
+            f32::to_radians(15.0),
+            f32::to_radians(25.0),
+        )];
+        match IesProfile::open("ies/downlight.ies") {
+            Ok(profile) => point_lights.push(PointLight::with_profile(
+                Point::new(
+                    -half_length / 2.0,
+                    ceiling,
+                    box_z_offset - half_length / 2.0,
+                ),
+                down,
+                Spectrum::white() * per_square_unit,
+                Arc::new(profile),
+            )),
+            Err(e) => eprintln!("Couldn't load ies/downlight.ies: {}", e),
+        }
+        point_lights.push(PointLight::new(
+            Point::new(0.0, ceiling - 2.0, box_z_offset - half_length / 4.0),
+            Spectrum::white() * (10.0 * per_square_unit),
+        ));
+
+        let sphere_radius = 6.0;
+        let spheres = vec![Sphere::new(
+            Point::new(
+                0.0,
+                -half_length + sphere_radius,
+                box_z_offset - half_length / 2.0,
+            ),
+            sphere_radius,
+            cb.grey_diffuse_material,
+        )];
+
+        Scene::new(Vec::new(), spheres, cb.walls).with_point_lights(point_lights)
+    }
+
+    /// The Cornell box filled with a light haze, holding a block of smoke and a tank of murky
+    /// water.
+    pub fn new_fog() -> Scene {
+        let cb = Scene::cornell_box();
+        let (half_length, box_z_offset) = (cb.half_length, cb.box_z_offset);
+        let mut shapes = cb.walls;
+        let floor = -half_length;
+        let z = box_z_offset - half_length / 2.0;
+
+        let smoke = Material::new(BSDF::Null, Spectrum::black(), Spectrum::black()).with_interior(
+            HomogeneousMedium::new(
+                Spectrum::new_f(0.02, 0.02, 0.02),
+                Spectrum::new_f(0.15, 0.15, 0.15),
+                0.3,
+            )
+            .into(),
+        );
+        shapes.push(Shape::axis_aligned_box(
+            Point::new(-15.0, floor, z - 6.0),
+            Point::new(-3.0, floor + 24.0, z + 6.0),
+            smoke,
+        ));
+
+        // water absorbs red first, and the silt in it scatters forward
+        let water = Material::new(
+            BSDF::Dielectric {
+                ior: Ior::Constant(1.33),
+            },
+            Spectrum::white(),
+            Spectrum::black(),
+        )
+        .with_interior(
+            HomogeneousMedium::new(
+                Spectrum::new_f(0.12, 0.04, 0.03),
+                Spectrum::new_f(0.05, 0.06, 0.06),
+                0.8,
+            )
+            .into(),
+        );
+        shapes.push(Shape::axis_aligned_box(
+            Point::new(3.0, floor, z - 5.0),
+            Point::new(15.0, floor + 14.0, z + 5.0),
+            water,
+        ));
+
+        Scene::new(Vec::new(), vec![cb.sphere_light], shapes).with_fog(
+            HomogeneousMedium::new(
+                Spectrum::new_f(0.001, 0.001, 0.001),
+                Spectrum::new_f(0.008, 0.008, 0.008),
+                0.5,
+            )
+            .into(),
+        )
+    }
+
+    /// A plume of smoke from a voxel grid, standing on the floor of the Cornell box.
+    pub fn new_smoke() -> Scene {
+        let cb = Scene::cornell_box();
+        let (half_length, box_z_offset) = (cb.half_length, cb.box_z_offset);
+        let mut shapes = cb.walls;
+        match VoxelGrid::open("volumes/smoke.vol") {
+            Ok(grid) => {
+                let [nx, ny, nz] = grid.resolution();
+                println!("Loaded a {}x{}x{} voxel grid", nx, ny, nz);
+                // stretch the grid's own bounds over most of the box's height
+                let (min, max) = grid.bounds();
+                let scale = 1.6 * half_length / (max.y() - min.y());
+                let size = (max - min) * scale;
+                let min = Point::new(
+                    -size.x() / 2.0,
+                    -half_length,
+                    box_z_offset - half_length / 2.0 - size.z() / 2.0,
+                );
+                let max = min + size;
+                let smoke = GridMedium::new(
+                    Arc::new(grid),
+                    min,
+                    max,
+                    0.6,
+                    Spectrum::new_f(0.9, 0.9, 0.9),
+                    0.2,
+                );
+                let bounds = Material::new(BSDF::Null, Spectrum::black(), Spectrum::black())
+                    .with_interior(smoke.into());
+                shapes.push(Shape::axis_aligned_box(min, max, bounds));
+            }
+            Err(e) => eprintln!("Couldn't load volumes/smoke.vol: {}", e),
+        }
+
+        Scene::new(Vec::new(), vec![cb.sphere_light], shapes)
+    }
+
+    /// A skin, a wax and a marble ball in the Cornell box. They're triangle meshes, as the
+    /// random walks need closed surfaces they can reliably find their way out of.
+    pub fn new_subsurface() -> Scene {
+        let cb = Scene::cornell_box();
+        let (half_length, box_z_offset) = (cb.half_length, cb.box_z_offset);
+        let mut balls = Vec::new();
+        let radius = 5.0;
+        let materials = [
+            Material::subsurface(
+                Spectrum::new_f(0.85, 0.55, 0.45),
+                Spectrum::new_f(0.9, 0.35, 0.2),
+                1.4,
+            ),
+            Material::subsurface(
+                Spectrum::new_f(0.95, 0.85, 0.6),
+                Spectrum::new_f(1.5, 1.2, 0.8),
+                1.45,
+            ),
+            Material::subsurface(
+                Spectrum::new_f(0.93, 0.93, 0.9),
+                Spectrum::new_f(0.6, 0.6, 0.6),
+                1.5,
+            ),
+        ];
+        for (i, material) in materials.iter().enumerate() {
+            let x = (i as f32 - 1.0) * half_length * 0.6;
+            balls.push(Scene::icosphere(
+                Point::new(x, -half_length + radius, box_z_offset - half_length / 2.0),
+                radius,
+                3,
+                material,
+            ));
+        }
+
+        Scene::new(balls, vec![cb.sphere_light], cb.walls)
+    }
+
+    /// Analytic shapes in the Cornell box, lit by a rectangular panel that only shines down.
+    pub fn new_shapes() -> Scene {
+        let cb = Scene::cornell_box();
+        let (half_length, box_z_offset) = (cb.half_length, cb.box_z_offset);
+        let (floor, z) = (-half_length, box_z_offset - half_length / 2.0);
+        let up = Vector::new(0.0, 1.0, 0.0);
+        let mut shapes = cb.walls;
+
+        let panel_light = Material::new(BSDF::Diffuse, Spectrum::black(), Spectrum::white() * 3.0)
+            .with_one_sided_emission();
+        shapes.push(Shape::quad(
+            Point::new(0.0, half_length - 0.01, z),
+            Vector::new(6.0, 0.0, 0.0),
+            Vector::new(0.0, 0.0, 6.0),
+            panel_light,
+        ));
+
+        // a closed can and a cone
+        let can_base = Point::new(-11.0, floor, z - 4.0);
+        shapes.push(Shape::cylinder(
+            can_base,
+            up * 12.0,
+            4.0,
+            cb.grey_diffuse_material.clone(),
+        ));
+        shapes.push(Shape::disk(
+            can_base + up * 12.0,
+            up,
+            4.0,
+            cb.grey_diffuse_material.clone(),
+        ));
+        shapes.push(Shape::cone(
+            Point::new(11.0, floor, z - 6.0),
+            up * 14.0,
+            5.0,
+            cb.grey_diffuse_material.clone(),
+        ));
+
+        // a box turned a little about the vertical
+        let angle = f32::to_radians(30.0);
+        let (sin, cos) = angle.sin_cos();
+        shapes.push(Shape::oriented_box(
+            Point::new(1.0, floor + 4.0, z + 8.0),
+            Frame::from_normal_tangent(Vector::new(sin, 0.0, cos), Vector::new(cos, 0.0, -sin)),
+            Vector::new(4.0, 4.0, 4.0),
+            cb.grey_diffuse_material,
+        ));
+
+        // a round mirror on the back wall
+        shapes.push(Shape::disk(
+            Point::new(0.0, 4.0, box_z_offset - half_length + 0.01),
+            Vector::new(0.0, 0.0, 1.0),
+            7.0,
+            Material::new(BSDF::Specular, Spectrum::white(), Spectrum::black()),
+        ));
+
+        Scene::new(Vec::new(), Vec::new(), shapes)
+    }
+
+    /// One teapot mesh placed three times: turned, stretched, and shrunk, two of them painted
+    /// with other materials.
+    pub fn new_instances() -> Scene {
+        let cb = Scene::cornell_box();
+        let (floor, z) = (-cb.half_length, cb.box_z_offset);
+        let up = Vector::new(0.0, 1.0, 0.0);
+        let teapot = Arc::new(Mesh::new(Scene::load_obj(
+            "obj/teapot.obj",
+            1.0,
+            Point::origin(),
+            cb.grey_diffuse_material,
+            &NormalOptions::default(),
+        )));
+        // the teapot's lowest point is this far below its origin
+        let teapot_bottom = 39.88;
+
+        let place = |scale: Transform, angle: f32, position: Point| {
+            scale
+                .then(&Transform::rotation(up, f32::to_radians(angle)))
+                .then(&Transform::translation(position - Point::origin()))
+        };
+        let instances = vec![
+            Instance::new(
+                teapot.clone(),
+                place(
+                    Transform::scale(0.1, 0.1, 0.1),
+                    30.0,
+                    Point::new(-9.0, floor + 0.1 * teapot_bottom, z - 8.0),
+                ),
+            ),
+            Instance::new(
+                teapot.clone(),
+                place(
+                    Transform::scale(0.07, 0.14, 0.07),
+                    -60.0,
+                    Point::new(10.0, floor + 0.14 * teapot_bottom, z - 2.0),
+                ),
+            )
+            .with_material(
+                Material::new(
+                    BSDF::Glossy { exponent: 200.0 },
+                    Spectrum::red(),
+                    Spectrum::black(),
+                )
+                .with_specular(Spectrum::grey().into()),
+            ),
+            Instance::new(
+                teapot,
+                place(
+                    Transform::scale(0.06, 0.06, 0.06),
+                    180.0,
+                    Point::new(1.0, floor + 0.06 * teapot_bottom, z + 10.0),
+                ),
+            )
+            .with_material(Material::new(
+                BSDF::Conductor { metal: Metal::Gold },
+                Spectrum::white(),
+                Spectrum::black(),
+            )),
+        ];
+
+        Scene::new(Vec::new(), vec![cb.sphere_light], cb.walls).with_instances(instances)
+    }
+
+    /// Sphere made of triangles by splitting the faces of an icosahedron `subdivisions` times,
+    /// with smooth normals.
+    fn icosphere(
+        center: Point,
+        radius: f32,
+        subdivisions: u32,
+        material: &Material,
+    ) -> TriangleMesh {
+        let phi = (1.0 + f32::sqrt(5.0)) / 2.0;
+        let mut vertices: Vec<Vector> = [
+            (-1.0, phi, 0.0),
+            (1.0, phi, 0.0),
+            (-1.0, -phi, 0.0),
+            (1.0, -phi, 0.0),
+            (0.0, -1.0, phi),
+            (0.0, 1.0, phi),
+            (0.0, -1.0, -phi),
+            (0.0, 1.0, -phi),
+            (phi, 0.0, -1.0),
+            (phi, 0.0, 1.0),
+            (-phi, 0.0, -1.0),
+            (-phi, 0.0, 1.0),
+        ]
+        .iter()
+        .map(|&(x, y, z)| Vector::new_normalized(x, y, z))
+        .collect();
+        let mut faces: Vec<[usize; 3]> = vec![
+            [0, 11, 5],
+            [0, 5, 1],
+            [0, 1, 7],
+            [0, 7, 10],
+            [0, 10, 11],
+            [1, 5, 9],
+            [5, 11, 4],
+            [11, 10, 2],
+            [10, 7, 6],
+            [7, 1, 8],
+            [3, 9, 4],
+            [3, 4, 2],
+            [3, 2, 6],
+            [3, 6, 8],
+            [3, 8, 9],
+            [4, 9, 5],
+            [2, 4, 11],
+            [6, 2, 10],
+            [8, 6, 7],
+            [9, 8, 1],
+        ];
+
+        for _ in 0..subdivisions {
+            // edges are shared by two faces, which have to share the vertex splitting them
+            let mut midpoints: HashMap<(usize, usize), usize> = HashMap::new();
+            let mut midpoint = |a: usize, b: usize, vertices: &mut Vec<Vector>| {
+                *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                    vertices.push((vertices[a] + vertices[b]).normalized());
+                    vertices.len() - 1
+                })
+            };
+            faces = faces
+                .iter()
+                .flat_map(|&[a, b, c]| {
+                    let ab = midpoint(a, b, &mut vertices);
+                    let bc = midpoint(b, c, &mut vertices);
+                    let ca = midpoint(c, a, &mut vertices);
+                    vec![[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+                })
+                .collect();
+        }
+
+        let positions = vertices.iter().map(|&n| center + n * radius).collect();
+        let faces = faces.iter().map(|face| face.map(|i| i as u32)).collect();
+        TriangleMesh::new(positions, faces, material.clone()).with_normals(vertices)
     }
 
     /// Intersects the scene with the given ray.
-    /// Iterative BVH traversal with inline intersection testing.
+    /// Iterative BVH traversal with inline intersection testing, going down into the meshes of
+    /// the instances the ray may hit. Hits on transparent parts of opacity-masked materials are
+    /// skipped.
     #[inline]
     pub fn intersect(&self, ray: Ray) -> Option<RayIntersection> {
-        let bvh_ray = ray_to_bvh_ray(&ray);
-        let nodes = &self.bvh.nodes;
-
-        BVH_STACK.with(|cell| {
-            let mut stack = cell.borrow_mut();
-            stack.clear();
-            stack.push(0);
-
-            let mut min_dist = f32::INFINITY;
-            let mut min_object: Option<&Object> = None;
-
-            while let Some(node_index) = stack.pop() {
-                match nodes[node_index] {
-                    BVHNode::Node {
-                        ref child_l_aabb,
-                        child_l_index,
-                        ref child_r_aabb,
-                        child_r_index,
-                        ..
-                    } => {
-                        if bvh_ray.intersects_aabb(child_l_aabb) {
-                            stack.push(child_l_index);
-                        }
-                        if bvh_ray.intersects_aabb(child_r_aabb) {
-                            stack.push(child_r_index);
-                        }
-                    }
-                    BVHNode::Leaf { shape_index, .. } => {
-                        let object = &self.objects[shape_index];
-                        if let Some(d) = object.intersect(&ray) {
-                            if d < min_dist {
-                                min_dist = d;
-                                min_object = Some(object);
-                            }
+        let mut closest = BVH_STACK
+            .with(|cell| closest_hit(&self.bvh, &self.objects, &ray, None, &mut cell.borrow_mut()))
+            .map(|(object, distance)| RayIntersection::new(object, ray, distance));
+
+        if let Some(instance_bvh) = &self.instance_bvh {
+            INSTANCE_STACK.with(|cell| {
+                traverse_bvh(instance_bvh, &ray, &mut cell.borrow_mut(), |index| {
+                    let instance = &self.instances[index];
+                    let (object_ray, scale) = instance.object_ray(&ray);
+                    let mesh = &instance.mesh;
+                    let hit = MESH_STACK.with(|cell| {
+                        let material = instance.material_override();
+                        closest_hit(
+                            &mesh.bvh,
+                            &mesh.objects,
+                            &object_ray,
+                            material,
+                            &mut cell.borrow_mut(),
+                        )
+                    });
+                    if let Some((object, distance)) = hit {
+                        let distance = distance / scale;
+                        if closest.as_ref().is_none_or(|c| distance < c.distance) {
+                            closest = Some(RayIntersection {
+                                distance,
+                                object,
+                                instance: Some(instance),
+                                ray,
+                            });
                         }
                     }
-                }
-            }
-            min_object.map(|object| RayIntersection::new(object, ray, min_dist))
-        })
+                    false
+                });
+            });
+        }
+        closest
     }
 
     /// Tests if any object blocks the ray before `max_dist`.
-    /// Iterative BVH traversal with early exit.
+    /// Iterative BVH traversal with early exit. Opacity masks let rays through.
     #[inline]
     pub fn is_occluded(&self, ray: &Ray, max_dist: f32) -> bool {
-        let bvh_ray = ray_to_bvh_ray(ray);
-        let nodes = &self.bvh.nodes;
-
-        BVH_SHADOW_STACK.with(|cell| {
-            let mut stack = cell.borrow_mut();
-            stack.clear();
-            stack.push(0);
-
-            while let Some(node_index) = stack.pop() {
-                match nodes[node_index] {
-                    BVHNode::Node {
-                        ref child_l_aabb,
-                        child_l_index,
-                        ref child_r_aabb,
-                        child_r_index,
-                        ..
-                    } => {
-                        if bvh_ray.intersects_aabb(child_l_aabb) {
-                            stack.push(child_l_index);
-                        }
-                        if bvh_ray.intersects_aabb(child_r_aabb) {
-                            stack.push(child_r_index);
-                        }
-                    }
-                    BVHNode::Leaf { shape_index, .. } => {
-                        let object = &self.objects[shape_index];
-                        if let Some(d) = object.intersect(ray) {
-                            if d > 0.0 && d < max_dist {
-                                if object.material().emittance.is_black() {
-                                    return true; // early exit - found a blocker
-                                }
-                            }
-                        }
+        let blocked = BVH_SHADOW_STACK.with(|cell| {
+            is_blocked(
+                &self.bvh,
+                &self.objects,
+                ray,
+                max_dist,
+                None,
+                &mut cell.borrow_mut(),
+            )
+        });
+        if blocked {
+            return true;
+        }
+        match &self.instance_bvh {
+            Some(instance_bvh) => INSTANCE_STACK.with(|cell| {
+                traverse_bvh(instance_bvh, ray, &mut cell.borrow_mut(), |index| {
+                    let instance = &self.instances[index];
+                    let (object_ray, scale) = instance.object_ray(ray);
+                    let mesh = &instance.mesh;
+                    MESH_STACK.with(|cell| {
+                        let material = instance.material_override();
+                        let max_dist = max_dist * scale;
+                        is_blocked(
+                            &mesh.bvh,
+                            &mesh.objects,
+                            &object_ray,
+                            max_dist,
+                            material,
+                            &mut cell.borrow_mut(),
+                        )
+                    })
+                })
+            }),
+            None => false,
+        }
+    }
+
+    /// Fraction of light making it `max_dist` along the ray, starting out in `medium`. Media
+    /// boundaries and lights let light through, anything else blocks it.
+    pub fn transmittance(
+        &self,
+        ray: &Ray,
+        max_dist: f32,
+        medium: Option<&Medium>,
+        wavelengths: &Wavelengths,
+    ) -> Spectrum {
+        if self.is_occluded(ray, max_dist) {
+            return Spectrum::black();
+        }
+        if !self.has_interiors {
+            return medium.map_or(Spectrum::white(), |m| {
+                m.transmittance(ray, max_dist, wavelengths)
+            });
+        }
+
+        // walk through the boundaries, switching media as they're crossed
+        let (mut ray, mut remaining, mut medium) = (*ray, max_dist, medium);
+        let mut transmittance = Spectrum::white();
+        loop {
+            let hit = self
+                .intersect(ray)
+                .filter(|hit| hit.distance() > 0.0 && hit.distance() < remaining);
+            let distance = hit.as_ref().map_or(remaining, |hit| hit.distance());
+            if let Some(medium) = medium {
+                transmittance = transmittance * medium.transmittance(&ray, distance, wavelengths);
+            }
+            match hit {
+                Some(hit) => {
+                    if hit.is_medium_boundary() {
+                        medium = self.medium_across(&hit);
                     }
+                    ray = hit.continue_through();
+                    remaining -= distance;
                 }
+                None => return transmittance,
             }
-            false
-        })
+        }
+    }
+
+    /// The medium the ray of `hit` ends up in after crossing the surface: the object's
+    /// interior when going in, the fog when coming out. Media don't nest.
+    #[inline]
+    pub fn medium_across<'a>(&'a self, hit: &RayIntersection<'a>) -> Option<&'a Medium> {
+        if hit.is_entering() {
+            hit.material().interior.as_deref()
+        } else {
+            self.fog.as_ref()
+        }
+    }
+
+    /// Medium the camera starts out in.
+    #[inline]
+    pub fn fog(&self) -> Option<&Medium> {
+        self.fog.as_ref()
     }
 
     /// Returns light objects. Returns a slice reference to avoid allocation.
@@ -500,6 +1737,176 @@ impl Scene {
     pub fn get_object(&self, index: usize) -> &Object {
         &self.objects[index]
     }
+
+    /// Where renders of the scene start from.
+    pub fn camera(&self) -> Camera {
+        self.camera
+    }
+
+    #[inline]
+    pub fn point_lights(&self) -> &[PointLight] {
+        &self.point_lights
+    }
+
+    /// Number of triangles stored, counting instanced meshes once.
+    pub fn triangle_count(&self) -> usize {
+        let instanced: usize = self
+            .instanced_meshes()
+            .iter()
+            .map(|mesh| mesh.triangle_count())
+            .sum();
+        self.meshes
+            .iter()
+            .map(|mesh| mesh.triangle_count())
+            .sum::<usize>()
+            + instanced
+    }
+
+    /// Bytes taken by the geometry and the BVHs. Textures and media aren't counted.
+    pub fn memory_usage(&self) -> usize {
+        let boxed: usize = self
+            .objects
+            .iter()
+            .map(|object| match object {
+                Object::Triangle(_) => 0,
+                Object::Sphere(_) => size_of::<Sphere>(),
+                Object::Shape(_) => size_of::<Shape>(),
+            })
+            .sum();
+        let bvh_nodes = self.bvh.nodes.capacity()
+            + self
+                .instance_bvh
+                .as_ref()
+                .map_or(0, |bvh| bvh.nodes.capacity());
+        let meshes: usize = self.meshes.iter().map(|mesh| mesh.memory_usage()).sum();
+        let instanced: usize = self
+            .instanced_meshes()
+            .iter()
+            .map(|mesh| mesh.memory_usage())
+            .sum();
+        self.objects.capacity() * size_of::<Object>()
+            + boxed
+            + bvh_nodes * size_of::<BVHNode>()
+            + meshes
+            + self.instances.capacity() * size_of::<Instance>()
+            + instanced
+    }
+
+    /// Every mesh used by the instances, once.
+    fn instanced_meshes(&self) -> Vec<&Mesh> {
+        let mut meshes: Vec<&Arc<Mesh>> = self.instances.iter().map(|i| &i.mesh).collect();
+        meshes.sort_by_key(|mesh| Arc::as_ptr(mesh));
+        meshes.dedup_by(|a, b| Arc::ptr_eq(a, b));
+        meshes.into_iter().map(|mesh| mesh.as_ref()).collect()
+    }
+
+    /// The first photometric profile used by a light, for previewing.
+    pub fn ies_profile(&self) -> Option<&IesProfile> {
+        let point_light_profiles = self.point_lights.iter().filter_map(|l| l.profile());
+        let emitter_profiles = self.light_indexes.iter().filter_map(|&i| {
+            let material = self.objects[i].material();
+            material
+                .emission_profile
+                .as_ref()
+                .map(|(profile, _)| profile)
+        });
+        point_light_profiles
+            .chain(emitter_profiles)
+            .next()
+            .map(|profile| profile.as_ref())
+    }
+}
+
+/// Visits the leaves of `bvh` whose boxes `ray` goes through, until `visit` returns true.
+/// Returns whether it stopped early.
+#[inline(always)]
+fn traverse_bvh(
+    bvh: &BVH,
+    ray: &Ray,
+    stack: &mut Vec<usize>,
+    mut visit: impl FnMut(usize) -> bool,
+) -> bool {
+    if bvh.nodes.is_empty() {
+        return false;
+    }
+    let bvh_ray = ray_to_bvh_ray(ray);
+    stack.clear();
+    stack.push(0);
+
+    while let Some(node_index) = stack.pop() {
+        match bvh.nodes[node_index] {
+            BVHNode::Node {
+                ref child_l_aabb,
+                child_l_index,
+                ref child_r_aabb,
+                child_r_index,
+                ..
+            } => {
+                if bvh_ray.intersects_aabb(child_l_aabb) {
+                    stack.push(child_l_index);
+                }
+                if bvh_ray.intersects_aabb(child_r_aabb) {
+                    stack.push(child_r_index);
+                }
+            }
+            BVHNode::Leaf { shape_index, .. } => {
+                if visit(shape_index) {
+                    return true;
+                }
+            }
+        }
+    }
+    false
+}
+
+/// Closest of `objects` hit by `ray`, and how far along it. `material` stands in for the
+/// objects' own when checking opacity masks.
+#[inline(always)]
+fn closest_hit<'a>(
+    bvh: &BVH,
+    objects: &'a [Object],
+    ray: &Ray,
+    material: Option<&Material>,
+    stack: &mut Vec<usize>,
+) -> Option<(&'a Object, f32)> {
+    let mut closest: Option<(&Object, f32)> = None;
+    traverse_bvh(bvh, ray, stack, |index| {
+        let object = &objects[index];
+        if let Some(d) = object.intersect(ray) {
+            let material = material.unwrap_or_else(|| object.material());
+            if closest.is_none_or(|(_, min_dist)| d < min_dist)
+                && object.is_opaque_at(material, ray, d)
+            {
+                closest = Some((object, d));
+            }
+        }
+        false
+    });
+    closest
+}
+
+/// Whether any of `objects` blocks `ray` before `max_dist`. Lights and media boundaries don't.
+#[inline(always)]
+fn is_blocked(
+    bvh: &BVH,
+    objects: &[Object],
+    ray: &Ray,
+    max_dist: f32,
+    material: Option<&Material>,
+    stack: &mut Vec<usize>,
+) -> bool {
+    traverse_bvh(bvh, ray, stack, |index| {
+        let object = &objects[index];
+        let material = material.unwrap_or_else(|| object.material());
+        match object.intersect(ray) {
+            Some(d) if d > 0.0 && d < max_dist => {
+                material.emittance.is_black()
+                    && !material.is_medium_boundary()
+                    && object.is_opaque_at(material, ray, d)
+            }
+            _ => false,
+        }
+    })
 }
 
 #[inline(always)]
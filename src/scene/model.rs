@@ -0,0 +1,43 @@
+use super::objects::Material;
+use super::triangle_mesh::TriangleMesh;
+use super::Vector;
+
+/// A named object or group of a mesh file, kept apart so it can be edited on its own before
+/// being handed to a `Scene`.
+pub struct Model {
+    pub name: String,
+    pub mesh: TriangleMesh,
+    /// Hidden models are left out of the scene.
+    pub visible: bool,
+}
+
+impl Model {
+    pub fn new(name: String, mesh: TriangleMesh) -> Model {
+        Model {
+            name,
+            mesh,
+            visible: true,
+        }
+    }
+
+    /// Replaces the material of every face, including ones coming from an MTL file.
+    pub fn set_material(&mut self, material: Material) {
+        self.mesh.set_material(material);
+    }
+
+    /// Uniformly scales the model about the origin, then moves it by `offset`.
+    pub fn transform(&mut self, scale: f32, offset: Vector) {
+        self.mesh.transform(scale, offset);
+    }
+
+    /// Gathers the faces of all visible models into one mesh.
+    pub fn into_mesh(models: Vec<Model>) -> TriangleMesh {
+        models.into_iter().filter(|model| model.visible).fold(
+            TriangleMesh::empty(),
+            |mut mesh, model| {
+                mesh.append(model.mesh);
+                mesh
+            },
+        )
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use super::objects::{Material, BSDF};
+use super::texture::{FilterMode, ImageTexture, Texture, WrapMode};
+use crate::common::Spectrum;
+use crate::spectral::Ior;
+
+/// Turns the materials of an MTL file into ours. Textures are resolved relative to the
+/// directory of the OBJ file and loaded once even if several materials use them.
+pub struct MtlConverter {
+    directory: PathBuf,
+    textures: HashMap<(PathBuf, bool), Option<Arc<ImageTexture>>>,
+}
+
+impl MtlConverter {
+    pub fn new(obj_filename: &str) -> MtlConverter {
+        let directory = Path::new(obj_filename)
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        MtlConverter {
+            directory,
+            textures: HashMap::new(),
+        }
+    }
+
+    /// Maps the usual MTL statements onto our BSDFs:
+    /// - `Kd` / `map_Kd` become a diffuse reflectance,
+    /// - `Ks` with `Ns` add a glossy lobe, `illum 3` or `5` turns a non-diffuse material
+    ///   into a mirror,
+    /// - `d < 1` or `Ni != 1` with `illum 4`, `6`, `7` or `9` make it glass,
+    /// - `Ke` makes it emissive,
+    /// - `map_d` is used as an opacity mask and `bump` / `map_Bump` as a bump map.
+    pub fn convert(&mut self, mtl: &tobj::Material) -> Material {
+        let diffuse = spectrum(mtl.diffuse);
+        let specular = spectrum(mtl.specular);
+        let emittance = mtl
+            .unknown_param
+            .get("Ke")
+            .and_then(|ke| parse_floats(ke))
+            .map(spectrum)
+            .unwrap_or_else(Spectrum::black);
+        let illum = mtl.illumination_model.unwrap_or(2);
+
+        let transparent = mtl.dissolve < 1.0 && mtl.dissolve_texture.is_empty();
+        let refractive = matches!(illum, 4 | 6 | 7 | 9) && mtl.optical_density != 1.0;
+        let (bsdf, reflectance) = if transparent || refractive {
+            let ior = if mtl.optical_density > 1.0 {
+                mtl.optical_density
+            } else {
+                1.5
+            };
+            // glass is tinted by its transmission filter when there is one
+            let tint = mtl
+                .unknown_param
+                .get("Tf")
+                .and_then(|tf| parse_floats(tf))
+                .map(spectrum)
+                .unwrap_or_else(Spectrum::white);
+            (
+                BSDF::Dielectric {
+                    ior: Ior::Constant(ior),
+                },
+                tint,
+            )
+        } else if matches!(illum, 3 | 5) && diffuse.is_black() && !specular.is_black() {
+            (BSDF::Specular, specular)
+        } else if illum >= 2 && !specular.is_black() && mtl.shininess > 0.0 {
+            (
+                BSDF::Glossy {
+                    exponent: mtl.shininess,
+                },
+                diffuse,
+            )
+        } else {
+            (BSDF::Diffuse, diffuse)
+        };
+
+        let mut material = Material::new(bsdf, reflectance, emittance);
+        if let BSDF::Glossy { .. } = bsdf {
+            material = material.with_specular(specular.into());
+        }
+        if !mtl.diffuse_texture.is_empty() {
+            if let Some(texture) = self.texture(&mtl.diffuse_texture, true) {
+                material = material.with_reflectance(texture);
+            }
+        }
+        if !mtl.dissolve_texture.is_empty() {
+            if let Some(texture) = self.texture(&mtl.dissolve_texture, false) {
+                material = material.with_opacity(texture);
+            }
+        }
+        if !mtl.normal_texture.is_empty() {
+            let (filename, scale) = parse_bump(&mtl.normal_texture);
+            if let Some(texture) = self.texture(filename, false) {
+                material = material.with_bump_map(texture, scale);
+            }
+        }
+        material
+    }
+
+    /// A failed load is reported once and leaves the parameter untextured.
+    fn texture(&mut self, filename: &str, srgb: bool) -> Option<Texture> {
+        let path = self.directory.join(filename.replace('\\', "/"));
+        self.textures
+            .entry((path.clone(), srgb))
+            .or_insert_with(|| {
+                match ImageTexture::open(
+                    &path.to_string_lossy(),
+                    WrapMode::Repeat,
+                    FilterMode::Trilinear,
+                    srgb,
+                ) {
+                    Ok(texture) => Some(Arc::new(texture)),
+                    Err(e) => {
+                        eprintln!("Couldn't load texture {}: {}", path.display(), e);
+                        None
+                    }
+                }
+            })
+            .clone()
+            .map(Texture::Image)
+    }
+}
+
+fn spectrum(rgb: [f32; 3]) -> Spectrum {
+    Spectrum::new_f(rgb[0], rgb[1], rgb[2])
+}
+
+/// Reads "r g b" or a single grey value.
+fn parse_floats(value: &str) -> Option<[f32; 3]> {
+    let values: Vec<f32> = value
+        .split_whitespace()
+        .map(str::parse)
+        .collect::<Result<_, _>>()
+        .ok()?;
+    match values[..] {
+        [grey] => Some([grey; 3]),
+        [r, g, b, ..] => Some([r, g, b]),
+        _ => None,
+    }
+}
+
+/// Splits a bump map statement like `-bm 0.5 bump.png` into the filename, which comes last,
+/// and the `-bm` multiplier.
+fn parse_bump(statement: &str) -> (&str, f32) {
+    let tokens: Vec<&str> = statement.split_whitespace().collect();
+    let scale = tokens
+        .iter()
+        .position(|&t| t == "-bm")
+        .and_then(|i| tokens.get(i + 1))
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(1.0);
+    (tokens.last().copied().unwrap_or(""), scale)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bump_options_are_parsed() {
+        assert_eq!(
+            parse_bump("-bm 0.25 textures/bump.png"),
+            ("textures/bump.png", 0.25)
+        );
+        assert_eq!(parse_bump("bump.png"), ("bump.png", 1.0));
+    }
+}
@@ -2,32 +2,108 @@ use bvh::aabb::{Bounded, AABB};
 use bvh::bounding_hierarchy::BHShape;
 
 use std::f32::consts::PI;
+use std::sync::Arc;
 
-use super::super::common::{Spectrum, EPS};
-use super::{Point, Ray, Vector};
+use super::super::common::{weighted_coin_flip, Spectrum, EPS};
+use super::ies::IesProfile;
+use super::media::{HomogeneousMedium, Medium};
+use super::shapes::Shape;
+use super::texture::{Texture, TextureContext};
+use super::triangle_mesh::TriangleMesh;
+use super::{Frame, Point, Ray, SurfaceInteraction, Vector};
+use crate::spectral::{fresnel_conductor, luminous_efficacy, Blackbody, Ior, Metal, Wavelengths};
 
 #[derive(Clone, Copy, Debug)]
 pub enum BSDF {
     Diffuse,
+    /// Oren-Nayar rough diffuse. `sigma` is the standard deviation of the microfacet slope
+    /// angle in radians; 0 is Lambertian.
+    OrenNayar {
+        sigma: f32,
+    },
+    /// Burley's diffuse, which brightens grazing angles with a retro-reflective lobe as
+    /// `roughness` (0..1) increases.
+    RetroDiffuse {
+        roughness: f32,
+    },
+    /// Lambertian `reflectance` plus a normalized Phong lobe tinted by `specular`.
+    Glossy {
+        exponent: f32,
+    },
     Specular,
+    /// Smooth metal reflecting according to its measured complex index of refraction,
+    /// tinted by `reflectance`.
+    Conductor {
+        metal: Metal,
+    },
+    /// Smooth glass: Fresnel-weighted mirror reflection and refraction, tinted by
+    /// `reflectance`. Normals are assumed to point out of the object. A dispersive `ior`
+    /// splits light into its wavelengths in spectral mode.
+    Dielectric {
+        ior: Ior,
+    },
+    /// Invisible boundary of a participating medium: rays pass straight through it.
+    Null,
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Debug)]
 pub struct Material {
     pub bsdf: BSDF,
-    pub reflectance: Spectrum,
-    pub emittance: Spectrum,
+    pub reflectance: Texture,
+    /// Colour of the glossy lobe of `BSDF::Glossy`.
+    pub specular: Texture,
+    pub emittance: Texture,
+    /// Overrides the roughness parameter of `OrenNayar` (sigma) and `RetroDiffuse` per hit.
+    pub roughness: Option<Texture>,
+    /// Tangent-space normal map, RGB in [0, 1] mapped to xyz in [-1, 1].
+    pub normal_map: Option<Texture>,
+    /// Height map, scaled by `bump_scale` world units.
+    pub bump_map: Option<Texture>,
+    pub bump_scale: f32,
+    /// Cutout mask: 0 is fully transparent, 1 is solid. `None` is solid everywhere.
+    pub opacity: Option<Texture>,
+    /// Makes `opacity` alpha-tested: solid where it's at least this, transparent elsewhere.
+    pub opacity_cutoff: Option<f32>,
+    /// Angular distribution of emitted light, in a frame whose z axis is the luminaire's
+    /// nadir. Scales `emittance` by the profile's intensity relative to its peak.
+    pub emission_profile: Option<(Arc<IesProfile>, Frame)>,
+    /// Whether the back of the surface shades like the front. One-sided surfaces are black
+    /// from behind. Closed refractive objects have to be two-sided, their back is the inside.
+    pub two_sided: bool,
+    /// Whether light is also emitted from the back of the surface.
+    pub two_sided_emission: bool,
+    /// Medium filling a closed mesh with this material, entered through its front faces.
+    pub interior: Option<Arc<Medium>>,
+}
+
+/// Colour of a physically specified light.
+#[derive(Clone, Copy, Debug)]
+pub enum LightColor {
+    Rgb(Spectrum),
+    /// Colour temperature of a blackbody.
+    Kelvin(f32),
+}
+
+/// Total power of a physically specified light, whatever its size.
+#[derive(Clone, Copy, Debug)]
+pub enum LightPower {
+    /// Radiant power. For blackbodies that includes what is emitted outside the visible range,
+    /// like the label of an incandescent bulb.
+    Watts(f32),
+    Lumens(f32),
 }
 
 pub struct LightSample {
     pub pdf: f32,
     pub wi: Vector,
     pub distance: f32,
+    pub point: Point,
 }
 
 pub enum Object {
     Triangle(Triangle),
-    Sphere(Sphere),
+    Sphere(Box<Sphere>),
+    Shape(Box<Shape>),
 }
 
 impl Object {
@@ -35,45 +111,9 @@ impl Object {
     #[inline(always)]
     pub fn intersect(&self, ray: &Ray) -> Option<f32> {
         match self {
-            Object::Triangle(triangle) => {
-                let (p1, p2, p3) = (triangle.p1, triangle.p2, triangle.p3);
-                let direction = ray.direction;
-                let e1 = p2 - p1;
-                let e2 = p3 - p1;
-                let s = ray.origin - p1;
-                let s1 = direction.cross(e2);
-                let s2 = s.cross(e1);
-                let scalar = 1.0 / s1.dot(e1);
-                let (t, b1, b2) = (
-                    s2.dot(e2) * scalar,
-                    s1.dot(s) * scalar,
-                    s2.dot(direction) * scalar,
-                );
-                if b1 < 0.0 || b2 < 0.0 || b1 > 1.0 || b2 > 1.0 || b1 + b2 > 1.0 + EPS || t < EPS {
-                    None
-                } else {
-                    Some(t)
-                }
-            }
-            Object::Sphere(sphere) => {
-                let l: Vector = sphere.center - ray.origin;
-                let adj = l.dot(ray.direction);
-                let d2 = l.dot(l) - (adj * adj);
-                let radius2 = sphere.radius * sphere.radius;
-                if d2 > radius2 {
-                    return None;
-                }
-                let thc = (radius2 - d2).sqrt();
-                let t0 = adj - thc;
-                let t1 = adj + thc;
-
-                if t0 < 0.0 && t1 < 0.0 {
-                    return None;
-                }
-
-                let distance = if t0 < t1 { t0 } else { t1 };
-                Some(distance)
-            }
+            Object::Triangle(triangle) => triangle.intersect(ray),
+            Object::Sphere(sphere) => sphere.intersect(ray),
+            Object::Shape(shape) => shape.intersect(ray),
         }
     }
 
@@ -82,22 +122,198 @@ impl Object {
         match self {
             Object::Triangle(triangle) => triangle.surface_normal(point),
             Object::Sphere(sphere) => sphere.surface_normal(point),
+            Object::Shape(shape) => shape.normal(point),
+        }
+    }
+
+    /// Normal of the actual geometry, which for triangles ignores the vertex normals.
+    #[inline(always)]
+    pub fn geometric_normal(&self, point: Point) -> Vector {
+        match self {
+            Object::Triangle(triangle) => triangle.plane_normal_not_normalized().normalized(),
+            Object::Sphere(sphere) => sphere.surface_normal(point),
+            Object::Shape(shape) => shape.normal(point),
+        }
+    }
+
+    /// Partial derivatives of the surface position with respect to u and v.
+    #[inline(always)]
+    pub fn uv_derivatives(&self, point: Point) -> (Vector, Vector) {
+        match self {
+            Object::Triangle(triangle) => triangle.uv_derivatives(),
+            Object::Sphere(sphere) => sphere.uv_derivatives(point),
+            Object::Shape(shape) => shape.uv_derivatives(point),
+        }
+    }
+
+    /// Shading frame at `point`: the interpolated normal, perturbed by `material`'s bump or
+    /// normal map, with the tangent following increasing u.
+    pub fn shading_frame(&self, material: &Material, point: Point, ctx: &TextureContext) -> Frame {
+        let mut normal = self.surface_normal(point).normalized();
+        let (dpdu, dpdv) = self.uv_derivatives(point);
+
+        if let Some(bump_map) = &material.bump_map {
+            normal = bump_normal(bump_map, material.bump_scale, normal, dpdu, dpdv, ctx);
+        }
+
+        let mut frame = Frame::from_normal_tangent(normal, dpdu);
+        // keep the bitangent pointing along increasing v, whatever the triangle winding
+        if frame.bitangent.dot(dpdv) < 0.0 {
+            frame.bitangent = -frame.bitangent;
         }
+
+        if let Some(normal_map) = &material.normal_map {
+            let [r, g, b] = normal_map.evaluate(ctx).rgb();
+            let mapped = frame.to_world(Vector::new(2.0 * r - 1.0, 2.0 * g - 1.0, 2.0 * b - 1.0));
+            if mapped.norm() > EPS {
+                let tangent = frame.tangent;
+                let bitangent = frame.bitangent;
+                frame = Frame::from_normal_tangent(mapped.normalized(), tangent);
+                if frame.bitangent.dot(bitangent) < 0.0 {
+                    frame.bitangent = -frame.bitangent;
+                }
+            }
+        }
+        frame
     }
 
     #[inline(always)]
     pub fn material(&self) -> &Material {
         match self {
-            Object::Triangle(triangle) => &triangle.material,
+            Object::Triangle(triangle) => triangle.material(),
             Object::Sphere(sphere) => &sphere.material,
+            Object::Shape(shape) => &shape.material,
+        }
+    }
+
+    #[inline(always)]
+    pub fn uv(&self, point: Point) -> (f32, f32) {
+        match self {
+            Object::Triangle(triangle) => triangle.uv(point),
+            Object::Sphere(sphere) => sphere.uv(point),
+            Object::Shape(shape) => shape.uv(point),
+        }
+    }
+
+    /// Surface area in square scene units.
+    pub fn area(&self) -> f32 {
+        match self {
+            Object::Triangle(triangle) => triangle.area(),
+            Object::Sphere(sphere) => sphere.area(),
+            Object::Shape(shape) => shape.area(),
+        }
+    }
+
+    /// Roughly how many uv units one world unit covers on this surface.
+    #[inline(always)]
+    pub fn uv_scale(&self) -> f32 {
+        match self {
+            Object::Triangle(triangle) => triangle.uv_scale(),
+            Object::Sphere(sphere) => 1.0 / (PI * sphere.radius),
+            Object::Shape(shape) => shape.uv_scale(),
+        }
+    }
+
+    /// `footprint` is the width in world units of the area the lookup should cover.
+    #[inline(always)]
+    pub fn texture_context(&self, point: Point, footprint: f32) -> TextureContext {
+        let object_point = match self {
+            Object::Triangle(_) => point,
+            Object::Sphere(sphere) => Point::origin() + (point - sphere.center),
+            Object::Shape(shape) => shape.object_point(point),
+        };
+        TextureContext {
+            uv: self.uv(point),
+            uv_width: footprint * self.uv_scale(),
+            point,
+            object_point,
+            vertex_color: match self {
+                Object::Triangle(triangle) => triangle.color(point),
+                _ => Spectrum::white(),
+            },
+            wavelengths: Wavelengths::rgb(),
         }
     }
 
+    /// Whether a hit `distance` along `ray` counts, given `material`'s opacity mask.
+    /// Partially transparent hits are kept with probability equal to their opacity, unless
+    /// the mask has a cutoff.
+    #[inline(always)]
+    pub fn is_opaque_at(&self, material: &Material, ray: &Ray, distance: f32) -> bool {
+        match &material.opacity {
+            None => true,
+            Some(opacity) => {
+                let point = ray.origin + ray.direction * distance;
+                let ctx = self.texture_context(point, ray.cone_width_at(distance));
+                let alpha = opacity.evaluate_alpha(&ctx);
+                match material.opacity_cutoff {
+                    Some(cutoff) => alpha >= cutoff,
+                    None => alpha >= 1.0 || (alpha > 0.0 && weighted_coin_flip(alpha)),
+                }
+            }
+        }
+    }
+
+    /// Radiance emitted at a point on the surface towards `direction`. The front of the surface
+    /// is the side its geometric normal points to.
+    #[inline(always)]
+    pub fn emittance(
+        &self,
+        point: Point,
+        direction: Vector,
+        wavelengths: &Wavelengths,
+    ) -> Spectrum {
+        self.emittance_with(self.material(), point, direction, wavelengths)
+    }
+
+    /// Like `emittance`, with `material` standing in for the object's own.
+    #[inline(always)]
+    pub fn emittance_with(
+        &self,
+        material: &Material,
+        point: Point,
+        direction: Vector,
+        wavelengths: &Wavelengths,
+    ) -> Spectrum {
+        if !material.two_sided_emission && direction.dot(self.geometric_normal(point)) <= 0.0 {
+            return Spectrum::black();
+        }
+        let emittance = match &material.emittance {
+            Texture::Constant(emittance) => wavelengths.upsample(*emittance),
+            texture => {
+                let mut ctx = self.texture_context(point, 0.0);
+                ctx.wavelengths = *wavelengths;
+                texture.evaluate_spectrum(&ctx)
+            }
+        };
+        match &material.emission_profile {
+            Some((profile, frame)) => {
+                emittance * profile.relative_intensity(frame.to_local(direction))
+            }
+            None => emittance,
+        }
+    }
+
+    /// Picks a point on the light to illuminate `intersection_point` with. The pdf is the
+    /// inverse of the density over solid angle.
     pub fn sample_l(&self, intersection_point: Point) -> LightSample {
         match self {
-            Object::Triangle(_) => {
-                unimplemented!()
+            Object::Triangle(triangle) => {
+                let (point, normal) = triangle.sample_point();
+                area_light_sample(intersection_point, point, normal, triangle.area())
             }
+            Object::Shape(shape) => match shape.sample_point() {
+                Some((point, normal)) => {
+                    area_light_sample(intersection_point, point, normal, shape.area())
+                }
+                // only found by bouncing into it
+                None => LightSample {
+                    pdf: 0.0,
+                    wi: Vector::new(0.0, 1.0, 0.0),
+                    distance: 0.0,
+                    point: intersection_point,
+                },
+            },
             Object::Sphere(sphere) => {
                 let p = intersection_point;
                 let s = sphere.random_point();
@@ -112,47 +328,349 @@ impl Object {
                     pdf,
                     wi,
                     distance: d_s,
+                    point: s,
                 }
             }
         }
     }
+}
 
+impl Material {
+    /// Whether the surface is only there to bound a medium, and rays go straight through it.
     #[inline(always)]
-    pub fn bsdf(&self, _wi: Vector, _wo: Vector) -> Spectrum {
-        let material = self.material();
-        match material.bsdf {
-            BSDF::Diffuse => material.reflectance * (1.0 / PI),
-            BSDF::Specular => Spectrum::black(),
+    pub fn is_medium_boundary(&self) -> bool {
+        matches!(self.bsdf, BSDF::Null)
+    }
+
+    /// `wi` points towards the light, `wo` is the direction of the incoming ray.
+    #[inline(always)]
+    pub fn bsdf(&self, wi: Vector, wo: Vector, normal: Vector, ctx: &TextureContext) -> Spectrum {
+        let roughness = |default: f32| {
+            self.roughness
+                .as_ref()
+                .map_or(default, |texture| texture.evaluate_float(ctx))
+        };
+        match self.bsdf {
+            BSDF::Diffuse => self.reflectance.evaluate_spectrum(ctx) * (1.0 / PI),
+            BSDF::OrenNayar { sigma } => {
+                let sigma = roughness(sigma);
+                self.reflectance.evaluate_spectrum(ctx) * (oren_nayar(wi, -wo, normal, sigma) / PI)
+            }
+            BSDF::RetroDiffuse { roughness: r } => {
+                let r = roughness(r);
+                self.reflectance.evaluate_spectrum(ctx) * (retro_diffuse(wi, -wo, normal, r) / PI)
+            }
+            BSDF::Glossy { exponent } => {
+                let diffuse = self.reflectance.evaluate_spectrum(ctx) * (1.0 / PI);
+                let cos_alpha = f32::max(0.0, wi.dot(reflect(wo, normal)));
+                let lobe = (exponent + 2.0) / (2.0 * PI) * f32::powf(cos_alpha, exponent);
+                diffuse + self.specular.evaluate_spectrum(ctx) * lobe
+            }
+            BSDF::Specular | BSDF::Conductor { .. } | BSDF::Dielectric { .. } | BSDF::Null => {
+                Spectrum::black()
+            }
         }
     }
 
     /// Use instead of bsdf when you want to bounce the vector.
-    pub fn sample_bsdf(&self, wo: Vector, normal: Vector) -> BSDFSample {
-        let material = self.material();
-        match material.bsdf {
-            BSDF::Diffuse => {
-                let wi = Vector::random_hemisphere().to_coord_space(normal);
-                let pdf = 2.0 * PI;
-                let reflected = self.bsdf(wi, wo);
-                BSDFSample { wi, pdf, reflected }
+    pub fn sample_bsdf(&self, wo: Vector, si: &SurfaceInteraction) -> BSDFSample {
+        let (frame, ctx) = (&si.frame, &si.ctx);
+        let normal = frame.normal;
+        match self.bsdf {
+            BSDF::Diffuse | BSDF::OrenNayar { .. } | BSDF::RetroDiffuse { .. } => {
+                // sample around the side of the surface the ray came from
+                let local = Vector::random_cosine_hemisphere();
+                let (wi, normal) = if wo.dot(normal) > 0.0 {
+                    (frame.to_world(-local), -normal)
+                } else {
+                    (frame.to_world(local), normal)
+                };
+                let cos_theta = f32::max(wi.dot(normal), EPS);
+                let pdf = PI / cos_theta;
+                let reflected = self.bsdf(wi, wo, normal, ctx);
+                BSDFSample {
+                    wi,
+                    pdf,
+                    reflected,
+                    specular: false,
+                    transmitted: false,
+                    dispersed: false,
+                }
+            }
+            BSDF::Glossy { exponent } => {
+                let normal = if wo.dot(normal) > 0.0 {
+                    -normal
+                } else {
+                    normal
+                };
+                let mirror = reflect(wo, normal);
+                // pick a lobe proportionally to how much it reflects, but evaluate both
+                let diffuse_weight = self.reflectance.evaluate(ctx).average();
+                let specular_weight = self.specular.evaluate(ctx).average();
+                let total_weight = diffuse_weight + specular_weight;
+                let p_specular = if total_weight > 0.0 {
+                    specular_weight / total_weight
+                } else {
+                    0.5
+                };
+                let wi = if weighted_coin_flip(p_specular) {
+                    let cos_alpha = f32::powf(fastrand::f32(), 1.0 / (exponent + 1.0));
+                    let sin_alpha = f32::sqrt(f32::max(0.0, 1.0 - cos_alpha * cos_alpha));
+                    let (sin_phi, cos_phi) = f32::sin_cos(2.0 * PI * fastrand::f32());
+                    Frame::from_normal(mirror).to_world(Vector::new(
+                        sin_alpha * cos_phi,
+                        sin_alpha * sin_phi,
+                        cos_alpha,
+                    ))
+                } else {
+                    Vector::random_cosine_hemisphere().to_coord_space(normal)
+                };
+
+                let cos_theta = f32::max(0.0, wi.dot(normal));
+                let cos_alpha = f32::max(0.0, wi.dot(mirror));
+                let density = (1.0 - p_specular) * cos_theta / PI
+                    + p_specular * (exponent + 1.0) / (2.0 * PI) * f32::powf(cos_alpha, exponent);
+                let (pdf, reflected) = if density > EPS && cos_theta > 0.0 {
+                    (1.0 / density, self.bsdf(wi, wo, normal, ctx))
+                } else {
+                    (0.0, Spectrum::black())
+                };
+                BSDFSample {
+                    wi,
+                    pdf,
+                    reflected,
+                    specular: false,
+                    transmitted: false,
+                    dispersed: false,
+                }
             }
             BSDF::Specular => {
-                let wi = wo - normal * 2.0 * wo.dot(normal);
+                let wi = reflect(wo, normal);
                 let pdf = 1.0;
                 let cos_theta = f32::abs(wi.dot(normal));
                 // undoing the cos theta multiplication in the raytracer
-                let reflected = material.reflectance * (1.0 / cos_theta);
-                BSDFSample { wi, pdf, reflected }
+                let reflected = self.reflectance.evaluate_spectrum(ctx) * (1.0 / cos_theta);
+                BSDFSample {
+                    wi,
+                    pdf,
+                    reflected,
+                    specular: true,
+                    transmitted: false,
+                    dispersed: false,
+                }
+            }
+            BSDF::Conductor { metal } => {
+                let wi = reflect(wo, normal);
+                let cos_theta = f32::abs(wi.dot(normal));
+                let fresnel = ctx.wavelengths.map(|lambda| {
+                    let (eta, k) = metal.ior(lambda);
+                    fresnel_conductor(cos_theta, eta, k)
+                });
+                let reflected =
+                    self.reflectance.evaluate_spectrum(ctx) * fresnel * (1.0 / cos_theta);
+                BSDFSample {
+                    wi,
+                    pdf: 1.0,
+                    reflected,
+                    specular: true,
+                    transmitted: false,
+                    dispersed: false,
+                }
+            }
+            BSDF::Dielectric { ior } => {
+                let wavelengths = ctx.wavelengths;
+                // the normal is on the outside of the object
+                let entering = si.entering;
+                let normal = if entering { normal } else { -normal };
+                let cos_i = -wo.dot(normal);
+                let etas =
+                    ior.for_wavelengths(wavelengths)
+                        .map(|ior| if entering { 1.0 / ior } else { ior });
+                let fresnel = etas.map(|eta| fresnel_dielectric(cos_i, eta));
+
+                // the hero wavelength picks the direction
+                let (wi, transmitted) = match refract(wo, normal, etas[0]) {
+                    Some(refracted) if !weighted_coin_flip(fresnel[0]) => (refracted, true),
+                    _ => (reflect(wo, normal), false),
+                };
+                let cos_theta = f32::abs(wi.dot(normal));
+                // choosing between the two by the hero's Fresnel term cancels it out for the
+                // hero, the others are weighted by how much their own term differs
+                let dispersed = transmitted && ior.is_dispersive() && wavelengths.is_spectral();
+                let weight = if dispersed {
+                    wavelengths.hero_only()
+                } else if transmitted {
+                    let [f0, f1, f2] = fresnel;
+                    Spectrum::new_f(1.0, (1.0 - f1) / (1.0 - f0), (1.0 - f2) / (1.0 - f0))
+                } else {
+                    let [f0, f1, f2] = fresnel;
+                    Spectrum::new_f(1.0, f1 / f0, f2 / f0)
+                };
+                let reflected =
+                    self.reflectance.evaluate_spectrum(ctx) * weight * (1.0 / cos_theta);
+                BSDFSample {
+                    wi,
+                    pdf: 1.0,
+                    reflected,
+                    specular: true,
+                    transmitted,
+                    dispersed,
+                }
+            }
+            BSDF::Null => {
+                let cos_theta = f32::max(f32::abs(wo.dot(normal)), EPS);
+                BSDFSample {
+                    wi: wo,
+                    pdf: 1.0,
+                    reflected: Spectrum::white() * (1.0 / cos_theta),
+                    specular: true,
+                    transmitted: true,
+                    dispersed: false,
+                }
             }
         }
     }
 }
 
+/// Light sample for a point picked uniformly over a surface of `area`: converts the area
+/// density to solid angle as seen from `from`.
+#[inline(always)]
+fn area_light_sample(from: Point, point: Point, normal: Vector, area: f32) -> LightSample {
+    let to_light = point - from;
+    let distance = to_light.norm();
+    let wi = to_light * (1.0 / distance);
+    let cos_light = f32::abs(normal.dot(wi));
+    LightSample {
+        pdf: area * cos_light / (distance * distance),
+        wi,
+        distance,
+        point,
+    }
+}
+
+/// Mirrors the incoming direction `wo` about `normal`.
+#[inline(always)]
+fn reflect(wo: Vector, normal: Vector) -> Vector {
+    wo - normal * 2.0 * wo.dot(normal)
+}
+
+/// Refracts the incoming direction `wo` through a surface whose `normal` faces it, with `eta`
+/// the ratio of the indices of refraction (incident over transmitted). `None` on total
+/// internal reflection.
+#[inline(always)]
+fn refract(wo: Vector, normal: Vector, eta: f32) -> Option<Vector> {
+    let cos_i = -wo.dot(normal);
+    let sin2_t = eta * eta * f32::max(0.0, 1.0 - cos_i * cos_i);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = f32::sqrt(1.0 - sin2_t);
+    Some(wo * eta + normal * (eta * cos_i - cos_t))
+}
+
+/// Fraction of light reflected at a smooth dielectric boundary, for unpolarized light.
+fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+    let cos_i = cos_i.clamp(0.0, 1.0);
+    let sin2_t = eta * eta * (1.0 - cos_i * cos_i);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = f32::sqrt(1.0 - sin2_t);
+    let r_parallel = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    let r_perpendicular = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    0.5 * (r_parallel * r_parallel + r_perpendicular * r_perpendicular)
+}
+
+/// Normal of a surface displaced along `normal` by a height map (Blinn's bump mapping), with
+/// the height derivatives taken by finite differences in uv.
+fn bump_normal(
+    bump_map: &Texture,
+    scale: f32,
+    normal: Vector,
+    dpdu: Vector,
+    dpdv: Vector,
+    ctx: &TextureContext,
+) -> Vector {
+    let du = f32::max(0.5 * ctx.uv_width, 0.0005);
+    let dv = du;
+    let shifted = |du: f32, dv: f32| {
+        let offset = dpdu * du + dpdv * dv;
+        TextureContext {
+            uv: (ctx.uv.0 + du, ctx.uv.1 + dv),
+            point: ctx.point + offset,
+            object_point: ctx.object_point + offset,
+            ..*ctx
+        }
+    };
+    let height = bump_map.evaluate_float(ctx) * scale;
+    let height_u = bump_map.evaluate_float(&shifted(du, 0.0)) * scale;
+    let height_v = bump_map.evaluate_float(&shifted(0.0, dv)) * scale;
+
+    let dpdu = dpdu + normal * ((height_u - height) / du);
+    let dpdv = dpdv + normal * ((height_v - height) / dv);
+    let bumped = dpdu.cross(dpdv);
+    if bumped.norm() <= EPS {
+        return normal;
+    }
+    let bumped = bumped.normalized();
+    if bumped.dot(normal) < 0.0 {
+        -bumped
+    } else {
+        bumped
+    }
+}
+
+/// Oren-Nayar factor relative to Lambertian, for `wi` and `wo` both pointing away from the
+/// surface.
+fn oren_nayar(wi: Vector, wo: Vector, normal: Vector, sigma: f32) -> f32 {
+    let sigma2 = sigma * sigma;
+    let a = 1.0 - sigma2 / (2.0 * (sigma2 + 0.33));
+    let b = 0.45 * sigma2 / (sigma2 + 0.09);
+
+    let cos_i = f32::min(f32::abs(wi.dot(normal)), 1.0);
+    let cos_o = f32::min(f32::abs(wo.dot(normal)), 1.0);
+    let sin_i = f32::sqrt(1.0 - cos_i * cos_i);
+    let sin_o = f32::sqrt(1.0 - cos_o * cos_o);
+
+    // cosine of the azimuthal angle between the two directions
+    let max_cos = if sin_i > EPS && sin_o > EPS {
+        let wi_t = wi - normal * wi.dot(normal);
+        let wo_t = wo - normal * wo.dot(normal);
+        f32::max(0.0, wi_t.dot(wo_t) / (wi_t.norm() * wo_t.norm()))
+    } else {
+        0.0
+    };
+
+    let (sin_alpha, tan_beta) = if cos_i > cos_o {
+        (sin_o, sin_i / cos_i)
+    } else {
+        (sin_i, sin_o / f32::max(cos_o, EPS))
+    };
+    a + b * max_cos * sin_alpha * tan_beta
+}
+
+/// Burley diffuse factor relative to Lambertian, for `wi` and `wo` both pointing away from the
+/// surface.
+fn retro_diffuse(wi: Vector, wo: Vector, normal: Vector, roughness: f32) -> f32 {
+    let cos_i = f32::abs(wi.dot(normal));
+    let cos_o = f32::abs(wo.dot(normal));
+    let half = wi + wo;
+    let cos_d = if half.norm() > EPS {
+        wi.dot(half.normalized())
+    } else {
+        0.0
+    };
+    let fd90 = 0.5 + 2.0 * roughness * cos_d * cos_d;
+    let schlick = |cos: f32| 1.0 + (fd90 - 1.0) * f32::powi(1.0 - cos, 5);
+    schlick(cos_i) * schlick(cos_o)
+}
+
 impl Bounded for Object {
     fn aabb(&self) -> AABB {
         match self {
             Object::Triangle(triangle) => triangle.aabb(),
             Object::Sphere(sphere) => sphere.aabb(),
+            Object::Shape(shape) => shape.aabb(),
         }
     }
 }
@@ -164,6 +682,7 @@ impl BHShape for Object {
                 triangle.node_index = index;
             }
             Object::Sphere(sphere) => sphere.node_index = index,
+            Object::Shape(shape) => shape.node_index = index,
         }
     }
 
@@ -171,6 +690,7 @@ impl BHShape for Object {
         match self {
             Object::Triangle(triangle) => triangle.node_index,
             Object::Sphere(sphere) => sphere.node_index,
+            Object::Shape(shape) => shape.node_index,
         }
     }
 }
@@ -182,15 +702,10 @@ pub struct Sphere {
     node_index: usize,
 }
 
+/// One face of a `TriangleMesh`. Vertex data and materials stay in the mesh.
 pub struct Triangle {
-    p1: Point,
-    p2: Point,
-    p3: Point,
-    vn1: Vector,
-    vn2: Vector,
-    vn3: Vector,
-    plane_normal_not_normalized: Vector,
-    material: Material,
+    mesh: Arc<TriangleMesh>,
+    face: u32,
     node_index: usize,
 }
 
@@ -198,16 +713,142 @@ pub struct BSDFSample {
     pub wi: Vector,
     pub pdf: f32,
     pub reflected: Spectrum,
+    /// Whether `wi` was picked from a delta distribution (perfect mirror or glass).
+    pub specular: bool,
+    /// Whether `wi` goes through the surface rather than bouncing off it.
+    pub transmitted: bool,
+    /// Whether a dispersive refraction left only the hero wavelength on the path.
+    pub dispersed: bool,
 }
 
 impl Material {
     pub fn new(bsdf: BSDF, reflectance: Spectrum, emittance: Spectrum) -> Material {
         Material {
             bsdf,
-            reflectance,
-            emittance,
+            reflectance: reflectance.into(),
+            specular: Spectrum::black().into(),
+            emittance: emittance.into(),
+            roughness: None,
+            normal_map: None,
+            bump_map: None,
+            bump_scale: 1.0,
+            opacity: None,
+            opacity_cutoff: None,
+            emission_profile: None,
+            two_sided: true,
+            two_sided_emission: true,
+            interior: None,
         }
     }
+
+    pub fn with_reflectance(mut self, reflectance: Texture) -> Material {
+        self.reflectance = reflectance;
+        self
+    }
+
+    /// A black diffuse emitter of `area` square meters giving off `power`. Its radiance is in
+    /// nits, so scenes lit this way usually need an exposure to look right.
+    pub fn light(color: LightColor, power: LightPower, area: f32) -> Material {
+        let efficacy = match color {
+            LightColor::Rgb(rgb) => luminous_efficacy(rgb),
+            LightColor::Kelvin(temperature) => Blackbody::luminous_efficacy(temperature),
+        };
+        let lumens = match power {
+            LightPower::Watts(watts) => watts * efficacy,
+            LightPower::Lumens(lumens) => lumens,
+        };
+        // a Lambertian emitter sends pi times its radiance out of each unit of area
+        let luminance = lumens / (PI * area);
+        let emittance = match color {
+            LightColor::Rgb(rgb) => {
+                let [r, g, b] = rgb.rgb();
+                let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+                Texture::Constant(rgb * if y > 0.0 { luminance / y } else { 0.0 })
+            }
+            LightColor::Kelvin(temperature) => {
+                Texture::Blackbody(Blackbody::new(temperature, luminance))
+            }
+        };
+        Material::new(BSDF::Diffuse, Spectrum::black(), Spectrum::black()).with_emittance(emittance)
+    }
+
+    /// Translucent material like skin, wax or marble for closed meshes: a smooth dielectric
+    /// surface over a medium that light random walks through before coming back out with
+    /// roughly `albedo`. `mean_free_path` is in scene units, and larger values let light
+    /// spread further under the surface.
+    pub fn subsurface(albedo: Spectrum, mean_free_path: Spectrum, ior: f32) -> Material {
+        Material::new(
+            BSDF::Dielectric {
+                ior: Ior::Constant(ior),
+            },
+            Spectrum::white(),
+            Spectrum::black(),
+        )
+        .with_interior(HomogeneousMedium::from_albedo(albedo, mean_free_path, 0.0).into())
+    }
+
+    pub fn with_specular(mut self, specular: Texture) -> Material {
+        self.specular = specular;
+        self
+    }
+
+    pub fn with_emittance(mut self, emittance: Texture) -> Material {
+        self.emittance = emittance;
+        self
+    }
+
+    pub fn with_roughness(mut self, roughness: Texture) -> Material {
+        self.roughness = Some(roughness);
+        self
+    }
+
+    pub fn with_normal_map(mut self, normal_map: Texture) -> Material {
+        self.normal_map = Some(normal_map);
+        self
+    }
+
+    pub fn with_bump_map(mut self, bump_map: Texture, bump_scale: f32) -> Material {
+        self.bump_map = Some(bump_map);
+        self.bump_scale = bump_scale;
+        self
+    }
+
+    pub fn with_opacity(mut self, opacity: Texture) -> Material {
+        self.opacity = Some(opacity);
+        self
+    }
+
+    pub fn with_opacity_cutoff(mut self, cutoff: f32) -> Material {
+        self.opacity_cutoff = Some(cutoff);
+        self
+    }
+
+    /// Shapes the emission with a photometric profile whose nadir points towards `direction`.
+    pub fn with_emission_profile(
+        mut self,
+        profile: Arc<IesProfile>,
+        direction: Vector,
+    ) -> Material {
+        self.emission_profile = Some((profile, Frame::from_normal(direction.normalized())));
+        self
+    }
+
+    /// Makes the back of the surface black, so only its front can be seen and lit.
+    pub fn one_sided(mut self) -> Material {
+        self.two_sided = false;
+        self
+    }
+
+    /// Only emits light from the front of the surface, like a panel light.
+    pub fn with_one_sided_emission(mut self) -> Material {
+        self.two_sided_emission = false;
+        self
+    }
+
+    pub fn with_interior(mut self, medium: Medium) -> Material {
+        self.interior = Some(Arc::new(medium));
+        self
+    }
 }
 
 impl Sphere {
@@ -220,6 +861,52 @@ impl Sphere {
         }
     }
 
+    pub fn area(&self) -> f32 {
+        4.0 * PI * self.radius * self.radius
+    }
+
+    pub fn set_material(&mut self, material: Material) {
+        self.material = material;
+    }
+
+    /// Nearest hit in front of the ray. The discriminant comes from how close the ray passes
+    /// to the center and the roots from the stable form of the quadratic formula (Haines et
+    /// al., Ray Tracing Gems chapter 7), so grazing rays and small spheres far from the ray
+    /// origin keep their precision.
+    #[inline(always)]
+    fn intersect(&self, ray: &Ray) -> Option<f32> {
+        let f = ray.origin - self.center;
+        // distance along the ray to where it passes closest to the center
+        let b = -f.dot(ray.direction);
+        let closest = f + ray.direction * b;
+        let radius2 = self.radius * self.radius;
+        let discriminant = radius2 - closest.dot(closest);
+        if discriminant < 0.0 {
+            return None;
+        }
+        let c = f.dot(f) - radius2;
+        let q = b + f32::copysign(f32::sqrt(discriminant), b);
+        if q == 0.0 {
+            return None;
+        }
+        let (t0, t1) = (c / q, q);
+        let (t0, t1) = if t0 < t1 { (t0, t1) } else { (t1, t0) };
+        if t0 > 0.0 {
+            Some(t0)
+        } else if t1 > 0.0 {
+            Some(t1)
+        } else {
+            None
+        }
+    }
+
+    /// Whether `point` is inside the sphere.
+    #[inline(always)]
+    pub fn contains(&self, point: Point) -> bool {
+        let f = point - self.center;
+        f.dot(f) < self.radius * self.radius
+    }
+
     fn random_point(&self) -> Point {
         let random_vector = Vector::random_sphere();
         self.center + (random_vector * self.radius)
@@ -228,6 +915,28 @@ impl Sphere {
     fn surface_normal(&self, point: Point) -> Vector {
         (point - self.center).normalized()
     }
+
+    fn uv_derivatives(&self, point: Point) -> (Vector, Vector) {
+        let p = point - self.center;
+        let (x, y, z) = (p.x(), p.y(), p.z());
+        let rho = f32::sqrt(x * x + z * z);
+        let dpdu = Vector::new(-z, 0.0, x) * (2.0 * PI);
+        let dpdv = if rho > EPS {
+            Vector::new(-y * x / rho, rho, -y * z / rho) * PI
+        } else {
+            // at the poles any direction on the tangent plane will do
+            Vector::new(self.radius * PI, 0.0, 0.0)
+        };
+        (dpdu, dpdv)
+    }
+
+    /// Spherical mapping: u goes around the y axis, v from the bottom pole to the top one.
+    fn uv(&self, point: Point) -> (f32, f32) {
+        let p = (point - self.center) * (1.0 / self.radius);
+        let u = 0.5 + f32::atan2(p.z(), p.x()) / (2.0 * PI);
+        let v = 0.5 + f32::asin(p.y().clamp(-1.0, 1.0)) / PI;
+        (u, v)
+    }
 }
 
 struct BarycentricCoordinates {
@@ -237,40 +946,126 @@ struct BarycentricCoordinates {
 }
 
 impl Triangle {
-    pub fn new(
-        p1: Point,
-        p2: Point,
-        p3: Point,
-        vn1: Vector,
-        vn2: Vector,
-        vn3: Vector,
-        material: Material,
-    ) -> Triangle {
-        let (vn1, vn2, vn3) = (vn1.normalized(), vn2.normalized(), vn3.normalized());
-        let plane_normal_not_normalized = (p2 - p1).cross(p3 - p1);
-        Triangle {
-            p1,
-            p2,
-            p3,
-            vn1,
-            vn2,
-            vn3,
-            plane_normal_not_normalized,
-            material,
+    /// Every face of `mesh`.
+    pub fn from_mesh(mesh: &Arc<TriangleMesh>) -> impl Iterator<Item = Triangle> + '_ {
+        (0..mesh.triangle_count() as u32).map(move |face| Triangle {
+            mesh: mesh.clone(),
+            face,
             node_index: 0,
+        })
+    }
+
+    #[inline(always)]
+    fn indices(&self) -> [usize; 3] {
+        self.mesh.faces[self.face as usize].map(|i| i as usize)
+    }
+
+    #[inline(always)]
+    fn vertices(&self) -> [Point; 3] {
+        self.indices().map(|i| self.mesh.positions[i])
+    }
+
+    /// Watertight ray-triangle intersection (Woop, Benthin and Wald 2013). The vertices are
+    /// moved into a space where the ray starts at the origin and runs along +z, so triangles
+    /// sharing an edge compute exactly the same edge function and no ray slips between them.
+    /// Hits closer than the rounding error of the distance are rejected, as in PBRT.
+    #[inline(always)]
+    fn intersect(&self, ray: &Ray) -> Option<f32> {
+        let d = [ray.direction.x(), ray.direction.y(), ray.direction.z()];
+        // the largest component of the direction becomes z
+        let kz = if d[0].abs() > d[1].abs() {
+            if d[0].abs() > d[2].abs() {
+                0
+            } else {
+                2
+            }
+        } else if d[1].abs() > d[2].abs() {
+            1
+        } else {
+            2
+        };
+        let (kx, ky) = ((kz + 1) % 3, (kz + 2) % 3);
+        let (sx, sy, sz) = (-d[kx] / d[kz], -d[ky] / d[kz], 1.0 / d[kz]);
+        let [p0, p1, p2] = self.vertices().map(|p| {
+            let v = p - ray.origin;
+            let v = [v.x(), v.y(), v.z()];
+            [v[kx] + sx * v[kz], v[ky] + sy * v[kz], v[kz] * sz]
+        });
+
+        let edge = |a: [f32; 3], b: [f32; 3]| {
+            let e = a[0] * b[1] - a[1] * b[0];
+            if e == 0.0 {
+                // right on the edge in single precision, let double precision decide
+                (a[0] as f64 * b[1] as f64 - a[1] as f64 * b[0] as f64) as f32
+            } else {
+                e
+            }
+        };
+        let (e0, e1, e2) = (edge(p1, p2), edge(p2, p0), edge(p0, p1));
+        if (e0 < 0.0 || e1 < 0.0 || e2 < 0.0) && (e0 > 0.0 || e1 > 0.0 || e2 > 0.0) {
+            return None;
+        }
+        let det = e0 + e1 + e2;
+        if det == 0.0 {
+            return None;
+        }
+        let t_scaled = e0 * p0[2] + e1 * p1[2] + e2 * p2[2];
+        if (det < 0.0 && t_scaled >= 0.0) || (det > 0.0 && t_scaled <= 0.0) {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+        let t = t_scaled * inv_det;
+
+        // bound the rounding error on t
+        let gamma = |n: f32| {
+            let e = n * f32::EPSILON * 0.5;
+            e / (1.0 - e)
+        };
+        let max_abs = |i: usize| p0[i].abs().max(p1[i].abs()).max(p2[i].abs());
+        let (max_x, max_y, max_z) = (max_abs(0), max_abs(1), max_abs(2));
+        let delta_z = gamma(3.0) * max_z;
+        let delta_x = gamma(5.0) * (max_x + max_z);
+        let delta_y = gamma(5.0) * (max_y + max_z);
+        let delta_e = 2.0 * (gamma(2.0) * max_x * max_y + delta_y * max_x + delta_x * max_y);
+        let max_e = e0.abs().max(e1.abs()).max(e2.abs());
+        let delta_t =
+            3.0 * (gamma(3.0) * max_e * max_z + delta_e * max_z + delta_z * max_e) * inv_det.abs();
+        if t > delta_t {
+            Some(t)
+        } else {
+            None
         }
     }
 
-    pub fn new_without_vn(p1: Point, p2: Point, p3: Point, material: Material) -> Triangle {
-        let normal = (p2 - p1).cross(p3 - p1);
-        Triangle::new(p1, p2, p3, normal, normal, normal, material)
+    #[inline(always)]
+    fn plane_normal_not_normalized(&self) -> Vector {
+        let [p1, p2, p3] = self.vertices();
+        (p2 - p1).cross(p3 - p1)
+    }
+
+    #[inline(always)]
+    fn material(&self) -> &Material {
+        let id = self.mesh.material_ids[self.face as usize];
+        &self.mesh.materials[id as usize]
+    }
+
+    /// Texture coordinates of the vertices. Meshes without them map each face onto the
+    /// lower left half of the unit square.
+    #[inline(always)]
+    fn uvs(&self) -> [(f32, f32); 3] {
+        if self.mesh.uvs.is_empty() {
+            [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)]
+        } else {
+            self.indices().map(|i| self.mesh.uvs[i])
+        }
     }
 
     #[inline(always)]
     fn barycentric_coordinates(&self, p: Point) -> BarycentricCoordinates {
-        let v0 = self.p2 - self.p1;
-        let v1 = self.p3 - self.p1;
-        let v2 = p - self.p1;
+        let [p1, p2, p3] = self.vertices();
+        let v0 = p2 - p1;
+        let v1 = p3 - p1;
+        let v2 = p - p1;
         let d00 = v0.dot(v0);
         let d01 = v0.dot(v1);
         let d11 = v1.dot(v1);
@@ -284,9 +1079,76 @@ impl Triangle {
         BarycentricCoordinates { u, v, w }
     }
 
+    /// Interpolated vertex normal, or the face normal where the mesh has none.
     fn surface_normal(&self, point: Point) -> Vector {
+        let normals = &self.mesh.normals;
+        if !normals.is_empty() {
+            let [i1, i2, i3] = self.indices();
+            let b = self.barycentric_coordinates(point);
+            let normal = normals[i1] * b.u + normals[i2] * b.v + normals[i3] * b.w;
+            if normal.norm() > EPS {
+                return normal;
+            }
+        }
+        self.plane_normal_not_normalized().normalized()
+    }
+
+    fn uv(&self, point: Point) -> (f32, f32) {
+        let b = self.barycentric_coordinates(point);
+        let [uv1, uv2, uv3] = self.uvs();
+        (
+            uv1.0 * b.u + uv2.0 * b.v + uv3.0 * b.w,
+            uv1.1 * b.u + uv2.1 * b.v + uv3.1 * b.w,
+        )
+    }
+
+    /// Interpolated vertex colour, white where the mesh has none.
+    fn color(&self, point: Point) -> Spectrum {
+        let colors = &self.mesh.colors;
+        if colors.is_empty() {
+            return Spectrum::white();
+        }
+        let [i1, i2, i3] = self.indices();
         let b = self.barycentric_coordinates(point);
-        self.vn1 * b.u + self.vn2 * b.v + self.vn3 * b.w
+        colors[i1] * b.u + colors[i2] * b.v + colors[i3] * b.w
+    }
+
+    fn uv_derivatives(&self) -> (Vector, Vector) {
+        let [uv1, uv2, uv3] = self.uvs();
+        let [p1, p2, p3] = self.vertices();
+        let (du1, dv1) = (uv2.0 - uv1.0, uv2.1 - uv1.1);
+        let (du2, dv2) = (uv3.0 - uv1.0, uv3.1 - uv1.1);
+        let (e1, e2) = (p2 - p1, p3 - p1);
+        let determinant = du1 * dv2 - dv1 * du2;
+        if f32::abs(determinant) < 1e-12 {
+            // degenerate uvs, any frame on the plane will do
+            let frame = Frame::from_normal(self.plane_normal_not_normalized().normalized());
+            return (frame.tangent, frame.bitangent);
+        }
+        let inv = 1.0 / determinant;
+        ((e1 * dv2 - e2 * dv1) * inv, (e2 * du1 - e1 * du2) * inv)
+    }
+
+    pub fn area(&self) -> f32 {
+        0.5 * self.plane_normal_not_normalized().norm()
+    }
+
+    /// A point picked uniformly over the triangle, and its geometric normal.
+    fn sample_point(&self) -> (Point, Vector) {
+        let [p1, p2, p3] = self.vertices();
+        let su = f32::sqrt(fastrand::f32());
+        let (b1, b2) = (1.0 - su, fastrand::f32() * su);
+        let point = p1 + (p2 - p1) * b1 + (p3 - p1) * b2;
+        (point, self.plane_normal_not_normalized().normalized())
+    }
+
+    fn uv_scale(&self) -> f32 {
+        let [uv1, uv2, uv3] = self.uvs();
+        let (du1, dv1) = (uv2.0 - uv1.0, uv2.1 - uv1.1);
+        let (du2, dv2) = (uv3.0 - uv1.0, uv3.1 - uv1.1);
+        let uv_area = f32::abs(du1 * dv2 - du2 * dv1);
+        let world_area = self.plane_normal_not_normalized().norm();
+        f32::sqrt(uv_area / world_area)
     }
 }
 
@@ -301,12 +1163,13 @@ impl Bounded for Sphere {
 
 impl Bounded for Triangle {
     fn aabb(&self) -> AABB {
-        let min_x = f32::min(f32::min(self.p1.x(), self.p2.x()), self.p3.x());
-        let min_y = f32::min(f32::min(self.p1.y(), self.p2.y()), self.p3.y());
-        let min_z = f32::min(f32::min(self.p1.z(), self.p2.z()), self.p3.z());
-        let max_x = f32::max(f32::max(self.p1.x(), self.p2.x()), self.p3.x());
-        let max_y = f32::max(f32::max(self.p1.y(), self.p2.y()), self.p3.y());
-        let max_z = f32::max(f32::max(self.p1.z(), self.p2.z()), self.p3.z());
+        let [p1, p2, p3] = self.vertices();
+        let min_x = f32::min(f32::min(p1.x(), p2.x()), p3.x());
+        let min_y = f32::min(f32::min(p1.y(), p2.y()), p3.y());
+        let min_z = f32::min(f32::min(p1.z(), p2.z()), p3.z());
+        let max_x = f32::max(f32::max(p1.x(), p2.x()), p3.x());
+        let max_y = f32::max(f32::max(p1.y(), p2.y()), p3.y());
+        let max_z = f32::max(f32::max(p1.z(), p2.z()), p3.z());
 
         let min = Point::new(min_x, min_y, min_z);
         let max = Point::new(max_x, max_y, max_z);
@@ -322,23 +1185,246 @@ fn point_to_bvh_point(p: Point) -> bvh::nalgebra::Point3<f32> {
 
 #[cfg(test)]
 mod tests {
+    use super::super::procedural::{ColorRamp, Pattern, ProceduralTexture, TextureSpace};
     use super::*;
 
     #[test]
     fn debug_triangle_surface_normal() {
         let material = Material::new(BSDF::Specular, Spectrum::white(), Spectrum::black());
 
-        let triangle = Triangle::new(
-            Point::new(-5.0, -5.0, -20.0),
-            Point::new(5.0, -5.0, -20.0),
-            Point::new(5.0, 5.0, -20.0),
-            Vector::new_normalized(-0.4, 0.0, 1.0),
-            Vector::new_normalized(0.4, 0.0, 1.0),
-            Vector::new_normalized(0.0, 0.0, 1.0),
-            material,
+        let mesh = Arc::new(
+            TriangleMesh::new(
+                vec![
+                    Point::new(-5.0, -5.0, -20.0),
+                    Point::new(5.0, -5.0, -20.0),
+                    Point::new(5.0, 5.0, -20.0),
+                ],
+                vec![[0, 1, 2]],
+                material,
+            )
+            .with_normals(vec![
+                Vector::new_normalized(-0.4, 0.0, 1.0),
+                Vector::new_normalized(0.4, 0.0, 1.0),
+                Vector::new_normalized(0.0, 0.0, 1.0),
+            ]),
         );
+        let triangle = Triangle::from_mesh(&mesh).next().unwrap();
 
         let ipoint = Point::new(4.173316, 3.258237, -20.0);
         triangle.surface_normal(ipoint);
     }
+
+    #[test]
+    fn rays_along_shared_edges_hit() {
+        let material = Material::new(BSDF::Diffuse, Spectrum::grey(), Spectrum::black());
+        // a slanted quad split along its diagonal
+        let mesh = Arc::new(TriangleMesh::new(
+            vec![
+                Point::new(-3.1, -2.7, -10.3),
+                Point::new(4.3, -2.2, -9.1),
+                Point::new(3.7, 5.9, -11.7),
+                Point::new(-2.9, 4.1, -10.9),
+            ],
+            vec![[0, 1, 2], [0, 2, 3]],
+            material,
+        ));
+        let triangles: Vec<Triangle> = Triangle::from_mesh(&mesh).collect();
+        let origin = Point::new(0.13, 0.29, 1.7);
+        let [start, end] = [mesh.positions[0], mesh.positions[2]];
+        for i in 1..1000 {
+            let target = start + (end - start) * (i as f32 / 1000.0);
+            let ray = Ray::new(origin, target - origin);
+            let hits = triangles.iter().filter(|t| t.intersect(&ray).is_some());
+            assert!(hits.count() >= 1, "ray {} slipped through the edge", i);
+        }
+    }
+
+    #[test]
+    fn sphere_hits_are_in_front_of_the_ray() {
+        let material = Material::new(BSDF::Diffuse, Spectrum::grey(), Spectrum::black());
+        let sphere = Sphere::new(Point::new(0.0, 0.0, -10.0), 2.0, material);
+        let forward = Vector::new(0.0, 0.0, -1.0);
+
+        let outside = Ray::new(Point::origin(), forward);
+        assert!((sphere.intersect(&outside).unwrap() - 8.0).abs() < 1e-5);
+        assert!(!sphere.contains(outside.origin));
+        // from inside only the far side is ahead
+        let inside = Ray::new(Point::new(0.0, 0.0, -9.0), forward);
+        assert!((sphere.intersect(&inside).unwrap() - 3.0).abs() < 1e-5);
+        assert!(sphere.contains(inside.origin));
+        let behind = Ray::new(Point::new(0.0, 0.0, -13.0), forward);
+        assert!(sphere.intersect(&behind).is_none());
+
+        // a small sphere far away, grazed
+        let far = Sphere::new(Point::new(0.0, 0.0, -1e4), 1e-2, sphere.material.clone());
+        let grazing = Ray::new(Point::new(0.0, 0.0099, 0.0), forward);
+        assert!((far.intersect(&grazing).unwrap() - 1e4).abs() < 1.0);
+    }
+
+    #[test]
+    fn one_sided_emission() {
+        let material = Material::new(BSDF::Diffuse, Spectrum::black(), Spectrum::white());
+        let panel = |material: Material| {
+            let mesh = Arc::new(TriangleMesh::new(
+                vec![
+                    Point::new(0.0, 0.0, 0.0),
+                    Point::new(1.0, 0.0, 0.0),
+                    Point::new(0.0, 1.0, 0.0),
+                ],
+                vec![[0, 1, 2]],
+                material,
+            ));
+            let triangle = Triangle::from_mesh(&mesh).next().unwrap();
+            Object::Triangle(triangle)
+        };
+        let (point, wavelengths) = (Point::new(0.25, 0.25, 0.0), Wavelengths::rgb());
+        let (front, back) = (Vector::new(0.0, 0.0, 1.0), Vector::new(0.0, 0.0, -1.0));
+
+        let two_sided = panel(material.clone());
+        assert!(!two_sided.emittance(point, back, &wavelengths).is_black());
+        let one_sided = panel(material.with_one_sided_emission());
+        assert!(!one_sided.emittance(point, front, &wavelengths).is_black());
+        assert!(one_sided.emittance(point, back, &wavelengths).is_black());
+    }
+
+    #[test]
+    fn oren_nayar_without_roughness_is_lambertian() {
+        let normal = Vector::new(0.0, 1.0, 0.0);
+        let wi = Vector::new_normalized(0.3, 1.0, -0.2);
+        let wo = Vector::new_normalized(-0.6, 0.4, 0.1);
+        assert!((oren_nayar(wi, wo, normal, 0.0) - 1.0).abs() < 1e-6);
+        assert!(oren_nayar(wi, wo, normal, 0.5) < 1.0);
+    }
+
+    #[test]
+    fn dispersion_splits_off_the_hero_once_per_path() {
+        let flint = Material::new(
+            BSDF::Dielectric { ior: Ior::flint() },
+            Spectrum::white(),
+            Spectrum::black(),
+        );
+        let interaction = |normal: Vector, entering: bool, wavelengths: Wavelengths| {
+            let point = Point::origin();
+            SurfaceInteraction {
+                point,
+                geometric_normal: normal,
+                frame: Frame::from_normal_tangent(normal, Vector::new(1.0, 0.0, 0.0)),
+                ctx: TextureContext {
+                    uv: (0.0, 0.0),
+                    uv_width: 0.0,
+                    point,
+                    object_point: point,
+                    vertex_color: Spectrum::white(),
+                    wavelengths,
+                },
+                entering,
+            }
+        };
+
+        // straight down through a slab, in through its top and out through its bottom
+        let wo = Vector::new(0.0, 0.0, -1.0);
+        let mut wavelengths = Wavelengths::sample();
+        let mut weight = Spectrum::white();
+        for &(normal, entering) in [(-wo, true), (wo, false)].iter() {
+            let si = interaction(normal, entering, wavelengths);
+            let sample = loop {
+                let sample = flint.sample_bsdf(wo, &si);
+                if sample.transmitted {
+                    break sample;
+                }
+            };
+            assert!(sample.dispersed);
+            weight = weight * sample.reflected * f32::abs(sample.wi.dot(normal));
+            wavelengths = wavelengths.without_secondary();
+        }
+        // white only comes out nearly flat from the spectral upsampling
+        let [hero, second, third] = weight.rgb();
+        assert!((hero - 3.0).abs() < 1e-2, "hero weighted {}", hero);
+        assert_eq!([second, third], [0.0, 0.0]);
+    }
+
+    /// A unit right triangle in the z = 0 plane whose uvs follow x and y.
+    fn uv_triangle(material: Material) -> (Arc<TriangleMesh>, TextureContext) {
+        let mesh = Arc::new(
+            TriangleMesh::new(
+                vec![
+                    Point::new(0.0, 0.0, 0.0),
+                    Point::new(1.0, 0.0, 0.0),
+                    Point::new(0.0, 1.0, 0.0),
+                ],
+                vec![[0, 1, 2]],
+                material,
+            )
+            .with_uvs(vec![(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)]),
+        );
+        let point = Point::new(0.25, 0.25, 0.0);
+        let ctx = TextureContext {
+            uv: (0.25, 0.25),
+            uv_width: 0.0,
+            point,
+            object_point: point,
+            vertex_color: Spectrum::white(),
+            wavelengths: Wavelengths::rgb(),
+        };
+        (mesh, ctx)
+    }
+
+    fn close(a: Vector, b: Vector) -> bool {
+        (a - b).norm() < 1e-3
+    }
+
+    #[test]
+    fn bump_maps_tilt_the_normal_along_their_slope() {
+        let up = Vector::new(0.0, 0.0, 1.0);
+        let grey = Material::new(BSDF::Diffuse, Spectrum::grey(), Spectrum::black());
+        let shading_normal = |material: Material| {
+            let (mesh, ctx) = uv_triangle(material);
+            let triangle = Object::Triangle(Triangle::from_mesh(&mesh).next().unwrap());
+            triangle
+                .shading_frame(triangle.material(), ctx.point, &ctx)
+                .normal
+        };
+
+        let flat = grey
+            .clone()
+            .with_bump_map(Texture::Constant(Spectrum::grey()), 1.0);
+        assert!(close(shading_normal(flat), up));
+
+        // the height rises by one per unit of u, so the surface leans back towards -u
+        let slope = ProceduralTexture::new(
+            Pattern::Gradient {
+                axis: Vector::new(1.0, 0.0, 0.0),
+            },
+            TextureSpace::Uv,
+            1.0,
+            ColorRamp::two_colors(Spectrum::black(), Spectrum::white()),
+        );
+        let sloped = grey.with_bump_map(Texture::Procedural(Arc::new(slope)), 1.0);
+        assert!(close(
+            shading_normal(sloped),
+            Vector::new_normalized(-1.0, 0.0, 1.0)
+        ));
+    }
+
+    #[test]
+    fn normal_maps_are_in_the_tangent_frame() {
+        let grey = Material::new(BSDF::Diffuse, Spectrum::grey(), Spectrum::black());
+        let shading_frame = |texel: Spectrum| {
+            let material = grey.clone().with_normal_map(Texture::Constant(texel));
+            let (mesh, ctx) = uv_triangle(material);
+            let triangle = Object::Triangle(Triangle::from_mesh(&mesh).next().unwrap());
+            triangle.shading_frame(triangle.material(), ctx.point, &ctx)
+        };
+
+        let straight = shading_frame(Spectrum::new_f(0.5, 0.5, 1.0));
+        assert!(close(straight.normal, Vector::new(0.0, 0.0, 1.0)));
+        assert!(close(straight.tangent, Vector::new(1.0, 0.0, 0.0)));
+        assert!(close(straight.bitangent, Vector::new(0.0, 1.0, 0.0)));
+
+        // red leans the normal towards increasing u, green towards increasing v
+        let along_u = shading_frame(Spectrum::new_f(1.0, 0.5, 1.0));
+        assert!(close(along_u.normal, Vector::new_normalized(1.0, 0.0, 1.0)));
+        let along_v = shading_frame(Spectrum::new_f(0.5, 1.0, 1.0));
+        assert!(close(along_v.normal, Vector::new_normalized(0.0, 1.0, 1.0)));
+    }
 }
@@ -0,0 +1,453 @@
+use std::fs;
+
+use super::objects::Material;
+use super::texture::srgb_to_linear;
+use super::triangle_mesh::TriangleMesh;
+use super::{parse_error, LoadError, Point, Vector};
+use crate::common::Spectrum;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ScalarType {
+    Int8,
+    UInt8,
+    Int16,
+    UInt16,
+    Int32,
+    UInt32,
+    Float32,
+    Float64,
+}
+
+impl ScalarType {
+    fn from_name(name: &str) -> Result<ScalarType, LoadError> {
+        Ok(match name {
+            "char" | "int8" => ScalarType::Int8,
+            "uchar" | "uint8" => ScalarType::UInt8,
+            "short" | "int16" => ScalarType::Int16,
+            "ushort" | "uint16" => ScalarType::UInt16,
+            "int" | "int32" => ScalarType::Int32,
+            "uint" | "uint32" => ScalarType::UInt32,
+            "float" | "float32" => ScalarType::Float32,
+            "double" | "float64" => ScalarType::Float64,
+            _ => return parse_error(format!("unknown property type '{}'", name)),
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            ScalarType::Int8 | ScalarType::UInt8 => 1,
+            ScalarType::Int16 | ScalarType::UInt16 => 2,
+            ScalarType::Int32 | ScalarType::UInt32 | ScalarType::Float32 => 4,
+            ScalarType::Float64 => 8,
+        }
+    }
+
+    /// What a colour channel of this type is divided by to bring it into [0, 1].
+    fn color_range(self) -> f64 {
+        match self {
+            ScalarType::Int8 => 127.0,
+            ScalarType::UInt8 => 255.0,
+            ScalarType::Int16 => 32767.0,
+            ScalarType::UInt16 => 65535.0,
+            ScalarType::Int32 => 2147483647.0,
+            ScalarType::UInt32 => 4294967295.0,
+            ScalarType::Float32 | ScalarType::Float64 => 1.0,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Property {
+    Scalar(ScalarType, String),
+    /// A count of type `.0` followed by that many items of type `.1`.
+    List(ScalarType, ScalarType, String),
+}
+
+impl Property {
+    fn name(&self) -> &str {
+        match self {
+            Property::Scalar(_, name) | Property::List(_, _, name) => name,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+impl Element {
+    fn find(&self, names: &[&str]) -> Option<usize> {
+        self.properties
+            .iter()
+            .position(|property| names.contains(&property.name()))
+    }
+
+    fn scalar_type(&self, index: usize) -> ScalarType {
+        match &self.properties[index] {
+            Property::Scalar(ty, _) | Property::List(_, ty, _) => *ty,
+        }
+    }
+}
+
+/// Reads the values following the header one at a time, whatever the encoding.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    format: Format,
+}
+
+impl<'a> Reader<'a> {
+    fn read(&mut self, ty: ScalarType) -> Result<f64, LoadError> {
+        match self.format {
+            Format::Ascii => self.read_ascii(),
+            Format::BinaryLittleEndian | Format::BinaryBigEndian => self.read_binary(ty),
+        }
+    }
+
+    fn read_ascii(&mut self) -> Result<f64, LoadError> {
+        let rest = &self.bytes[self.position..];
+        let start = rest
+            .iter()
+            .position(|b| !b.is_ascii_whitespace())
+            .ok_or_else(|| LoadError::Parse("unexpected end of file".to_string()))?;
+        let length = rest[start..]
+            .iter()
+            .position(|b| b.is_ascii_whitespace())
+            .unwrap_or(rest.len() - start);
+        self.position += start + length;
+        let token = String::from_utf8_lossy(&rest[start..start + length]);
+        token
+            .parse()
+            .map_err(|_| LoadError::Parse(format!("'{}' is not a number", token)))
+    }
+
+    fn read_binary(&mut self, ty: ScalarType) -> Result<f64, LoadError> {
+        let size = ty.size();
+        if self.position + size > self.bytes.len() {
+            return parse_error("unexpected end of file".to_string());
+        }
+        // bring everything to little endian, then decode
+        let mut b = [0u8; 8];
+        b[..size].copy_from_slice(&self.bytes[self.position..self.position + size]);
+        if self.format == Format::BinaryBigEndian {
+            b[..size].reverse();
+        }
+        self.position += size;
+        Ok(match ty {
+            ScalarType::Int8 => b[0] as i8 as f64,
+            ScalarType::UInt8 => b[0] as f64,
+            ScalarType::Int16 => i16::from_le_bytes([b[0], b[1]]) as f64,
+            ScalarType::UInt16 => u16::from_le_bytes([b[0], b[1]]) as f64,
+            ScalarType::Int32 => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            ScalarType::UInt32 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            ScalarType::Float32 => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            ScalarType::Float64 => f64::from_le_bytes(b),
+        })
+    }
+
+    /// One row of `element`: a value per scalar property, and the items of list properties.
+    fn read_row(&mut self, element: &Element) -> Result<Vec<Vec<f64>>, LoadError> {
+        element
+            .properties
+            .iter()
+            .map(|property| match property {
+                Property::Scalar(ty, _) => Ok(vec![self.read(*ty)?]),
+                Property::List(count_type, item_type, _) => {
+                    let count = self.read(*count_type)?;
+                    if count < 0.0 {
+                        return parse_error(format!("negative list length {}", count));
+                    }
+                    (0..count as usize).map(|_| self.read(*item_type)).collect()
+                }
+            })
+            .collect()
+    }
+}
+
+fn parse_header(header: &str) -> Result<(Format, Vec<Element>), LoadError> {
+    let mut lines = header.lines().map(str::trim);
+    if lines.next() != Some("ply") {
+        return parse_error("missing 'ply' magic".to_string());
+    }
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    for line in lines {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["format", name, _version] => {
+                format = Some(match *name {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::BinaryLittleEndian,
+                    "binary_big_endian" => Format::BinaryBigEndian,
+                    _ => return parse_error(format!("unknown format '{}'", name)),
+                })
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|_| LoadError::Parse(format!("bad element count '{}'", count)))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count_type, item_type, name] => {
+                let property = Property::List(
+                    ScalarType::from_name(count_type)?,
+                    ScalarType::from_name(item_type)?,
+                    name.to_string(),
+                );
+                match elements.last_mut() {
+                    Some(element) => element.properties.push(property),
+                    None => return parse_error("property before any element".to_string()),
+                }
+            }
+            ["property", ty, name] => {
+                let property = Property::Scalar(ScalarType::from_name(ty)?, name.to_string());
+                match elements.last_mut() {
+                    Some(element) => element.properties.push(property),
+                    None => return parse_error("property before any element".to_string()),
+                }
+            }
+            ["comment", ..] | ["obj_info", ..] | [] => {}
+            _ => return parse_error(format!("unexpected header line '{}'", line)),
+        }
+    }
+    let format = format.ok_or_else(|| LoadError::Parse("missing format".to_string()))?;
+    Ok((format, elements))
+}
+
+pub fn open(filename: &str, material: Material) -> Result<TriangleMesh, LoadError> {
+    parse(&fs::read(filename)?, material)
+}
+
+/// Reads a PLY file in any of its three encodings. Vertices need x, y and z, and may have
+/// normals (nx, ny, nz), uvs (u and v, s and t, or texture_u and texture_v) and sRGB colours
+/// (red, green, blue). Polygon faces are split into triangle fans, and any other elements or
+/// properties are skipped.
+pub fn parse(bytes: &[u8], material: Material) -> Result<TriangleMesh, LoadError> {
+    const END_HEADER: &[u8] = b"end_header";
+    let end = bytes
+        .windows(END_HEADER.len())
+        .position(|window| window == END_HEADER)
+        .ok_or_else(|| LoadError::Parse("missing end_header".to_string()))?;
+    // the body starts on the line after end_header
+    let body = end
+        + END_HEADER.len()
+        + bytes[end + END_HEADER.len()..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(0, |newline| newline + 1);
+    let (format, elements) = parse_header(&String::from_utf8_lossy(&bytes[..end]))?;
+
+    let mut reader = Reader {
+        bytes,
+        position: body,
+        format,
+    };
+    let mut positions = Vec::new();
+    let mut normals = Vec::new();
+    let mut uvs = Vec::new();
+    let mut colors = Vec::new();
+    let mut faces = Vec::new();
+    for element in &elements {
+        match element.name.as_str() {
+            "vertex" => {
+                let position = match (
+                    element.find(&["x"]),
+                    element.find(&["y"]),
+                    element.find(&["z"]),
+                ) {
+                    (Some(x), Some(y), Some(z)) => [x, y, z],
+                    _ => return parse_error("vertices without x, y and z".to_string()),
+                };
+                let normal = element
+                    .find(&["nx"])
+                    .zip(element.find(&["ny"]))
+                    .zip(element.find(&["nz"]));
+                let uv = element
+                    .find(&["u", "s", "texture_u", "texture_s"])
+                    .zip(element.find(&["v", "t", "texture_v", "texture_t"]));
+                let color = element
+                    .find(&["red"])
+                    .zip(element.find(&["green"]))
+                    .zip(element.find(&["blue"]));
+                for _ in 0..element.count {
+                    let row = reader.read_row(element)?;
+                    let value = |index: usize| row[index].first().copied().unwrap_or(0.0);
+                    let [x, y, z] = position.map(|i| value(i) as f32);
+                    positions.push(Point::new(x, y, z));
+                    if let Some(((nx, ny), nz)) = normal {
+                        normals.push(Vector::new(
+                            value(nx) as f32,
+                            value(ny) as f32,
+                            value(nz) as f32,
+                        ));
+                    }
+                    if let Some((u, v)) = uv {
+                        uvs.push((value(u) as f32, value(v) as f32));
+                    }
+                    if let Some(((r, g), b)) = color {
+                        let channel = |index: usize| {
+                            let range = element.scalar_type(index).color_range();
+                            srgb_to_linear((value(index) / range).clamp(0.0, 1.0) as f32)
+                        };
+                        colors.push(Spectrum::new_f(channel(r), channel(g), channel(b)));
+                    }
+                }
+            }
+            "face" => {
+                let indices = element
+                    .find(&["vertex_indices", "vertex_index"])
+                    .ok_or_else(|| LoadError::Parse("faces without vertex_indices".to_string()))?;
+                for _ in 0..element.count {
+                    let row = reader.read_row(element)?;
+                    // casting would quietly turn a negative index into vertex 0
+                    let polygon = row[indices]
+                        .iter()
+                        .map(|&i| {
+                            if i >= 0.0 && i.fract() == 0.0 && i <= u32::MAX as f64 {
+                                Ok(i as u32)
+                            } else {
+                                parse_error(format!("invalid vertex index {}", i))
+                            }
+                        })
+                        .collect::<Result<Vec<u32>, LoadError>>()?;
+                    for i in 2..polygon.len() {
+                        faces.push([polygon[0], polygon[i - 1], polygon[i]]);
+                    }
+                }
+            }
+            _ => {
+                for _ in 0..element.count {
+                    reader.read_row(element)?;
+                }
+            }
+        }
+    }
+
+    if let Some(index) = faces
+        .iter()
+        .flatten()
+        .find(|&&i| i as usize >= positions.len())
+    {
+        return parse_error(format!(
+            "vertex index {} out of range for {} vertices",
+            index,
+            positions.len()
+        ));
+    }
+    let mut mesh = TriangleMesh::new(positions, faces, material);
+    if !normals.is_empty() {
+        mesh = mesh.with_normals(normals);
+    }
+    if !uvs.is_empty() {
+        mesh = mesh.with_uvs(uvs);
+    }
+    if !colors.is_empty() {
+        mesh = mesh.with_colors(colors);
+    }
+    Ok(mesh)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::objects::BSDF;
+    use super::*;
+
+    fn material() -> Material {
+        Material::new(BSDF::Diffuse, Spectrum::grey(), Spectrum::black())
+    }
+
+    #[test]
+    fn ascii_and_binary_files_give_the_same_mesh() {
+        let ascii = b"ply\nformat ascii 1.0\ncomment a unit quad\n\
+            element vertex 4\nproperty float x\nproperty float y\nproperty float z\n\
+            property uchar red\nproperty uchar green\nproperty uchar blue\n\
+            element face 1\nproperty list uchar int vertex_indices\nend_header\n\
+            0 0 0 255 0 0\n1 0 0 0 255 0\n1 1 0 0 0 255\n0 1 0 255 255 255\n4 0 1 2 3\n";
+        let binary = |format: &str, big_endian: bool| {
+            let mut bytes = format!(
+                "ply\nformat {} 1.0\nelement vertex 4\nproperty float x\nproperty float y\n\
+                property float z\nproperty uchar red\nproperty uchar green\n\
+                property uchar blue\nelement face 1\nproperty list uchar int vertex_indices\n\
+                end_header\n",
+                format
+            )
+            .into_bytes();
+            let vertices = [
+                ([0.0f32, 0.0, 0.0], [255u8, 0, 0]),
+                ([1.0, 0.0, 0.0], [0, 255, 0]),
+                ([1.0, 1.0, 0.0], [0, 0, 255]),
+                ([0.0, 1.0, 0.0], [255, 255, 255]),
+            ];
+            for (position, color) in vertices.iter() {
+                for c in position.iter() {
+                    let c = if big_endian {
+                        c.to_be_bytes()
+                    } else {
+                        c.to_le_bytes()
+                    };
+                    bytes.extend_from_slice(&c);
+                }
+                bytes.extend_from_slice(color);
+            }
+            bytes.push(4);
+            for i in 0..4i32 {
+                let i = if big_endian {
+                    i.to_be_bytes()
+                } else {
+                    i.to_le_bytes()
+                };
+                bytes.extend_from_slice(&i);
+            }
+            bytes
+        };
+
+        let meshes = [
+            parse(ascii, material()).unwrap(),
+            parse(&binary("binary_little_endian", false), material()).unwrap(),
+            parse(&binary("binary_big_endian", true), material()).unwrap(),
+        ];
+        for mesh in meshes.iter() {
+            assert_eq!(mesh.faces, vec![[0, 1, 2], [0, 2, 3]]);
+            assert_eq!(mesh.positions.len(), 4);
+            assert_eq!(mesh.positions[2].x(), 1.0);
+            assert_eq!(mesh.positions[2].y(), 1.0);
+            assert_eq!(mesh.colors[1].rgb(), [0.0, 1.0, 0.0]);
+            assert!(mesh.normals.is_empty() && mesh.uvs.is_empty());
+        }
+        assert!(parse(b"ply\nformat ascii 1.0\nend_header\n", material())
+            .unwrap()
+            .is_empty());
+        assert!(parse(b"not a ply file", material()).is_err());
+    }
+
+    #[test]
+    fn vertex_indices_must_be_in_range() {
+        let triangle = |indices: &str| {
+            let ply = format!(
+                "ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\n\
+                property float y\nproperty float z\nelement face 1\n\
+                property list uchar int vertex_indices\nend_header\n\
+                0 0 0\n1 0 0\n0 1 0\n3 {}\n",
+                indices
+            );
+            parse(ply.as_bytes(), material())
+        };
+        assert_eq!(triangle("0 1 2").unwrap().faces, vec![[0, 1, 2]]);
+        assert!(triangle("0 1 3").is_err());
+        match triangle("-1 1 2") {
+            Err(LoadError::Parse(message)) => assert_eq!(message, "invalid vertex index -1"),
+            _ => panic!("expected a parse error"),
+        }
+    }
+}
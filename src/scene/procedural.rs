@@ -0,0 +1,328 @@
+use super::texture::TextureContext;
+use super::{Point, Vector};
+use crate::common::Spectrum;
+
+/// Which coordinates a procedural pattern is evaluated in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextureSpace {
+    /// (u, v, 0)
+    Uv,
+    /// Relative to the object the texture is on, so the pattern sticks to it.
+    Object,
+    World,
+}
+
+/// Scalar patterns in [0, 1], turned into colours by a `ColorRamp`.
+#[derive(Clone, Copy, Debug)]
+pub enum Pattern {
+    Checker,
+    /// Fractal Brownian motion over Perlin noise.
+    Noise {
+        octaves: u32,
+    },
+    /// Like `Noise`, but sums absolute values for a billowy look.
+    Turbulence {
+        octaves: u32,
+    },
+    /// Sine stripes along x distorted by turbulence.
+    Marble {
+        stripes: f32,
+        distortion: f32,
+    },
+    /// Concentric rings around the y axis, distorted by noise.
+    Wood {
+        rings: f32,
+        distortion: f32,
+    },
+    /// Distance to the closest point of a jittered grid (Worley's F1).
+    Voronoi,
+    /// Linear ramp along an axis, repeating every unit.
+    Gradient {
+        axis: Vector,
+    },
+}
+
+/// Piecewise-linear map from [0, 1] to colours.
+#[derive(Clone, Debug)]
+pub struct ColorRamp {
+    /// Sorted by position.
+    stops: Vec<(f32, Spectrum)>,
+}
+
+impl ColorRamp {
+    pub fn new(mut stops: Vec<(f32, Spectrum)>) -> ColorRamp {
+        assert!(!stops.is_empty(), "a color ramp needs at least one stop");
+        stops.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        ColorRamp { stops }
+    }
+
+    pub fn two_colors(a: Spectrum, b: Spectrum) -> ColorRamp {
+        ColorRamp::new(vec![(0.0, a), (1.0, b)])
+    }
+
+    pub fn evaluate(&self, t: f32) -> Spectrum {
+        let first = self.stops[0];
+        if t <= first.0 {
+            return first.1;
+        }
+        for window in self.stops.windows(2) {
+            let ((t0, c0), (t1, c1)) = (window[0], window[1]);
+            if t <= t1 {
+                let frac = if t1 > t0 { (t - t0) / (t1 - t0) } else { 1.0 };
+                return c0 * (1.0 - frac) + c1 * frac;
+            }
+        }
+        self.stops.last().unwrap().1
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ProceduralTexture {
+    pattern: Pattern,
+    space: TextureSpace,
+    /// Pattern frequency: coordinates are multiplied by this before evaluation.
+    scale: f32,
+    ramp: ColorRamp,
+}
+
+impl ProceduralTexture {
+    pub fn new(pattern: Pattern, space: TextureSpace, scale: f32, ramp: ColorRamp) -> Self {
+        ProceduralTexture {
+            pattern,
+            space,
+            scale,
+            ramp,
+        }
+    }
+
+    pub fn evaluate(&self, ctx: &TextureContext) -> Spectrum {
+        let p = match self.space {
+            TextureSpace::Uv => Vector::new(ctx.uv.0, ctx.uv.1, 0.0),
+            TextureSpace::Object => ctx.object_point - Point::origin(),
+            TextureSpace::World => ctx.point - Point::origin(),
+        } * self.scale;
+        self.ramp.evaluate(self.pattern.evaluate(p).clamp(0.0, 1.0))
+    }
+}
+
+impl Pattern {
+    fn evaluate(&self, p: Vector) -> f32 {
+        match *self {
+            Pattern::Checker => {
+                let sum = p.x().floor() + p.y().floor() + p.z().floor();
+                if sum.rem_euclid(2.0) < 1.0 {
+                    0.0
+                } else {
+                    1.0
+                }
+            }
+            Pattern::Noise { octaves } => 0.5 + 0.5 * fbm(p, octaves),
+            Pattern::Turbulence { octaves } => turbulence(p, octaves),
+            Pattern::Marble {
+                stripes,
+                distortion,
+            } => {
+                let phase = p.x() * stripes + distortion * turbulence(p, 6);
+                0.5 + 0.5 * f32::sin(phase)
+            }
+            Pattern::Wood { rings, distortion } => {
+                let radius = f32::sqrt(p.x() * p.x() + p.z() * p.z());
+                let r = radius * rings + distortion * perlin(p);
+                r - r.floor()
+            }
+            Pattern::Voronoi => voronoi(p),
+            Pattern::Gradient { axis } => {
+                let t = p.dot(axis);
+                t - t.floor()
+            }
+        }
+    }
+}
+
+/// Sum of `octaves` layers of noise, each twice the frequency and half the amplitude of the
+/// last. Roughly in [-1, 1].
+pub fn fbm(p: Vector, octaves: u32) -> f32 {
+    let (mut sum, mut amplitude, mut frequency, mut total) = (0.0, 1.0, 1.0, 0.0);
+    for _ in 0..octaves {
+        sum += amplitude * perlin(p * frequency);
+        total += amplitude;
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+    if total > 0.0 {
+        sum / total
+    } else {
+        0.0
+    }
+}
+
+/// Like `fbm` but over absolute values, in [0, 1].
+pub fn turbulence(p: Vector, octaves: u32) -> f32 {
+    let (mut sum, mut amplitude, mut frequency, mut total) = (0.0, 1.0, 1.0, 0.0);
+    for _ in 0..octaves {
+        sum += amplitude * f32::abs(perlin(p * frequency));
+        total += amplitude;
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+    if total > 0.0 {
+        sum / total
+    } else {
+        0.0
+    }
+}
+
+/// Improved Perlin noise, roughly in [-1, 1] and 0 on every lattice point.
+pub fn perlin(p: Vector) -> f32 {
+    let (x, y, z) = (p.x().floor(), p.y().floor(), p.z().floor());
+    let (fx, fy, fz) = (p.x() - x, p.y() - y, p.z() - z);
+    let (ix, iy, iz) = (x as i32, y as i32, z as i32);
+    let (u, v, w) = (fade(fx), fade(fy), fade(fz));
+
+    let corner = |dx: i32, dy: i32, dz: i32| {
+        let h = hash(ix + dx, iy + dy, iz + dz);
+        gradient(h, fx - dx as f32, fy - dy as f32, fz - dz as f32)
+    };
+    let x00 = lerp(u, corner(0, 0, 0), corner(1, 0, 0));
+    let x10 = lerp(u, corner(0, 1, 0), corner(1, 1, 0));
+    let x01 = lerp(u, corner(0, 0, 1), corner(1, 0, 1));
+    let x11 = lerp(u, corner(0, 1, 1), corner(1, 1, 1));
+    lerp(w, lerp(v, x00, x10), lerp(v, x01, x11))
+}
+
+/// Distance to the nearest feature point, one per unit cell.
+pub fn voronoi(p: Vector) -> f32 {
+    let (cx, cy, cz) = (
+        p.x().floor() as i32,
+        p.y().floor() as i32,
+        p.z().floor() as i32,
+    );
+    let mut min_distance2 = f32::INFINITY;
+    for dz in -1..=1 {
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                let (x, y, z) = (cx + dx, cy + dy, cz + dz);
+                let h = hash(x, y, z);
+                let feature = Vector::new(
+                    x as f32 + unit_float(h),
+                    y as f32 + unit_float(h.rotate_left(11)),
+                    z as f32 + unit_float(h.rotate_left(22)),
+                );
+                let d = feature - p;
+                min_distance2 = f32::min(min_distance2, d.dot(d));
+            }
+        }
+    }
+    f32::sqrt(min_distance2)
+}
+
+#[inline(always)]
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+#[inline(always)]
+fn lerp(t: f32, a: f32, b: f32) -> f32 {
+    a + t * (b - a)
+}
+
+/// Dot product with one of the 12 edge-midpoint gradients of Perlin's improved noise.
+#[inline(always)]
+fn gradient(hash: u32, x: f32, y: f32, z: f32) -> f32 {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = if h < 4 {
+        y
+    } else if h == 12 || h == 14 {
+        x
+    } else {
+        z
+    };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+/// Integer lattice hash, stands in for Perlin's permutation table.
+#[inline(always)]
+fn hash(x: i32, y: i32, z: i32) -> u32 {
+    let mut h = (x as u32).wrapping_mul(0x8da6_b343)
+        ^ (y as u32).wrapping_mul(0xd816_3841)
+        ^ (z as u32).wrapping_mul(0xcb1a_b31f);
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x7feb_352d);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x846c_a68b);
+    h ^ (h >> 16)
+}
+
+#[inline(always)]
+fn unit_float(h: u32) -> f32 {
+    (h & 0x00ff_ffff) as f32 / 16_777_216.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-5
+    }
+
+    #[test]
+    fn patterns_have_known_values() {
+        let checker = Pattern::Checker;
+        assert_eq!(checker.evaluate(Vector::new(0.5, 0.5, 0.5)), 0.0);
+        assert_eq!(checker.evaluate(Vector::new(1.5, 0.5, 0.5)), 1.0);
+        assert_eq!(checker.evaluate(Vector::new(-0.5, 0.5, 0.5)), 1.0);
+        assert_eq!(checker.evaluate(Vector::new(1.5, 1.5, 0.5)), 0.0);
+
+        // Perlin noise vanishes on the lattice, at every octave
+        let lattice = Vector::new(3.0, -2.0, 5.0);
+        assert!(close(perlin(lattice), 0.0));
+        assert!(close(Pattern::Noise { octaves: 4 }.evaluate(lattice), 0.5));
+        assert!(close(
+            Pattern::Turbulence { octaves: 4 }.evaluate(lattice),
+            0.0
+        ));
+        let marble = Pattern::Marble {
+            stripes: std::f32::consts::FRAC_PI_2,
+            distortion: 3.0,
+        };
+        assert!(close(marble.evaluate(Vector::new(1.0, 0.0, 0.0)), 1.0));
+        assert!(close(marble.evaluate(Vector::new(0.0, 0.0, 0.0)), 0.5));
+        let wood = Pattern::Wood {
+            rings: 1.5,
+            distortion: 2.0,
+        };
+        assert!(close(wood.evaluate(Vector::new(3.0, 0.0, 4.0)), 0.5));
+
+        // the feature point of a cell is at no distance from itself
+        let h = hash(0, 0, 0);
+        let feature = Vector::new(
+            unit_float(h),
+            unit_float(h.rotate_left(11)),
+            unit_float(h.rotate_left(22)),
+        );
+        assert!(close(Pattern::Voronoi.evaluate(feature), 0.0));
+        assert!(Pattern::Voronoi.evaluate(Vector::new(0.5, 0.5, 0.5)) <= f32::sqrt(3.0));
+
+        let gradient = Pattern::Gradient {
+            axis: Vector::new(1.0, 0.0, 0.0),
+        };
+        assert!(close(gradient.evaluate(Vector::new(0.25, 7.0, 0.0)), 0.25));
+        assert!(close(gradient.evaluate(Vector::new(1.75, 0.0, 0.0)), 0.75));
+        assert!(close(gradient.evaluate(Vector::new(-0.25, 0.0, 0.0)), 0.75));
+    }
+
+    #[test]
+    fn ramps_blend_between_stops() {
+        let ramp = ColorRamp::new(vec![
+            (1.0, Spectrum::white()),
+            (0.5, Spectrum::new_f(1.0, 0.0, 0.0)),
+            (0.0, Spectrum::black()),
+        ]);
+        assert_eq!(ramp.evaluate(-1.0).rgb(), [0.0, 0.0, 0.0]);
+        assert_eq!(ramp.evaluate(0.25).rgb(), [0.5, 0.0, 0.0]);
+        assert_eq!(ramp.evaluate(0.75).rgb(), [1.0, 0.5, 0.5]);
+        assert_eq!(ramp.evaluate(2.0).rgb(), [1.0, 1.0, 1.0]);
+    }
+}
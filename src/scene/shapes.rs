@@ -0,0 +1,568 @@
+use bvh::aabb::{Bounded, AABB};
+use std::f32::consts::PI;
+
+use super::objects::Material;
+use super::{Frame, Point, Ray, Vector};
+use crate::common::EPS;
+
+/// How far an infinite plane reaches as far as the BVH is concerned.
+const PLANE_EXTENT: f32 = 1e4;
+
+#[derive(Clone, Copy, Debug)]
+enum ShapeKind {
+    /// Rectangle on the local xy plane, centered on the origin.
+    Quad {
+        half_width: f32,
+        half_height: f32,
+    },
+    /// The whole local xy plane.
+    Plane,
+    /// Circle on the local xy plane, centered on the origin.
+    Disk {
+        radius: f32,
+    },
+    Box {
+        half_size: Vector,
+    },
+    /// Open tube around the local z axis, from z = 0 to `height`.
+    Cylinder {
+        radius: f32,
+        height: f32,
+    },
+    /// Open cone around the local z axis, with its base at z = 0 and its apex at `height`.
+    Cone {
+        radius: f32,
+        height: f32,
+    },
+}
+
+/// An analytic primitive, defined in a local frame around `center`. The front of flat shapes
+/// is their local +z side, the other shapes face out.
+pub struct Shape {
+    kind: ShapeKind,
+    center: Point,
+    frame: Frame,
+    pub(super) material: Material,
+    pub(super) node_index: usize,
+}
+
+impl Shape {
+    fn new(kind: ShapeKind, center: Point, frame: Frame, material: Material) -> Shape {
+        Shape {
+            kind,
+            center,
+            frame,
+            material,
+            node_index: 0,
+        }
+    }
+
+    /// Rectangle spanned by `half_u` and `half_v` on either side of `center`. The edges must be
+    /// perpendicular, and the front faces `half_u` × `half_v`.
+    pub fn quad(center: Point, half_u: Vector, half_v: Vector, material: Material) -> Shape {
+        let normal = half_u.cross(half_v).normalized();
+        Shape::new(
+            ShapeKind::Quad {
+                half_width: half_u.norm(),
+                half_height: half_v.norm(),
+            },
+            center,
+            Frame::from_normal_tangent(normal, half_u),
+            material,
+        )
+    }
+
+    /// Infinite plane through `point`, facing `normal`.
+    pub fn plane(point: Point, normal: Vector, material: Material) -> Shape {
+        Shape::new(
+            ShapeKind::Plane,
+            point,
+            Frame::from_normal(normal.normalized()),
+            material,
+        )
+    }
+
+    pub fn disk(center: Point, normal: Vector, radius: f32, material: Material) -> Shape {
+        Shape::new(
+            ShapeKind::Disk { radius },
+            center,
+            Frame::from_normal(normal.normalized()),
+            material,
+        )
+    }
+
+    pub fn axis_aligned_box(min: Point, max: Point, material: Material) -> Shape {
+        let half_size = (max - min) * 0.5;
+        Shape::new(
+            ShapeKind::Box { half_size },
+            min + half_size,
+            Frame::from_normal_tangent(Vector::new(0.0, 0.0, 1.0), Vector::new(1.0, 0.0, 0.0)),
+            material,
+        )
+    }
+
+    /// Box whose edges run along the tangent, bitangent and normal of `frame`.
+    pub fn oriented_box(
+        center: Point,
+        frame: Frame,
+        half_size: Vector,
+        material: Material,
+    ) -> Shape {
+        Shape::new(ShapeKind::Box { half_size }, center, frame, material)
+    }
+
+    /// Open tube from `base` to `base + axis`.
+    pub fn cylinder(base: Point, axis: Vector, radius: f32, material: Material) -> Shape {
+        Shape::new(
+            ShapeKind::Cylinder {
+                radius,
+                height: axis.norm(),
+            },
+            base,
+            Frame::from_normal(axis.normalized()),
+            material,
+        )
+    }
+
+    /// Open cone with its base around `base` and its apex at `base + axis`.
+    pub fn cone(base: Point, axis: Vector, radius: f32, material: Material) -> Shape {
+        Shape::new(
+            ShapeKind::Cone {
+                radius,
+                height: axis.norm(),
+            },
+            base,
+            Frame::from_normal(axis.normalized()),
+            material,
+        )
+    }
+
+    pub fn set_material(&mut self, material: Material) {
+        self.material = material;
+    }
+
+    #[inline(always)]
+    fn to_local(&self, point: Point) -> Vector {
+        self.frame.to_local(point - self.center)
+    }
+
+    #[inline(always)]
+    fn to_world(&self, local: Vector) -> Point {
+        self.center + self.frame.to_world(local)
+    }
+
+    /// Position of `point` in the shape's own frame, for solid textures.
+    pub fn object_point(&self, point: Point) -> Point {
+        Point::origin() + self.to_local(point)
+    }
+
+    #[inline(always)]
+    pub fn intersect(&self, ray: &Ray) -> Option<f32> {
+        let o = self.frame.to_local(ray.origin - self.center);
+        let d = self.frame.to_local(ray.direction);
+        let on_plane = || {
+            if f32::abs(d.z()) < 1e-12 {
+                return None;
+            }
+            let t = -o.z() / d.z();
+            if t < EPS {
+                None
+            } else {
+                Some((t, o.x() + d.x() * t, o.y() + d.y() * t))
+            }
+        };
+
+        match self.kind {
+            ShapeKind::Quad {
+                half_width,
+                half_height,
+            } => on_plane()
+                .filter(|&(_, x, y)| f32::abs(x) <= half_width && f32::abs(y) <= half_height)
+                .map(|(t, _, _)| t),
+            ShapeKind::Plane => on_plane().map(|(t, _, _)| t),
+            ShapeKind::Disk { radius } => on_plane()
+                .filter(|&(_, x, y)| x * x + y * y <= radius * radius)
+                .map(|(t, _, _)| t),
+            ShapeKind::Box { half_size } => {
+                let (mut near, mut far) = (f32::NEG_INFINITY, f32::INFINITY);
+                let axes = [
+                    (o.x(), d.x(), half_size.x()),
+                    (o.y(), d.y(), half_size.y()),
+                    (o.z(), d.z(), half_size.z()),
+                ];
+                for &(o, d, half) in axes.iter() {
+                    let inv = 1.0 / d;
+                    let (t0, t1) = ((-half - o) * inv, (half - o) * inv);
+                    near = near.max(t0.min(t1));
+                    far = far.min(t0.max(t1));
+                }
+                if near > far {
+                    None
+                } else if near >= EPS {
+                    Some(near)
+                } else if far >= EPS {
+                    Some(far)
+                } else {
+                    None
+                }
+            }
+            ShapeKind::Cylinder { radius, height } => {
+                let a = d.x() * d.x() + d.y() * d.y();
+                let b = 2.0 * (o.x() * d.x() + o.y() * d.y());
+                let c = o.x() * o.x() + o.y() * o.y() - radius * radius;
+                nearest_root(a, b, c, |t| {
+                    let z = o.z() + d.z() * t;
+                    z >= 0.0 && z <= height
+                })
+            }
+            ShapeKind::Cone { radius, height } => {
+                let k2 = (radius / height) * (radius / height);
+                let h = height - o.z();
+                let a = d.x() * d.x() + d.y() * d.y() - k2 * d.z() * d.z();
+                let b = 2.0 * (o.x() * d.x() + o.y() * d.y() + k2 * h * d.z());
+                let c = o.x() * o.x() + o.y() * o.y() - k2 * h * h;
+                nearest_root(a, b, c, |t| {
+                    let z = o.z() + d.z() * t;
+                    z >= 0.0 && z <= height
+                })
+            }
+        }
+    }
+
+    /// Unnormalized outward normal at a point given in the local frame.
+    fn local_normal(&self, p: Vector) -> Vector {
+        match self.kind {
+            ShapeKind::Quad { .. } | ShapeKind::Plane | ShapeKind::Disk { .. } => {
+                Vector::new(0.0, 0.0, 1.0)
+            }
+            ShapeKind::Box { half_size } => {
+                let (axis, sign) = box_face(p, half_size);
+                let mut normal = [0.0; 3];
+                normal[axis] = sign;
+                Vector::new(normal[0], normal[1], normal[2])
+            }
+            ShapeKind::Cylinder { .. } => Vector::new(p.x(), p.y(), 0.0),
+            ShapeKind::Cone { radius, height } => {
+                let r = f32::sqrt(p.x() * p.x() + p.y() * p.y());
+                Vector::new(p.x(), p.y(), r * radius / height)
+            }
+        }
+    }
+
+    pub fn normal(&self, point: Point) -> Vector {
+        self.frame
+            .to_world(self.local_normal(self.to_local(point)))
+            .normalized()
+    }
+
+    /// Quads and box faces map to the unit square, planes use local coordinates, disks and
+    /// the sides of cylinders and cones go around in u.
+    pub fn uv(&self, point: Point) -> (f32, f32) {
+        let p = self.to_local(point);
+        let around = || 0.5 + f32::atan2(p.y(), p.x()) / (2.0 * PI);
+        match self.kind {
+            ShapeKind::Quad {
+                half_width,
+                half_height,
+            } => (
+                0.5 + 0.5 * p.x() / half_width,
+                0.5 + 0.5 * p.y() / half_height,
+            ),
+            ShapeKind::Plane => (p.x(), p.y()),
+            ShapeKind::Disk { radius } => {
+                (around(), f32::sqrt(p.x() * p.x() + p.y() * p.y()) / radius)
+            }
+            ShapeKind::Box { half_size } => {
+                let (axis, _) = box_face(p, half_size);
+                let (p, half) = (component_array(p), component_array(half_size));
+                let (i, j) = ((axis + 1) % 3, (axis + 2) % 3);
+                (0.5 + 0.5 * p[i] / half[i], 0.5 + 0.5 * p[j] / half[j])
+            }
+            ShapeKind::Cylinder { height, .. } | ShapeKind::Cone { height, .. } => {
+                (around(), p.z() / height)
+            }
+        }
+    }
+
+    /// Partial derivatives of the surface position with respect to u and v.
+    pub fn uv_derivatives(&self, point: Point) -> (Vector, Vector) {
+        let p = self.to_local(point);
+        let r = f32::sqrt(p.x() * p.x() + p.y() * p.y());
+        let around = Vector::new(-p.y(), p.x(), 0.0) * (2.0 * PI);
+        let (x, y) = (Vector::new(1.0, 0.0, 0.0), Vector::new(0.0, 1.0, 0.0));
+        let (dpdu, dpdv) = match self.kind {
+            ShapeKind::Quad {
+                half_width,
+                half_height,
+            } => (x * (2.0 * half_width), y * (2.0 * half_height)),
+            ShapeKind::Plane => (x, y),
+            ShapeKind::Disk { radius } if r > EPS => {
+                (around, Vector::new(p.x(), p.y(), 0.0) * (radius / r))
+            }
+            ShapeKind::Box { half_size } => {
+                let (axis, _) = box_face(p, half_size);
+                let half = component_array(half_size);
+                let unit = |i: usize| {
+                    let mut v = [0.0; 3];
+                    v[i] = 2.0 * half[i];
+                    Vector::new(v[0], v[1], v[2])
+                };
+                (unit((axis + 1) % 3), unit((axis + 2) % 3))
+            }
+            ShapeKind::Cylinder { height, .. } => (around, Vector::new(0.0, 0.0, height)),
+            ShapeKind::Cone { radius, height } if r > EPS => (
+                around,
+                Vector::new(-p.x() * radius / r, -p.y() * radius / r, height),
+            ),
+            // the centre of a disk or the apex of a cone
+            _ => (x, y),
+        };
+        (self.frame.to_world(dpdu), self.frame.to_world(dpdv))
+    }
+
+    /// Surface area in square scene units.
+    pub fn area(&self) -> f32 {
+        match self.kind {
+            ShapeKind::Quad {
+                half_width,
+                half_height,
+            } => 4.0 * half_width * half_height,
+            ShapeKind::Plane => f32::INFINITY,
+            ShapeKind::Disk { radius } => PI * radius * radius,
+            ShapeKind::Box { half_size } => {
+                let (x, y, z) = (half_size.x(), half_size.y(), half_size.z());
+                8.0 * (x * y + y * z + z * x)
+            }
+            ShapeKind::Cylinder { radius, height } => 2.0 * PI * radius * height,
+            ShapeKind::Cone { radius, height } => {
+                PI * radius * f32::sqrt(radius * radius + height * height)
+            }
+        }
+    }
+
+    /// Roughly how many uv units one world unit covers on this surface.
+    pub fn uv_scale(&self) -> f32 {
+        match self.kind {
+            ShapeKind::Plane => 1.0,
+            // each face has its own unit square
+            ShapeKind::Box { .. } => 1.0 / f32::sqrt(self.area() / 6.0),
+            _ => 1.0 / f32::sqrt(self.area()),
+        }
+    }
+
+    /// A point picked uniformly over the surface, and the normal there. Infinite planes can't
+    /// be sampled.
+    pub fn sample_point(&self) -> Option<(Point, Vector)> {
+        let (u, v) = (fastrand::f32(), fastrand::f32());
+        let around = |r: f32, z: f32| {
+            let (sin, cos) = f32::sin_cos(2.0 * PI * v);
+            Vector::new(r * cos, r * sin, z)
+        };
+        let local = match self.kind {
+            ShapeKind::Quad {
+                half_width,
+                half_height,
+            } => Vector::new(
+                (2.0 * u - 1.0) * half_width,
+                (2.0 * v - 1.0) * half_height,
+                0.0,
+            ),
+            ShapeKind::Plane => return None,
+            ShapeKind::Disk { radius } => around(radius * f32::sqrt(u), 0.0),
+            ShapeKind::Box { half_size } => {
+                // a face with probability proportional to its area, then a point on it
+                let half = component_array(half_size);
+                let areas = [half[1] * half[2], half[2] * half[0], half[0] * half[1]];
+                let mut pick = u * (areas[0] + areas[1] + areas[2]);
+                let mut axis = 0;
+                while axis < 2 && pick >= areas[axis] {
+                    pick -= areas[axis];
+                    axis += 1;
+                }
+                let mut p = [0.0; 3];
+                p[axis] = if fastrand::bool() {
+                    half[axis]
+                } else {
+                    -half[axis]
+                };
+                p[(axis + 1) % 3] = (2.0 * v - 1.0) * half[(axis + 1) % 3];
+                p[(axis + 2) % 3] = (2.0 * fastrand::f32() - 1.0) * half[(axis + 2) % 3];
+                Vector::new(p[0], p[1], p[2])
+            }
+            ShapeKind::Cylinder { radius, height } => around(radius, fastrand::f32() * height),
+            ShapeKind::Cone { radius, height } => {
+                // the side widens linearly towards the base
+                let r = radius * f32::sqrt(u);
+                around(r, height * (1.0 - r / radius))
+            }
+        };
+        let normal = self.frame.to_world(self.local_normal(local)).normalized();
+        Some((self.to_world(local), normal))
+    }
+}
+
+/// Smallest root of at^2 + bt + c past `EPS` that `valid` accepts.
+#[inline(always)]
+fn nearest_root(a: f32, b: f32, c: f32, valid: impl Fn(f32) -> bool) -> Option<f32> {
+    if f32::abs(a) < 1e-12 {
+        return None;
+    }
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    // avoids the cancellation of -b + sqrt when they're close
+    let q = -0.5 * (b + f32::copysign(f32::sqrt(discriminant), b));
+    let (t0, t1) = (q / a, c / q);
+    let (t0, t1) = if t0 < t1 { (t0, t1) } else { (t1, t0) };
+    [t0, t1].iter().copied().find(|&t| t >= EPS && valid(t))
+}
+
+/// The axis of the box face closest to a local point, and which side of the box it's on.
+#[inline(always)]
+fn box_face(p: Vector, half_size: Vector) -> (usize, f32) {
+    let (p, half) = (component_array(p), component_array(half_size));
+    let mut axis = 0;
+    for i in 1..3 {
+        if f32::abs(p[i]) / half[i] > f32::abs(p[axis]) / half[axis] {
+            axis = i;
+        }
+    }
+    (axis, if p[axis] < 0.0 { -1.0 } else { 1.0 })
+}
+
+#[inline(always)]
+fn component_array(v: Vector) -> [f32; 3] {
+    [v.x(), v.y(), v.z()]
+}
+
+impl Bounded for Shape {
+    fn aabb(&self) -> AABB {
+        let (x, y, z0, z1) = match self.kind {
+            ShapeKind::Quad {
+                half_width,
+                half_height,
+            } => (half_width, half_height, 0.0, 0.0),
+            ShapeKind::Plane => (PLANE_EXTENT, PLANE_EXTENT, 0.0, 0.0),
+            ShapeKind::Disk { radius } => (radius, radius, 0.0, 0.0),
+            ShapeKind::Box { half_size } => {
+                (half_size.x(), half_size.y(), -half_size.z(), half_size.z())
+            }
+            ShapeKind::Cylinder { radius, height } | ShapeKind::Cone { radius, height } => {
+                (radius, radius, 0.0, height)
+            }
+        };
+        // bound the corners of the local box, wherever the frame turns them
+        let mut aabb = AABB::empty();
+        for &(cx, cy, cz) in [
+            (-x, -y, z0),
+            (x, -y, z0),
+            (-x, y, z0),
+            (x, y, z0),
+            (-x, -y, z1),
+            (x, -y, z1),
+            (-x, y, z1),
+            (x, y, z1),
+        ]
+        .iter()
+        {
+            let corner = self.to_world(Vector::new(cx, cy, cz));
+            aabb.grow_mut(&bvh::nalgebra::Point3::new(
+                corner.x(),
+                corner.y(),
+                corner.z(),
+            ));
+        }
+        aabb
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Spectrum;
+    use crate::scene::objects::BSDF;
+
+    fn material() -> Material {
+        Material::new(BSDF::Diffuse, Spectrum::grey(), Spectrum::black())
+    }
+
+    #[test]
+    fn rays_hit_the_outside_first() {
+        let ray = Ray::new(Point::new(0.0, 0.0, 10.0), Vector::new(0.0, 0.0, -1.0));
+        let shapes = [
+            Shape::axis_aligned_box(
+                Point::new(-1.0, -1.0, -1.0),
+                Point::new(1.0, 1.0, 1.0),
+                material(),
+            ),
+            Shape::cylinder(
+                Point::new(0.0, -1.0, 0.0),
+                Vector::new(0.0, 2.0, 0.0),
+                1.0,
+                material(),
+            ),
+            Shape::cone(
+                Point::new(0.0, -1.0, 0.0),
+                Vector::new(0.0, 2.0, 0.0),
+                2.0,
+                material(),
+            ),
+        ];
+        // the cone is 1 wide halfway up
+        for shape in shapes.iter() {
+            let t = shape.intersect(&ray).unwrap();
+            assert!((t - 9.0).abs() < 1e-4, "{}", t);
+            let normal = shape.normal(ray.origin + ray.direction * t);
+            assert!(normal.z() > 0.0);
+        }
+
+        // from inside, the far side
+        let inside = Ray::new(Point::new(0.0, 0.0, 0.0), Vector::new(0.0, 0.0, -1.0));
+        let t = shapes[0].intersect(&inside).unwrap();
+        assert!((t - 1.0).abs() < 1e-4, "{}", t);
+
+        let quad = Shape::quad(
+            Point::new(0.0, 0.0, 0.0),
+            Vector::new(2.0, 0.0, 0.0),
+            Vector::new(0.0, 1.0, 0.0),
+            material(),
+        );
+        assert!((quad.area() - 8.0).abs() < 1e-4);
+        assert!(quad.intersect(&ray).is_some());
+        let beside = Ray::new(Point::new(0.0, 1.5, 10.0), Vector::new(0.0, 0.0, -1.0));
+        assert!(quad.intersect(&beside).is_none());
+    }
+
+    #[test]
+    fn samples_lie_on_the_surface() {
+        let shapes = [
+            Shape::disk(
+                Point::new(1.0, 2.0, 3.0),
+                Vector::new(1.0, 1.0, 0.0),
+                2.0,
+                material(),
+            ),
+            Shape::axis_aligned_box(
+                Point::new(-1.0, -2.0, -3.0),
+                Point::new(1.0, 2.0, 3.0),
+                material(),
+            ),
+            Shape::cone(
+                Point::new(0.0, 0.0, 0.0),
+                Vector::new(0.0, 0.0, 3.0),
+                1.0,
+                material(),
+            ),
+        ];
+        for shape in shapes.iter() {
+            for _ in 0..100 {
+                let (point, normal) = shape.sample_point().unwrap();
+                // a ray coming back at the sample along the normal hits right there
+                let ray = Ray::new(point + normal * 0.5, -normal);
+                let t = shape.intersect(&ray).unwrap();
+                assert!((t - 0.5).abs() < 1e-3, "{}", t);
+            }
+        }
+    }
+}
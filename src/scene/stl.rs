@@ -0,0 +1,139 @@
+use std::fs;
+
+use super::objects::Material;
+use super::triangle_mesh::{weld, TriangleMesh};
+use super::{LoadError, Point};
+
+const HEADER_SIZE: usize = 84;
+const FACET_SIZE: usize = 50;
+
+pub fn open(filename: &str, material: Material) -> Result<TriangleMesh, LoadError> {
+    parse(&fs::read(filename)?, material)
+}
+
+/// Reads an ASCII or binary STL file. Facets only come as loose triangles, so corners at the
+/// same position are merged into shared vertices. The stored facet normals are ignored, they
+/// are often missing or wrong and the winding says the same.
+pub fn parse(bytes: &[u8], material: Material) -> Result<TriangleMesh, LoadError> {
+    let triangles = if is_binary(bytes) {
+        parse_binary(bytes)
+    } else {
+        parse_ascii(&String::from_utf8_lossy(bytes))?
+    };
+    if triangles.is_empty() {
+        return Err(LoadError::Parse("no facets".to_string()));
+    }
+
+    let corners: Vec<Point> = triangles
+        .iter()
+        .flatten()
+        .map(|corner| Point::new(corner[0], corner[1], corner[2]))
+        .collect();
+    let (positions, welded) = weld(&corners);
+    let faces = welded
+        .chunks(3)
+        .map(|face| [face[0], face[1], face[2]])
+        .collect();
+    Ok(TriangleMesh::new(positions, faces, material))
+}
+
+/// Binary files may start with "solid" too, so they are recognized by their size instead.
+fn is_binary(bytes: &[u8]) -> bool {
+    if bytes.len() < HEADER_SIZE {
+        return false;
+    }
+    let count = u32::from_le_bytes([bytes[80], bytes[81], bytes[82], bytes[83]]) as usize;
+    bytes.len() == HEADER_SIZE + count * FACET_SIZE
+}
+
+fn parse_binary(bytes: &[u8]) -> Vec<[[f32; 3]; 3]> {
+    bytes[HEADER_SIZE..]
+        .chunks_exact(FACET_SIZE)
+        .map(|facet| {
+            let float = |at: usize| {
+                f32::from_le_bytes([facet[at], facet[at + 1], facet[at + 2], facet[at + 3]])
+            };
+            // the facet normal comes first, then the corners, then an attribute word
+            [0, 1, 2].map(|corner| {
+                let at = 12 + corner * 12;
+                [float(at), float(at + 4), float(at + 8)]
+            })
+        })
+        .collect()
+}
+
+/// Reads the `vertex x y z` lines of every `outer loop`, splitting loops of more than three
+/// corners into fans.
+fn parse_ascii(text: &str) -> Result<Vec<[[f32; 3]; 3]>, LoadError> {
+    if !text.trim_start().starts_with("solid") {
+        return Err(LoadError::Parse("missing 'solid'".to_string()));
+    }
+    let mut triangles = Vec::new();
+    let mut corners: Vec<[f32; 3]> = Vec::new();
+    let mut tokens = text.split_whitespace();
+    while let Some(token) = tokens.next() {
+        match token {
+            "vertex" => {
+                let mut corner = [0.0; 3];
+                for c in &mut corner {
+                    *c = tokens
+                        .next()
+                        .and_then(|t| t.parse().ok())
+                        .ok_or_else(|| LoadError::Parse("bad vertex".to_string()))?;
+                }
+                corners.push(corner);
+            }
+            "endloop" => {
+                for i in 2..corners.len() {
+                    triangles.push([corners[0], corners[i - 1], corners[i]]);
+                }
+                corners.clear();
+            }
+            _ => {}
+        }
+    }
+    Ok(triangles)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::objects::BSDF;
+    use super::*;
+    use crate::common::Spectrum;
+
+    #[test]
+    fn ascii_and_binary_facets_share_their_corners() {
+        let material = || Material::new(BSDF::Diffuse, Spectrum::grey(), Spectrum::black());
+        let ascii = "solid part\n\
+            facet normal 0 0 1\n outer loop\n  vertex 0 0 0\n  vertex 1 0 0\n  vertex 1 1 0\n endloop\nendfacet\n\
+            facet normal 0 0 1\n outer loop\n  vertex -0 0 0\n  vertex 1 1 0\n  vertex 0 1 0\n endloop\nendfacet\n\
+            endsolid part\n";
+        // binary files may start with "solid" as well
+        let mut binary = b"solid but binary".to_vec();
+        binary.resize(80, 0);
+        binary.extend_from_slice(&2u32.to_le_bytes());
+        let facets = [
+            [
+                0.0f32, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 1.0, 0.0,
+            ],
+            [0.0, 0.0, 1.0, -0.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 1.0, 0.0],
+        ];
+        for facet in facets.iter() {
+            for value in facet.iter() {
+                binary.extend_from_slice(&value.to_le_bytes());
+            }
+            binary.extend_from_slice(&[0, 0]);
+        }
+
+        for mesh in [
+            parse(ascii.as_bytes(), material()).unwrap(),
+            parse(&binary, material()).unwrap(),
+        ]
+        .iter()
+        {
+            assert_eq!(mesh.positions.len(), 4);
+            assert_eq!(mesh.faces, vec![[0, 1, 2], [0, 2, 3]]);
+        }
+        assert!(parse(b"not an stl file", material()).is_err());
+    }
+}
@@ -0,0 +1,606 @@
+use std::collections::{HashMap, HashSet};
+use std::f32::consts::PI;
+
+use super::objects::Material;
+use super::triangle_mesh::{weld, TriangleMesh};
+use super::{Point, Vector};
+use crate::common::Spectrum;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scheme {
+    /// Splits every triangle into four. Other polygons are split into triangles first.
+    Loop,
+    /// Splits every polygon into quads, one per corner. Best suited to quad meshes.
+    CatmullClark,
+}
+
+/// How to refine a mesh as it's loaded.
+#[derive(Clone, Copy, Debug)]
+pub struct Subdivision {
+    pub scheme: Scheme,
+    /// Each level roughly quadruples the number of faces.
+    pub levels: u32,
+    /// Edges between faces meeting at more than this many radians are kept sharp instead of
+    /// being rounded off. Open edges are always sharp.
+    pub crease_angle: Option<f32>,
+}
+
+impl Subdivision {
+    pub fn new(scheme: Scheme, levels: u32) -> Subdivision {
+        Subdivision {
+            scheme,
+            levels,
+            crease_angle: None,
+        }
+    }
+
+    pub fn with_crease_angle(mut self, crease_angle: f32) -> Subdivision {
+        self.crease_angle = Some(crease_angle);
+        self
+    }
+
+    /// Refines `mesh` and splits the result into triangles. The normals are dropped, as the
+    /// surface changes shape.
+    pub fn apply(&self, mesh: PolygonMesh) -> TriangleMesh {
+        let mut mesh = mesh.welded();
+        if let Some(angle) = self.crease_angle {
+            mesh.mark_creases(angle);
+        }
+        if self.scheme == Scheme::Loop {
+            mesh = mesh.triangulated();
+        }
+        for _ in 0..self.levels {
+            mesh = mesh.subdivided(self.scheme);
+        }
+        mesh.into_triangles()
+    }
+}
+
+/// Faces with any number of corners, for subdividing before they're split into triangles.
+///
+/// Corners refer to a position and, separately, to the uv and colour they have in that face,
+/// so faces can share a position while disagreeing on its uv, as along texture seams.
+#[derive(Clone)]
+pub struct PolygonMesh {
+    positions: Vec<Point>,
+    /// Index into `positions` of each corner.
+    faces: Vec<Vec<u32>>,
+    /// Index into the uvs and colours of each corner.
+    corners: Vec<Vec<u32>>,
+    attribute_count: usize,
+    /// Either none or one per attribute.
+    uvs: Vec<(f32, f32)>,
+    /// Either none or one per attribute.
+    colors: Vec<Spectrum>,
+    /// Edges kept sharp, by their positions with the smaller index first.
+    creases: HashSet<(u32, u32)>,
+    material_ids: Vec<u32>,
+    materials: Vec<Material>,
+}
+
+impl PolygonMesh {
+    /// Polygons indexing into `vertices`, each made of `material`. Anything with less than
+    /// three corners is dropped.
+    pub fn new(vertices: Vec<Point>, polygons: Vec<Vec<u32>>, material: Material) -> PolygonMesh {
+        let polygons: Vec<Vec<u32>> = polygons.into_iter().filter(|p| p.len() >= 3).collect();
+        PolygonMesh {
+            attribute_count: vertices.len(),
+            positions: vertices,
+            corners: polygons.clone(),
+            material_ids: vec![0; polygons.len()],
+            faces: polygons,
+            uvs: Vec::new(),
+            colors: Vec::new(),
+            creases: HashSet::new(),
+            materials: vec![material],
+        }
+    }
+
+    /// One uv per vertex given to `new`.
+    pub fn with_uvs(mut self, uvs: Vec<(f32, f32)>) -> PolygonMesh {
+        assert_eq!(uvs.len(), self.attribute_count);
+        self.uvs = uvs;
+        self
+    }
+
+    /// One colour per vertex given to `new`.
+    pub fn with_colors(mut self, colors: Vec<Spectrum>) -> PolygonMesh {
+        assert_eq!(colors.len(), self.attribute_count);
+        self.colors = colors;
+        self
+    }
+
+    pub fn face_count(&self) -> usize {
+        self.faces.len()
+    }
+
+    /// Adds the faces of `other`, with their materials. Missing uvs are zero and missing
+    /// colours white, as for `TriangleMesh::append`.
+    pub fn append(&mut self, mut other: PolygonMesh) {
+        if self.uvs.is_empty() != other.uvs.is_empty() {
+            self.uvs.resize(self.attribute_count, (0.0, 0.0));
+            other.uvs.resize(other.attribute_count, (0.0, 0.0));
+        }
+        if self.colors.is_empty() != other.colors.is_empty() {
+            self.colors.resize(self.attribute_count, Spectrum::white());
+            other
+                .colors
+                .resize(other.attribute_count, Spectrum::white());
+        }
+
+        let first_position = self.positions.len() as u32;
+        let first_attribute = self.attribute_count as u32;
+        let first_material = self.materials.len() as u32;
+        self.positions.append(&mut other.positions);
+        self.faces.extend(
+            other
+                .faces
+                .iter()
+                .map(|face| face.iter().map(|i| i + first_position).collect()),
+        );
+        self.corners.extend(
+            other
+                .corners
+                .iter()
+                .map(|corners| corners.iter().map(|i| i + first_attribute).collect()),
+        );
+        self.attribute_count += other.attribute_count;
+        self.uvs.append(&mut other.uvs);
+        self.colors.append(&mut other.colors);
+        self.creases.extend(
+            other
+                .creases
+                .iter()
+                .map(|(a, b)| (a + first_position, b + first_position)),
+        );
+        self.material_ids
+            .extend(other.material_ids.iter().map(|id| id + first_material));
+        self.materials.append(&mut other.materials);
+    }
+
+    /// Splits the polygons into fans of triangles, without subdividing them.
+    pub fn into_triangles(self) -> TriangleMesh {
+        // one vertex per position and attribute used together
+        let mut vertices: HashMap<(u32, u32), u32> = HashMap::new();
+        let mut used = Vec::new();
+        let mut faces = Vec::new();
+        let mut material_ids = Vec::new();
+        for ((face, corners), &material_id) in
+            self.faces.iter().zip(&self.corners).zip(&self.material_ids)
+        {
+            let indices: Vec<u32> = face
+                .iter()
+                .zip(corners)
+                .map(|(&p, &a)| {
+                    *vertices.entry((p, a)).or_insert_with(|| {
+                        used.push((p, a));
+                        used.len() as u32 - 1
+                    })
+                })
+                .collect();
+            for i in 2..indices.len() {
+                faces.push([indices[0], indices[i - 1], indices[i]]);
+                material_ids.push(material_id);
+            }
+        }
+
+        let mut mesh = TriangleMesh::empty();
+        mesh.positions = used
+            .iter()
+            .map(|&(p, _)| self.positions[p as usize])
+            .collect();
+        if !self.uvs.is_empty() {
+            mesh.uvs = used.iter().map(|&(_, a)| self.uvs[a as usize]).collect();
+        }
+        if !self.colors.is_empty() {
+            mesh.colors = used.iter().map(|&(_, a)| self.colors[a as usize]).collect();
+        }
+        mesh.faces = faces;
+        mesh.material_ids = material_ids;
+        mesh.materials = self.materials;
+        mesh
+    }
+
+    /// Merges corners at the same position, so that faces on either side of a seam become
+    /// neighbours.
+    fn welded(mut self) -> PolygonMesh {
+        let (positions, welded) = weld(&self.positions);
+        for face in &mut self.faces {
+            face.iter_mut().for_each(|i| *i = welded[*i as usize]);
+        }
+        self.creases = self
+            .creases
+            .iter()
+            .map(|&(a, b)| edge_key(welded[a as usize], welded[b as usize]))
+            .collect();
+        self.positions = positions;
+        self
+    }
+
+    /// Marks the edges between faces meeting at more than `crease_angle` radians as creases.
+    fn mark_creases(&mut self, crease_angle: f32) {
+        let normals: Vec<Vector> = self
+            .faces
+            .iter()
+            .map(|face| {
+                let p0 = self.positions[face[0] as usize];
+                let normal = (2..face.len()).fold(Vector::new(0.0, 0.0, 0.0), |sum, i| {
+                    let (p1, p2) = (
+                        self.positions[face[i - 1] as usize],
+                        self.positions[face[i] as usize],
+                    );
+                    sum + (p1 - p0).cross(p2 - p0)
+                });
+                match normal.norm() {
+                    length if length > 0.0 => normal * (1.0 / length),
+                    _ => normal,
+                }
+            })
+            .collect();
+        let cos_crease = f32::cos(crease_angle);
+        let edges = Edges::new(&self.faces, self.positions.len());
+        for (&edge, faces) in edges.vertices.iter().zip(&edges.faces) {
+            if let [f, g] = faces[..] {
+                if normals[f].dot(normals[g]) < cos_crease {
+                    self.creases.insert(edge);
+                }
+            }
+        }
+    }
+
+    /// Splits the faces into fans of triangles, keeping the positions as they are.
+    fn triangulated(mut self) -> PolygonMesh {
+        let mut faces = Vec::new();
+        let mut corners = Vec::new();
+        let mut material_ids = Vec::new();
+        for ((face, corner), &material_id) in
+            self.faces.iter().zip(&self.corners).zip(&self.material_ids)
+        {
+            for i in 2..face.len() {
+                faces.push(vec![face[0], face[i - 1], face[i]]);
+                corners.push(vec![corner[0], corner[i - 1], corner[i]]);
+                material_ids.push(material_id);
+            }
+        }
+        self.faces = faces;
+        self.corners = corners;
+        self.material_ids = material_ids;
+        self
+    }
+
+    /// One level of subdivision. New positions are numbered after the old ones: first one per
+    /// edge, then for Catmull-Clark one per face.
+    fn subdivided(&self, scheme: Scheme) -> PolygonMesh {
+        let edges = Edges::new(&self.faces, self.positions.len());
+        let sharp: Vec<bool> = edges
+            .vertices
+            .iter()
+            .zip(&edges.faces)
+            .map(|(edge, faces)| faces.len() != 2 || self.creases.contains(edge))
+            .collect();
+        let position = |i: u32| self.positions[i as usize] - Point::origin();
+        let face_points: Vec<Vector> = self
+            .faces
+            .iter()
+            .map(|face| {
+                face.iter()
+                    .fold(Vector::new(0.0, 0.0, 0.0), |sum, &i| sum + position(i))
+                    * (1.0 / face.len() as f32)
+            })
+            .collect();
+
+        let edge_points: Vec<Vector> = edges
+            .vertices
+            .iter()
+            .zip(&edges.faces)
+            .zip(&sharp)
+            .map(|((&(a, b), faces), &sharp)| {
+                let ends = position(a) + position(b);
+                if sharp {
+                    ends * 0.5
+                } else if scheme == Scheme::CatmullClark {
+                    (ends + face_points[faces[0]] + face_points[faces[1]]) * 0.25
+                } else {
+                    // the corners across from the edge in the two triangles
+                    let across = faces.iter().fold(Vector::new(0.0, 0.0, 0.0), |sum, &f| {
+                        let opposite = self.faces[f].iter().find(|&&i| i != a && i != b);
+                        sum + opposite.map_or(Vector::new(0.0, 0.0, 0.0), |&i| position(i))
+                    });
+                    ends * 0.375 + across * 0.125
+                }
+            })
+            .collect();
+
+        let vertex_points: Vec<Vector> = (0..self.positions.len())
+            .map(|v| {
+                let v = v as u32;
+                let around = &edges.around[v as usize];
+                let sharp_ends: Vec<u32> = around
+                    .iter()
+                    .filter(|&&e| sharp[e])
+                    .map(|&e| edges.other_end(e, v))
+                    .collect();
+                match sharp_ends.len() {
+                    2 => {
+                        // along a crease only the crease itself has a say
+                        (position(v) * 6.0 + position(sharp_ends[0]) + position(sharp_ends[1]))
+                            * 0.125
+                    }
+                    count if count > 2 || around.is_empty() => position(v),
+                    _ => {
+                        let n = around.len() as f32;
+                        let neighbours =
+                            around.iter().fold(Vector::new(0.0, 0.0, 0.0), |sum, &e| {
+                                sum + position(edges.other_end(e, v))
+                            });
+                        match scheme {
+                            Scheme::Loop => {
+                                let weight = 0.375 + 0.25 * f32::cos(2.0 * PI / n);
+                                let beta = (0.625 - weight * weight) / n;
+                                position(v) * (1.0 - n * beta) + neighbours * beta
+                            }
+                            Scheme::CatmullClark => {
+                                let faces = &edges.vertex_faces[v as usize];
+                                let face_average =
+                                    faces.iter().fold(Vector::new(0.0, 0.0, 0.0), |sum, &f| {
+                                        sum + face_points[f]
+                                    }) * (1.0 / faces.len().max(1) as f32);
+                                let edge_average = (position(v) * n + neighbours) * (0.5 / n);
+                                (face_average + edge_average * 2.0 + position(v) * (n - 3.0))
+                                    * (1.0 / n)
+                            }
+                        }
+                    }
+                }
+            })
+            .collect();
+
+        let vertex_count = self.positions.len() as u32;
+        let edge_count = edges.vertices.len() as u32;
+        let mut positions: Vec<Point> = vertex_points
+            .iter()
+            .chain(&edge_points)
+            .map(|&p| Point::origin() + p)
+            .collect();
+        if scheme == Scheme::CatmullClark {
+            positions.extend(face_points.iter().map(|&p| Point::origin() + p));
+        }
+        let mut creases = HashSet::new();
+        for (e, &(a, b)) in edges.vertices.iter().enumerate() {
+            if sharp[e] && edges.faces[e].len() == 2 {
+                let middle = vertex_count + e as u32;
+                creases.insert(edge_key(a, middle));
+                creases.insert(edge_key(middle, b));
+            }
+        }
+
+        // uvs and colours are interpolated linearly inside each face
+        let mut attributes = Attributes::from(self);
+        let mut faces = Vec::new();
+        let mut corners = Vec::new();
+        let mut material_ids = Vec::new();
+        for (f, (face, corner)) in self.faces.iter().zip(&self.corners).enumerate() {
+            let k = face.len();
+            let middle =
+                |i: usize| vertex_count + edges.index[&edge_key(face[i], face[(i + 1) % k])];
+            let edge_corners: Vec<u32> = (0..k)
+                .map(|i| attributes.middle(corner[i], corner[(i + 1) % k]))
+                .collect();
+            match scheme {
+                Scheme::Loop => {
+                    faces.push(vec![face[0], middle(0), middle(2)]);
+                    corners.push(vec![corner[0], edge_corners[0], edge_corners[2]]);
+                    faces.push(vec![middle(0), face[1], middle(1)]);
+                    corners.push(vec![edge_corners[0], corner[1], edge_corners[1]]);
+                    faces.push(vec![middle(2), middle(1), face[2]]);
+                    corners.push(vec![edge_corners[2], edge_corners[1], corner[2]]);
+                    faces.push(vec![middle(0), middle(1), middle(2)]);
+                    corners.push(edge_corners);
+                    material_ids.extend_from_slice(&[self.material_ids[f]; 4]);
+                }
+                Scheme::CatmullClark => {
+                    let center = vertex_count + edge_count + f as u32;
+                    let center_corner = attributes.average(corner);
+                    for i in 0..k {
+                        let previous = (i + k - 1) % k;
+                        faces.push(vec![face[i], middle(i), center, middle(previous)]);
+                        corners.push(vec![
+                            corner[i],
+                            edge_corners[i],
+                            center_corner,
+                            edge_corners[previous],
+                        ]);
+                        material_ids.push(self.material_ids[f]);
+                    }
+                }
+            }
+        }
+
+        PolygonMesh {
+            positions,
+            faces,
+            corners,
+            attribute_count: attributes.count,
+            uvs: attributes.uvs,
+            colors: attributes.colors,
+            creases,
+            material_ids,
+            materials: self.materials.clone(),
+        }
+    }
+}
+
+fn edge_key(a: u32, b: u32) -> (u32, u32) {
+    (a.min(b), a.max(b))
+}
+
+/// The edges of a mesh, and what's around them.
+struct Edges {
+    /// The two ends of each edge, the smaller index first.
+    vertices: Vec<(u32, u32)>,
+    index: HashMap<(u32, u32), u32>,
+    /// Faces along each edge.
+    faces: Vec<Vec<usize>>,
+    /// Edges leaving each vertex.
+    around: Vec<Vec<usize>>,
+    /// Faces touching each vertex.
+    vertex_faces: Vec<Vec<usize>>,
+}
+
+impl Edges {
+    fn new(faces: &[Vec<u32>], vertex_count: usize) -> Edges {
+        let mut edges = Edges {
+            vertices: Vec::new(),
+            index: HashMap::new(),
+            faces: Vec::new(),
+            around: vec![Vec::new(); vertex_count],
+            vertex_faces: vec![Vec::new(); vertex_count],
+        };
+        for (f, face) in faces.iter().enumerate() {
+            for (i, &a) in face.iter().enumerate() {
+                edges.vertex_faces[a as usize].push(f);
+                let key = edge_key(a, face[(i + 1) % face.len()]);
+                let Edges {
+                    vertices,
+                    index,
+                    around,
+                    faces: edge_faces,
+                    ..
+                } = &mut edges;
+                let e = *index.entry(key).or_insert_with(|| {
+                    vertices.push(key);
+                    edge_faces.push(Vec::new());
+                    around[key.0 as usize].push(vertices.len() - 1);
+                    around[key.1 as usize].push(vertices.len() - 1);
+                    vertices.len() as u32 - 1
+                });
+                edge_faces[e as usize].push(f);
+            }
+        }
+        edges
+    }
+
+    fn other_end(&self, edge: usize, vertex: u32) -> u32 {
+        let (a, b) = self.vertices[edge];
+        if a == vertex {
+            b
+        } else {
+            a
+        }
+    }
+}
+
+/// The uvs and colours of a mesh being subdivided, growing as corners are added.
+struct Attributes {
+    count: usize,
+    uvs: Vec<(f32, f32)>,
+    colors: Vec<Spectrum>,
+    /// Attributes halfway between two others, so neighbouring faces agreeing on both ends of
+    /// an edge share its middle too.
+    middles: HashMap<(u32, u32), u32>,
+}
+
+impl Attributes {
+    fn from(mesh: &PolygonMesh) -> Attributes {
+        Attributes {
+            count: mesh.attribute_count,
+            uvs: mesh.uvs.clone(),
+            colors: mesh.colors.clone(),
+            middles: HashMap::new(),
+        }
+    }
+
+    fn middle(&mut self, a: u32, b: u32) -> u32 {
+        if a == b {
+            return a;
+        }
+        match self.middles.get(&edge_key(a, b)) {
+            Some(&middle) => middle,
+            None => {
+                let middle = self.average(&[a, b]);
+                self.middles.insert(edge_key(a, b), middle);
+                middle
+            }
+        }
+    }
+
+    fn average(&mut self, of: &[u32]) -> u32 {
+        let weight = 1.0 / of.len() as f32;
+        if !self.uvs.is_empty() {
+            let (u, v) = of.iter().fold((0.0, 0.0), |(u, v), &i| {
+                let uv = self.uvs[i as usize];
+                (u + uv.0 * weight, v + uv.1 * weight)
+            });
+            self.uvs.push((u, v));
+        }
+        if !self.colors.is_empty() {
+            let color = of.iter().fold(Spectrum::black(), |sum, &i| {
+                sum + self.colors[i as usize] * weight
+            });
+            self.colors.push(color);
+        }
+        self.count += 1;
+        self.count as u32 - 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::objects::BSDF;
+    use super::*;
+
+    fn cube() -> PolygonMesh {
+        let corners = (0..8)
+            .map(|i| {
+                let side = |bit: u32| if i & bit == 0 { -1.0 } else { 1.0 };
+                Point::new(side(1), side(2), side(4))
+            })
+            .collect();
+        let sides = vec![
+            vec![0, 2, 3, 1],
+            vec![4, 5, 7, 6],
+            vec![0, 1, 5, 4],
+            vec![2, 6, 7, 3],
+            vec![0, 4, 6, 2],
+            vec![1, 3, 7, 5],
+        ];
+        PolygonMesh::new(
+            corners,
+            sides,
+            Material::new(BSDF::Diffuse, Spectrum::grey(), Spectrum::black()),
+        )
+    }
+
+    fn radii(mesh: &TriangleMesh) -> (f32, f32) {
+        mesh.positions
+            .iter()
+            .map(|p| (*p - Point::origin()).norm())
+            .fold((f32::INFINITY, 0.0), |(min, max), r| {
+                (min.min(r), max.max(r))
+            })
+    }
+
+    #[test]
+    fn subdivided_cubes_round_off_unless_creased() {
+        let smooth = Subdivision::new(Scheme::CatmullClark, 3).apply(cube());
+        assert_eq!(smooth.triangle_count(), 6 * 4 * 4 * 4 * 2);
+        // the cube rounds off into nearly a sphere
+        let (min, max) = radii(&smooth);
+        assert!(max < 0.9 && max / min < 1.05);
+
+        let looped = Subdivision::new(Scheme::Loop, 2).apply(cube());
+        assert_eq!(looped.triangle_count(), 12 * 4 * 4);
+        assert!(radii(&looped).1 < 1.0);
+
+        // with every edge creased the sides stay flat
+        let creased = Subdivision::new(Scheme::CatmullClark, 2)
+            .with_crease_angle(f32::to_radians(45.0))
+            .apply(cube());
+        assert!(creased.positions.iter().all(|p| {
+            [p.x(), p.y(), p.z()]
+                .iter()
+                .any(|c| (c.abs() - 1.0).abs() < 1e-5)
+        }));
+        assert!((radii(&creased).1 - f32::sqrt(3.0)).abs() < 1e-5);
+    }
+}
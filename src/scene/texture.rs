@@ -0,0 +1,499 @@
+use std::fmt;
+use std::fs::File;
+use std::io::BufReader;
+use std::ops::{Add, Mul};
+use std::path::Path;
+use std::sync::Arc;
+
+use image::codecs::hdr::HdrDecoder;
+use image::error::{ImageError, ParameterError, ParameterErrorKind};
+use image::{DynamicImage, ImageResult};
+
+use super::procedural::ProceduralTexture;
+use super::Point;
+use crate::common::Spectrum;
+use crate::spectral::{Blackbody, Wavelengths};
+
+/// Everything a texture needs to know about the point it is being looked up at.
+#[derive(Clone, Copy, Debug)]
+pub struct TextureContext {
+    pub uv: (f32, f32),
+    /// Approximate width of the lookup footprint in uv space, used to pick a mip level.
+    /// Zero means "as sharp as possible".
+    pub uv_width: f32,
+    pub point: Point,
+    /// `point` relative to the object's own origin.
+    pub object_point: Point,
+    /// Interpolated vertex colour on meshes that have them, white elsewhere.
+    pub vertex_color: Spectrum,
+    /// What colours are turned into by `Texture::evaluate_spectrum`.
+    pub wavelengths: Wavelengths,
+}
+
+/// A material parameter that can vary over a surface.
+#[derive(Clone, Debug)]
+pub enum Texture {
+    Constant(Spectrum),
+    Image(Arc<ImageTexture>),
+    Procedural(Arc<ProceduralTexture>),
+    /// Light from a blackbody, evaluated exactly per wavelength in spectral mode.
+    Blackbody(Blackbody),
+    /// The colours stored with a mesh's vertices, as scanned models often have.
+    VertexColor,
+    /// Another texture multiplied by a factor, like glTF's texture and factor pairs.
+    Scaled(Arc<Texture>, f32),
+}
+
+impl Texture {
+    #[inline(always)]
+    pub fn evaluate(&self, ctx: &TextureContext) -> Spectrum {
+        match self {
+            Texture::Constant(spectrum) => *spectrum,
+            Texture::Image(image) => image.evaluate(ctx.uv, ctx.uv_width),
+            Texture::Procedural(procedural) => procedural.evaluate(ctx),
+            Texture::Blackbody(blackbody) => blackbody.rgb(),
+            Texture::VertexColor => ctx.vertex_color,
+            Texture::Scaled(texture, factor) => texture.evaluate(ctx) * *factor,
+        }
+    }
+
+    /// Lookup of a colour, as a value per wavelength when rendering spectrally. Data like
+    /// normal maps should use `evaluate`.
+    #[inline(always)]
+    pub fn evaluate_spectrum(&self, ctx: &TextureContext) -> Spectrum {
+        match self {
+            Texture::Blackbody(blackbody) if ctx.wavelengths.is_spectral() => {
+                ctx.wavelengths.map(|lambda| blackbody.value(lambda))
+            }
+            Texture::Scaled(texture, factor) => texture.evaluate_spectrum(ctx) * *factor,
+            texture => ctx.wavelengths.upsample(texture.evaluate(ctx)),
+        }
+    }
+
+    /// Scalar lookup for parameters like roughness; averages the channels.
+    #[inline(always)]
+    pub fn evaluate_float(&self, ctx: &TextureContext) -> f32 {
+        self.evaluate(ctx).average()
+    }
+
+    /// Lookup of an opacity mask: the alpha channel of images that have one, the average of
+    /// the channels otherwise.
+    #[inline(always)]
+    pub fn evaluate_alpha(&self, ctx: &TextureContext) -> f32 {
+        match self {
+            Texture::Image(image) if image.has_alpha() => image.alpha(ctx.uv, ctx.uv_width),
+            Texture::Scaled(texture, factor) => texture.evaluate_alpha(ctx) * factor,
+            texture => texture.evaluate_float(ctx),
+        }
+    }
+
+    /// Only constant textures can be known to be black everywhere.
+    pub fn is_black(&self) -> bool {
+        match self {
+            Texture::Constant(spectrum) => spectrum.is_black(),
+            Texture::Blackbody(blackbody) => blackbody.rgb().is_black(),
+            Texture::Scaled(texture, factor) => *factor == 0.0 || texture.is_black(),
+            Texture::Image(_) | Texture::Procedural(_) | Texture::VertexColor => false,
+        }
+    }
+}
+
+impl From<Spectrum> for Texture {
+    fn from(spectrum: Spectrum) -> Texture {
+        Texture::Constant(spectrum)
+    }
+}
+
+/// What happens to uv coordinates outside of [0, 1].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WrapMode {
+    Repeat,
+    Mirror,
+    Clamp,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FilterMode {
+    Nearest,
+    Bilinear,
+    /// Bilinear lookups in the two closest mip levels, blended by the footprint size.
+    Trilinear,
+}
+
+struct MipLevel<T> {
+    width: usize,
+    height: usize,
+    texels: Vec<T>,
+}
+
+/// An image stored as a mip pyramid of linear radiometric values.
+pub struct ImageTexture {
+    levels: Vec<MipLevel<Spectrum>>,
+    /// Pyramid of the alpha channel, for images that have one.
+    alpha_levels: Option<Vec<MipLevel<f32>>>,
+    wrap: WrapMode,
+    filter: FilterMode,
+}
+
+impl fmt::Debug for ImageTexture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "ImageTexture({}x{}, {} levels, {:?}, {:?})",
+            self.levels[0].width,
+            self.levels[0].height,
+            self.levels.len(),
+            self.wrap,
+            self.filter
+        )
+    }
+}
+
+impl ImageTexture {
+    /// Loads a PNG, JPEG or Radiance HDR image. 8-bit images holding colours should set
+    /// `srgb` so they are linearized; data maps (roughness, normals...) should not.
+    pub fn open(
+        filename: &str,
+        wrap: WrapMode,
+        filter: FilterMode,
+        srgb: bool,
+    ) -> ImageResult<ImageTexture> {
+        let is_hdr = Path::new(filename)
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("hdr"));
+        if !is_hdr {
+            return ImageTexture::from_image(&image::open(filename)?, wrap, filter, srgb);
+        }
+        let decoder = HdrDecoder::new(BufReader::new(File::open(filename)?))?;
+        let metadata = decoder.metadata();
+        let texels = decoder
+            .read_image_hdr()?
+            .iter()
+            .map(|p| Spectrum::new_f(p[0], p[1], p[2]))
+            .collect();
+        ImageTexture::new(
+            metadata.width as usize,
+            metadata.height as usize,
+            texels,
+            wrap,
+            filter,
+        )
+    }
+
+    /// Decodes a PNG or JPEG image held in memory, like the ones embedded in glTF files.
+    pub fn from_memory(
+        bytes: &[u8],
+        wrap: WrapMode,
+        filter: FilterMode,
+        srgb: bool,
+    ) -> ImageResult<ImageTexture> {
+        ImageTexture::from_image(&image::load_from_memory(bytes)?, wrap, filter, srgb)
+    }
+
+    /// Alpha is kept linear whatever `srgb` says.
+    fn from_image(
+        image: &DynamicImage,
+        wrap: WrapMode,
+        filter: FilterMode,
+        srgb: bool,
+    ) -> ImageResult<ImageTexture> {
+        let has_alpha = image.color().has_alpha();
+        let image = image.to_rgba8();
+        let decode = |c: u8| {
+            let c = c as f32 / 255.0;
+            if srgb {
+                srgb_to_linear(c)
+            } else {
+                c
+            }
+        };
+        let texels = image
+            .pixels()
+            .map(|p| Spectrum::new_f(decode(p[0]), decode(p[1]), decode(p[2])))
+            .collect();
+        let texture = ImageTexture::new(
+            image.width() as usize,
+            image.height() as usize,
+            texels,
+            wrap,
+            filter,
+        )?;
+        Ok(if has_alpha {
+            texture.with_alpha(image.pixels().map(|p| p[3] as f32 / 255.0).collect())
+        } else {
+            texture
+        })
+    }
+
+    /// Builds a texture from linear texels in row-major order, top row first. Images without
+    /// any texels are an error, there'd be nothing to look up.
+    pub fn new(
+        width: usize,
+        height: usize,
+        texels: Vec<Spectrum>,
+        wrap: WrapMode,
+        filter: FilterMode,
+    ) -> ImageResult<ImageTexture> {
+        if width == 0 || height == 0 {
+            return Err(ImageError::Parameter(ParameterError::from_kind(
+                ParameterErrorKind::DimensionMismatch,
+            )));
+        }
+        assert_eq!(texels.len(), width * height);
+        Ok(ImageTexture {
+            levels: MipLevel::pyramid(width, height, texels),
+            alpha_levels: None,
+            wrap,
+            filter,
+        })
+    }
+
+    /// Adds an alpha channel, in the same layout as the texels.
+    pub fn with_alpha(mut self, alpha: Vec<f32>) -> ImageTexture {
+        assert_eq!(alpha.len(), self.width() * self.height());
+        self.alpha_levels = Some(MipLevel::pyramid(self.width(), self.height(), alpha));
+        self
+    }
+
+    pub fn has_alpha(&self) -> bool {
+        self.alpha_levels.is_some()
+    }
+
+    pub fn width(&self) -> usize {
+        self.levels[0].width
+    }
+
+    pub fn height(&self) -> usize {
+        self.levels[0].height
+    }
+
+    /// Looks up the texture at `uv` for a footprint `uv_width` wide.
+    pub fn evaluate(&self, uv: (f32, f32), uv_width: f32) -> Spectrum {
+        self.lookup(&self.levels, uv, uv_width)
+    }
+
+    /// Looks up the alpha channel like `evaluate`; opaque for images without one.
+    pub fn alpha(&self, uv: (f32, f32), uv_width: f32) -> f32 {
+        match &self.alpha_levels {
+            Some(levels) => self.lookup(levels, uv, uv_width),
+            None => 1.0,
+        }
+    }
+
+    fn lookup<T: Texel>(&self, levels: &[MipLevel<T>], uv: (f32, f32), uv_width: f32) -> T {
+        // v points up in uv space, rows go down in the image
+        let (s, t) = (uv.0, 1.0 - uv.1);
+        match self.filter {
+            FilterMode::Nearest => levels[0].nearest(s, t, self.wrap),
+            FilterMode::Bilinear => levels[0].bilinear(s, t, self.wrap),
+            FilterMode::Trilinear => {
+                let texels_covered = uv_width * self.width().max(self.height()) as f32;
+                let level = f32::log2(f32::max(texels_covered, 1.0));
+                let max_level = (levels.len() - 1) as f32;
+                if level >= max_level {
+                    return levels.last().unwrap().bilinear(s, t, self.wrap);
+                }
+                let lower = level.floor();
+                let frac = level - lower;
+                let fine = levels[lower as usize].bilinear(s, t, self.wrap);
+                if frac == 0.0 {
+                    return fine;
+                }
+                let coarse = levels[lower as usize + 1].bilinear(s, t, self.wrap);
+                fine * (1.0 - frac) + coarse * frac
+            }
+        }
+    }
+}
+
+/// What mip levels can hold: anything that can be blended.
+trait Texel: Copy + Add<Output = Self> + Mul<f32, Output = Self> {}
+
+impl<T: Copy + Add<Output = T> + Mul<f32, Output = T>> Texel for T {}
+
+impl<T: Texel> MipLevel<T> {
+    /// The full-resolution level followed by ever smaller ones, down to a single texel.
+    fn pyramid(width: usize, height: usize, texels: Vec<T>) -> Vec<MipLevel<T>> {
+        let mut levels = vec![MipLevel {
+            width,
+            height,
+            texels,
+        }];
+        while let Some(next) = levels.last().unwrap().downsample() {
+            levels.push(next);
+        }
+        levels
+    }
+
+    /// Box-filters down to half resolution; `None` once the level is a single texel.
+    fn downsample(&self) -> Option<MipLevel<T>> {
+        if self.width == 1 && self.height == 1 {
+            return None;
+        }
+        let width = self.width.div_ceil(2);
+        let height = self.height.div_ceil(2);
+        let mut texels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let (x0, y0) = (2 * x, 2 * y);
+                let (x1, y1) = (
+                    usize::min(x0 + 1, self.width - 1),
+                    usize::min(y0 + 1, self.height - 1),
+                );
+                let sum = self.texel(x0, y0)
+                    + self.texel(x1, y0)
+                    + self.texel(x0, y1)
+                    + self.texel(x1, y1);
+                texels.push(sum * 0.25);
+            }
+        }
+        Some(MipLevel {
+            width,
+            height,
+            texels,
+        })
+    }
+
+    #[inline(always)]
+    fn texel(&self, x: usize, y: usize) -> T {
+        self.texels[y * self.width + x]
+    }
+
+    #[inline(always)]
+    fn wrapped_texel(&self, x: i64, y: i64, wrap: WrapMode) -> T {
+        let x = wrap_index(x, self.width, wrap);
+        let y = wrap_index(y, self.height, wrap);
+        self.texel(x, y)
+    }
+
+    fn nearest(&self, s: f32, t: f32, wrap: WrapMode) -> T {
+        let x = (s * self.width as f32).floor() as i64;
+        let y = (t * self.height as f32).floor() as i64;
+        self.wrapped_texel(x, y, wrap)
+    }
+
+    fn bilinear(&self, s: f32, t: f32, wrap: WrapMode) -> T {
+        // texel centers sit at half-integer coordinates
+        let x = s * self.width as f32 - 0.5;
+        let y = t * self.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (dx, dy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        self.wrapped_texel(x0, y0, wrap) * ((1.0 - dx) * (1.0 - dy))
+            + self.wrapped_texel(x0 + 1, y0, wrap) * (dx * (1.0 - dy))
+            + self.wrapped_texel(x0, y0 + 1, wrap) * ((1.0 - dx) * dy)
+            + self.wrapped_texel(x0 + 1, y0 + 1, wrap) * (dx * dy)
+    }
+}
+
+#[inline(always)]
+fn wrap_index(i: i64, size: usize, wrap: WrapMode) -> usize {
+    let size = size as i64;
+    match wrap {
+        WrapMode::Repeat => i.rem_euclid(size) as usize,
+        WrapMode::Clamp => i.clamp(0, size - 1) as usize,
+        WrapMode::Mirror => {
+            let period = i.rem_euclid(2 * size);
+            if period < size {
+                period as usize
+            } else {
+                (2 * size - 1 - period) as usize
+            }
+        }
+    }
+}
+
+#[inline(always)]
+pub(super) fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        f32::powf((c + 0.055) / 1.055, 2.4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::objects::{Material, BSDF};
+    use super::super::triangle_mesh::TriangleMesh;
+    use super::super::{Ray, Scene, Vector};
+    use super::*;
+
+    #[test]
+    fn mip_pyramid_averages_down_to_one_texel() {
+        let texels = vec![
+            Spectrum::new_f(1.0, 0.0, 0.0),
+            Spectrum::new_f(0.0, 1.0, 0.0),
+            Spectrum::new_f(0.0, 0.0, 1.0),
+            Spectrum::new_f(1.0, 1.0, 1.0),
+            Spectrum::new_f(0.0, 0.0, 0.0),
+            Spectrum::new_f(0.0, 0.0, 0.0),
+        ];
+        let texture =
+            ImageTexture::new(3, 2, texels, WrapMode::Repeat, FilterMode::Trilinear).unwrap();
+        assert_eq!(texture.levels.len(), 3);
+
+        // a footprint covering the whole image reads the last level
+        let average = texture.evaluate((0.5, 0.5), 1.0);
+        let sharp = texture.evaluate((1.0 / 6.0, 0.75), 0.0);
+        assert!(average.average() > 0.0 && average.average() < 1.0);
+        assert!((sharp.average() - 1.0 / 3.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn opacity_masks_read_the_alpha_channel() {
+        // an opaque black left half, and a white right half that's transparent
+        let mut png = Vec::new();
+        let pixels = vec![0, 0, 0, 255, 255, 255, 255, 0];
+        DynamicImage::ImageRgba8(image::RgbaImage::from_raw(2, 1, pixels).unwrap())
+            .write_to(&mut png, image::ImageFormat::Png)
+            .unwrap();
+        let mask =
+            ImageTexture::from_memory(&png, WrapMode::Clamp, FilterMode::Nearest, false).unwrap();
+        assert!(mask.has_alpha());
+
+        let material = Material::new(BSDF::Diffuse, Spectrum::grey(), Spectrum::black())
+            .with_opacity(Texture::Image(Arc::new(mask)));
+        let quad = TriangleMesh::new(
+            vec![
+                Point::new(0.0, 0.0, 0.0),
+                Point::new(1.0, 0.0, 0.0),
+                Point::new(1.0, 1.0, 0.0),
+                Point::new(0.0, 1.0, 0.0),
+            ],
+            vec![[0, 1, 2], [0, 2, 3]],
+            material,
+        )
+        .with_uvs(vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)]);
+        let scene = Scene::new(vec![quad], Vec::new(), Vec::new());
+        let down = Vector::new(0.0, 0.0, -1.0);
+        assert!(scene
+            .intersect(Ray::new(Point::new(0.25, 0.5, 1.0), down))
+            .is_some());
+        assert!(scene
+            .intersect(Ray::new(Point::new(0.75, 0.5, 1.0), down))
+            .is_none());
+    }
+
+    #[test]
+    fn empty_images_are_rejected() {
+        for &(width, height) in [(0, 4), (4, 0), (0, 0)].iter() {
+            let texture = ImageTexture::new(
+                width,
+                height,
+                Vec::new(),
+                WrapMode::Repeat,
+                FilterMode::Nearest,
+            );
+            assert!(texture.is_err());
+        }
+    }
+
+    #[test]
+    fn wrap_modes() {
+        assert_eq!(wrap_index(-1, 4, WrapMode::Repeat), 3);
+        assert_eq!(wrap_index(-1, 4, WrapMode::Clamp), 0);
+        assert_eq!(wrap_index(-1, 4, WrapMode::Mirror), 0);
+        assert_eq!(wrap_index(5, 4, WrapMode::Mirror), 2);
+    }
+}
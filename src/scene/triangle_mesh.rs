@@ -0,0 +1,504 @@
+use std::collections::{HashMap, VecDeque};
+use std::mem::size_of;
+
+use super::objects::Material;
+use super::{Point, Vector};
+use crate::common::Spectrum;
+
+/// Triangles sharing their vertices: positions, normals and uvs are stored once per vertex and
+/// faces refer to them by index. Each face picks one of the mesh's materials.
+#[derive(Clone)]
+pub struct TriangleMesh {
+    pub positions: Vec<Point>,
+    /// Unit normals, either none or one per position. Faces without them are flat.
+    pub normals: Vec<Vector>,
+    /// Either none or one per position.
+    pub uvs: Vec<(f32, f32)>,
+    /// Linear colours, either none or one per position. Materials see them through
+    /// `Texture::VertexColor`.
+    pub colors: Vec<Spectrum>,
+    pub faces: Vec<[u32; 3]>,
+    /// Index into `materials` of each face.
+    pub material_ids: Vec<u32>,
+    pub materials: Vec<Material>,
+}
+
+impl TriangleMesh {
+    /// A mesh with every face made of `material`.
+    pub fn new(positions: Vec<Point>, faces: Vec<[u32; 3]>, material: Material) -> TriangleMesh {
+        TriangleMesh {
+            positions,
+            normals: Vec::new(),
+            uvs: Vec::new(),
+            colors: Vec::new(),
+            material_ids: vec![0; faces.len()],
+            faces,
+            materials: vec![material],
+        }
+    }
+
+    pub fn empty() -> TriangleMesh {
+        TriangleMesh {
+            positions: Vec::new(),
+            normals: Vec::new(),
+            uvs: Vec::new(),
+            colors: Vec::new(),
+            faces: Vec::new(),
+            material_ids: Vec::new(),
+            materials: Vec::new(),
+        }
+    }
+
+    pub fn with_normals(mut self, normals: Vec<Vector>) -> TriangleMesh {
+        assert_eq!(normals.len(), self.positions.len());
+        self.normals = normals.iter().map(|n| n.normalized()).collect();
+        self
+    }
+
+    pub fn with_uvs(mut self, uvs: Vec<(f32, f32)>) -> TriangleMesh {
+        assert_eq!(uvs.len(), self.positions.len());
+        self.uvs = uvs;
+        self
+    }
+
+    pub fn with_colors(mut self, colors: Vec<Spectrum>) -> TriangleMesh {
+        assert_eq!(colors.len(), self.positions.len());
+        self.colors = colors;
+        self
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.faces.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.faces.is_empty()
+    }
+
+    /// Replaces the material of every face.
+    pub fn set_material(&mut self, material: Material) {
+        self.materials = vec![material];
+        self.material_ids.iter_mut().for_each(|id| *id = 0);
+    }
+
+    /// Smallest and largest corner of the box around the vertices.
+    pub fn bounds(&self) -> (Point, Point) {
+        let inf = f32::INFINITY;
+        self.positions.iter().fold(
+            (Point::new(inf, inf, inf), Point::new(-inf, -inf, -inf)),
+            |(min, max), p| {
+                (
+                    Point::new(min.x().min(p.x()), min.y().min(p.y()), min.z().min(p.z())),
+                    Point::new(max.x().max(p.x()), max.y().max(p.y()), max.z().max(p.z())),
+                )
+            },
+        )
+    }
+
+    /// Uniformly scales the mesh about the origin, then moves it by `offset`.
+    pub fn transform(&mut self, scale: f32, offset: Vector) {
+        for p in &mut self.positions {
+            *p = Point::origin() + (*p - Point::origin()) * scale + offset;
+        }
+    }
+
+    /// Replaces the normals with ones averaged over the faces around each vertex, weighted by
+    /// the angle of their corner there so that finely split faces don't pull harder than big
+    /// ones. Faces meeting at more than `crease_angle` radians keep a hard edge between them,
+    /// which splits their shared vertices. Vertices at the same position are treated as one,
+    /// so seams in the uvs don't show up as seams in the shading.
+    pub fn smooth_normals(&mut self, crease_angle: f32) {
+        let welded = self.welded_vertices();
+        let unit_normals: Vec<Vector> = self
+            .faces
+            .iter()
+            .map(|face| {
+                let [p1, p2, p3] = face.map(|i| self.positions[i as usize]);
+                normalized_or_zero((p2 - p1).cross(p3 - p1))
+            })
+            .collect();
+        let corner_angles: Vec<[f32; 3]> = self
+            .faces
+            .iter()
+            .map(|face| {
+                let p = face.map(|i| self.positions[i as usize]);
+                [0, 1, 2].map(|k| {
+                    let e1 = normalized_or_zero(p[(k + 1) % 3] - p[k]);
+                    let e2 = normalized_or_zero(p[(k + 2) % 3] - p[k]);
+                    f32::acos(e1.dot(e2).clamp(-1.0, 1.0))
+                })
+            })
+            .collect();
+
+        // faces around each welded vertex, packed one vertex after the other
+        let mut first_face = vec![0; self.positions.len() + 1];
+        for face in &self.faces {
+            for &i in face {
+                first_face[welded[i as usize] as usize + 1] += 1;
+            }
+        }
+        for i in 1..first_face.len() {
+            first_face[i] += first_face[i - 1];
+        }
+        let mut filled = first_face.clone();
+        let mut vertex_faces = vec![(0, 0); first_face[self.positions.len()]];
+        for (f, face) in self.faces.iter().enumerate() {
+            for (k, &i) in face.iter().enumerate() {
+                let w = welded[i as usize] as usize;
+                vertex_faces[filled[w]] = (f, k);
+                filled[w] += 1;
+            }
+        }
+
+        // corners whose vertex and normal agree share a vertex
+        let cos_crease = f32::cos(crease_angle);
+        let mut vertices: HashMap<(u32, [u32; 3]), u32> = HashMap::new();
+        let mut old_vertices = Vec::new();
+        let mut normals = Vec::new();
+        let mut faces = self.faces.clone();
+        for (f, face) in faces.iter_mut().enumerate() {
+            for corner in face.iter_mut() {
+                let w = welded[*corner as usize] as usize;
+                let mut normal = Vector::new(0.0, 0.0, 0.0);
+                for &(g, k) in &vertex_faces[first_face[w]..first_face[w + 1]] {
+                    if g == f || unit_normals[g].dot(unit_normals[f]) >= cos_crease {
+                        normal = normal + unit_normals[g] * corner_angles[g][k];
+                    }
+                }
+                let normal = normalized_or_zero(normal);
+                let key = (
+                    *corner,
+                    [normal.x(), normal.y(), normal.z()].map(f32::to_bits),
+                );
+                let v = *corner as usize;
+                *corner = *vertices.entry(key).or_insert_with(|| {
+                    old_vertices.push(v);
+                    normals.push(normal);
+                    normals.len() as u32 - 1
+                });
+            }
+        }
+
+        self.positions = old_vertices.iter().map(|&v| self.positions[v]).collect();
+        if !self.uvs.is_empty() {
+            self.uvs = old_vertices.iter().map(|&v| self.uvs[v]).collect();
+        }
+        if !self.colors.is_empty() {
+            self.colors = old_vertices.iter().map(|&v| self.colors[v]).collect();
+        }
+        self.normals = normals;
+        self.faces = faces;
+    }
+
+    /// Flips faces until every pair of neighbours agrees on the winding, then turns closed
+    /// pieces of the mesh inside out if their faces point inwards. Open pieces keep the
+    /// winding of whichever of their faces comes first. Returns the number of faces flipped.
+    ///
+    /// Vertex normals are left alone, call `smooth_normals` afterwards if they came from the
+    /// same broken file.
+    pub fn orient_faces(&mut self) -> usize {
+        let welded = self.welded_vertices();
+        let edge = |face: &[u32; 3], k: usize| {
+            (welded[face[k] as usize], welded[face[(k + 1) % 3] as usize])
+        };
+        // faces along each undirected edge
+        let mut edge_faces: HashMap<(u32, u32), Vec<usize>> = HashMap::new();
+        for (f, face) in self.faces.iter().enumerate() {
+            for k in 0..3 {
+                let (a, b) = edge(face, k);
+                edge_faces.entry((a.min(b), a.max(b))).or_default().push(f);
+            }
+        }
+
+        let mut flipped = vec![false; self.faces.len()];
+        let mut visited = vec![false; self.faces.len()];
+        let mut queue = VecDeque::new();
+        for start in 0..self.faces.len() {
+            if visited[start] {
+                continue;
+            }
+            visited[start] = true;
+            queue.push_back(start);
+            let mut component = Vec::new();
+            let mut closed = true;
+            while let Some(f) = queue.pop_front() {
+                component.push(f);
+                for k in 0..3 {
+                    let (a, b) = edge(&self.faces[f], k);
+                    let (a, b) = if flipped[f] { (b, a) } else { (a, b) };
+                    let neighbours = &edge_faces[&(a.min(b), a.max(b))];
+                    closed &= neighbours.len() == 2;
+                    for &g in neighbours {
+                        if visited[g] {
+                            continue;
+                        }
+                        visited[g] = true;
+                        // neighbours agree when they run along their shared edge in opposite
+                        // directions
+                        flipped[g] = (0..3).any(|j| edge(&self.faces[g], j) == (a, b));
+                        queue.push_back(g);
+                    }
+                }
+            }
+
+            if closed {
+                // the signed volume of a closed surface is negative when it faces inwards
+                let volume: f32 = component
+                    .iter()
+                    .map(|&f| {
+                        let [p1, p2, p3] =
+                            self.faces[f].map(|i| self.positions[i as usize] - Point::origin());
+                        let volume = p1.dot(p2.cross(p3));
+                        if flipped[f] {
+                            -volume
+                        } else {
+                            volume
+                        }
+                    })
+                    .sum();
+                if volume < 0.0 {
+                    component.iter().for_each(|&f| flipped[f] = !flipped[f]);
+                }
+            }
+        }
+
+        for (face, _) in self.faces.iter_mut().zip(&flipped).filter(|(_, f)| **f) {
+            face.swap(1, 2);
+        }
+        flipped.iter().filter(|f| **f).count()
+    }
+
+    /// Applies `options` to a freshly loaded mesh.
+    pub fn fix_normals(&mut self, options: &NormalOptions) {
+        if options.orient_faces {
+            let flipped = self.orient_faces();
+            if flipped > 0 {
+                println!("Flipped {} inconsistently wound faces", flipped);
+            }
+        }
+        if self.normals.is_empty() || options.recompute {
+            if let Some(angle) = options.crease_angle {
+                self.smooth_normals(angle);
+            } else {
+                self.normals.clear();
+            }
+        }
+    }
+
+    /// Index of a position shared by all vertices at the same position, for each vertex.
+    fn welded_vertices(&self) -> Vec<u32> {
+        weld(&self.positions).1
+    }
+
+    /// Adds the faces of `other`, with their materials. If only one of the meshes has normals
+    /// or uvs, the other's vertices get zero ones: flat faces and a constant uv. Missing colours
+    /// are white.
+    pub fn append(&mut self, mut other: TriangleMesh) {
+        let (count, other_count) = (self.positions.len(), other.positions.len());
+        if self.normals.is_empty() != other.normals.is_empty() {
+            let zero = Vector::new(0.0, 0.0, 0.0);
+            self.normals.resize(count, zero);
+            other.normals.resize(other_count, zero);
+        }
+        if self.uvs.is_empty() != other.uvs.is_empty() {
+            self.uvs.resize(count, (0.0, 0.0));
+            other.uvs.resize(other_count, (0.0, 0.0));
+        }
+        if self.colors.is_empty() != other.colors.is_empty() {
+            self.colors.resize(count, Spectrum::white());
+            other.colors.resize(other_count, Spectrum::white());
+        }
+
+        let (first_vertex, first_material) = (count as u32, self.materials.len() as u32);
+        self.positions.append(&mut other.positions);
+        self.normals.append(&mut other.normals);
+        self.uvs.append(&mut other.uvs);
+        self.colors.append(&mut other.colors);
+        self.faces.extend(
+            other
+                .faces
+                .iter()
+                .map(|face| face.map(|i| i + first_vertex)),
+        );
+        self.material_ids
+            .extend(other.material_ids.iter().map(|id| id + first_material));
+        self.materials.append(&mut other.materials);
+    }
+
+    /// Bytes taken by the vertex and face arrays and the materials.
+    pub fn memory_usage(&self) -> usize {
+        self.positions.capacity() * size_of::<Point>()
+            + self.normals.capacity() * size_of::<Vector>()
+            + self.uvs.capacity() * size_of::<(f32, f32)>()
+            + self.colors.capacity() * size_of::<Spectrum>()
+            + self.faces.capacity() * size_of::<[u32; 3]>()
+            + self.material_ids.capacity() * size_of::<u32>()
+            + self.materials.capacity() * size_of::<Material>()
+    }
+}
+
+/// What to do about the normals of a mesh file, see `TriangleMesh::fix_normals`.
+#[derive(Clone, Copy, Debug)]
+pub struct NormalOptions {
+    /// Meshes without normals are smoothed, except where faces meet at more than this many
+    /// radians. `None` leaves them flat shaded.
+    pub crease_angle: Option<f32>,
+    /// Replace the file's normals too, for files whose normals can't be trusted.
+    pub recompute: bool,
+    /// Make the winding of the faces consistent first, see `TriangleMesh::orient_faces`.
+    pub orient_faces: bool,
+}
+
+impl NormalOptions {
+    /// Flat shading and the file's normals and winding as they are.
+    pub fn flat() -> NormalOptions {
+        NormalOptions {
+            crease_angle: None,
+            recompute: false,
+            orient_faces: false,
+        }
+    }
+
+    pub fn with_crease_angle(mut self, crease_angle: f32) -> NormalOptions {
+        self.crease_angle = Some(crease_angle);
+        self
+    }
+
+    pub fn recomputed(mut self) -> NormalOptions {
+        self.recompute = true;
+        self
+    }
+
+    pub fn with_oriented_faces(mut self) -> NormalOptions {
+        self.orient_faces = true;
+        self
+    }
+}
+
+impl Default for NormalOptions {
+    /// Smooths meshes that have no normals of their own, keeping edges sharper than 60 degrees.
+    fn default() -> NormalOptions {
+        NormalOptions::flat().with_crease_angle(f32::to_radians(60.0))
+    }
+}
+
+/// Merges points at the same position: returns the distinct positions, in the order they
+/// first appear, and the index into those of each point.
+pub(super) fn weld(points: &[Point]) -> (Vec<Point>, Vec<u32>) {
+    let mut indices: HashMap<[u32; 3], u32> = HashMap::new();
+    let mut positions = Vec::new();
+    let welded = points
+        .iter()
+        .map(|p| {
+            // adding zero turns -0 into 0 so both weld together
+            let key = [p.x(), p.y(), p.z()].map(|c| (c + 0.0).to_bits());
+            *indices.entry(key).or_insert_with(|| {
+                positions.push(*p);
+                positions.len() as u32 - 1
+            })
+        })
+        .collect();
+    (positions, welded)
+}
+
+fn normalized_or_zero(v: Vector) -> Vector {
+    match v.norm() {
+        length if length > 0.0 => v * (1.0 / length),
+        _ => v,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::objects::BSDF;
+    use super::*;
+
+    #[test]
+    fn appended_faces_keep_their_vertices_and_materials() {
+        let quad = |material: Material| {
+            TriangleMesh::new(
+                vec![
+                    Point::new(0.0, 0.0, 0.0),
+                    Point::new(1.0, 0.0, 0.0),
+                    Point::new(1.0, 1.0, 0.0),
+                    Point::new(0.0, 1.0, 0.0),
+                ],
+                vec![[0, 1, 2], [0, 2, 3]],
+                material,
+            )
+        };
+        let grey = Material::new(BSDF::Diffuse, Spectrum::grey(), Spectrum::black());
+        let red = Material::new(BSDF::Diffuse, Spectrum::red(), Spectrum::black());
+        let mut mesh = quad(grey);
+        let uvs = vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)];
+        mesh.append(quad(red).with_uvs(uvs));
+
+        assert_eq!(mesh.triangle_count(), 4);
+        assert_eq!(mesh.positions.len(), 8);
+        assert_eq!(mesh.faces[3], [4, 6, 7]);
+        assert_eq!(mesh.material_ids, vec![0, 0, 1, 1]);
+        assert_eq!(mesh.uvs.len(), 8);
+        assert!(mesh.normals.is_empty());
+
+        mesh.set_material(Material::new(
+            BSDF::Specular,
+            Spectrum::white(),
+            Spectrum::black(),
+        ));
+        assert_eq!(mesh.materials.len(), 1);
+        assert_eq!(mesh.material_ids, vec![0; 4]);
+    }
+
+    #[test]
+    fn creases_split_smoothed_vertices() {
+        // two faces folded at a right angle along the x axis
+        let fold = || {
+            TriangleMesh::new(
+                vec![
+                    Point::new(0.0, 0.0, 0.0),
+                    Point::new(1.0, 0.0, 0.0),
+                    Point::new(0.0, 1.0, 0.0),
+                    Point::new(0.0, 0.0, 1.0),
+                ],
+                vec![[0, 1, 2], [1, 0, 3]],
+                Material::new(BSDF::Diffuse, Spectrum::grey(), Spectrum::black()),
+            )
+        };
+
+        let mut smooth = fold();
+        smooth.smooth_normals(f32::to_radians(100.0));
+        assert_eq!(smooth.positions.len(), 4);
+        let shared = smooth.normals[smooth.faces[0][0] as usize];
+        assert!((shared.y() - f32::sqrt(0.5)).abs() < 1e-5);
+        assert!((shared.z() - f32::sqrt(0.5)).abs() < 1e-5);
+
+        let mut creased = fold();
+        creased.smooth_normals(f32::to_radians(30.0));
+        assert_eq!(creased.positions.len(), 6);
+        assert!((creased.normals[creased.faces[0][0] as usize].z() - 1.0).abs() < 1e-5);
+        assert!((creased.normals[creased.faces[1][0] as usize].y() - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn orienting_faces_makes_closed_meshes_face_outwards() {
+        // a tetrahedron wound inwards, with one face the other way round, and its corners
+        // duplicated the way split uvs would
+        let positions = vec![
+            Point::new(0.0, 0.0, 0.0),
+            Point::new(1.0, 0.0, 0.0),
+            Point::new(0.0, 1.0, 0.0),
+            Point::new(0.0, 0.0, 1.0),
+            Point::new(0.0, 0.0, 0.0),
+        ];
+        let faces = vec![[0, 1, 2], [4, 1, 3], [0, 2, 3], [1, 3, 2]];
+        let mut mesh = TriangleMesh::new(
+            positions,
+            faces,
+            Material::new(BSDF::Diffuse, Spectrum::grey(), Spectrum::black()),
+        );
+
+        assert_eq!(mesh.orient_faces(), 3);
+        assert_eq!(mesh.faces, vec![[0, 2, 1], [4, 1, 3], [0, 3, 2], [1, 2, 3]]);
+        assert_eq!(mesh.orient_faces(), 0);
+    }
+}
@@ -0,0 +1,165 @@
+use std::fs;
+
+use super::{LoadError, Point};
+
+/// Dense grid of densities, as written by simulation tools in Mitsuba's binary `.vol` format.
+/// Cells are sample points: values are interpolated between cell centers.
+#[derive(Debug)]
+pub struct VoxelGrid {
+    resolution: [usize; 3],
+    /// x varies fastest, then y, then z.
+    densities: Vec<f32>,
+    max_density: f32,
+    /// Bounding box stored in the file.
+    bounds: (Point, Point),
+}
+
+impl VoxelGrid {
+    pub fn open(filename: &str) -> Result<VoxelGrid, LoadError> {
+        VoxelGrid::parse(&fs::read(filename)?)
+    }
+
+    /// Reads a `.vol` file: "VOL" and version 3, the encoding (1 for 32-bit floats, 3 for
+    /// bytes), the x, y and z resolution and channel count, the bounding box, then the data.
+    /// Multi-channel grids are averaged down to one density.
+    pub fn parse(bytes: &[u8]) -> Result<VoxelGrid, LoadError> {
+        if bytes.len() < 48 || &bytes[0..3] != b"VOL" {
+            return Err(LoadError::Parse("missing VOL header".to_string()));
+        }
+        if bytes[3] != 3 {
+            return Err(LoadError::Parse(format!(
+                "only version 3 is supported, not version {}",
+                bytes[3]
+            )));
+        }
+        let int = |offset: usize| {
+            i32::from_le_bytes([
+                bytes[offset],
+                bytes[offset + 1],
+                bytes[offset + 2],
+                bytes[offset + 3],
+            ])
+        };
+        let float = |offset: usize| f32::from_bits(int(offset) as u32);
+
+        let encoding = int(4);
+        let dimensions = [int(8), int(12), int(16), int(20)];
+        if dimensions.iter().any(|&d| d <= 0) {
+            return Err(LoadError::Parse("empty grid".to_string()));
+        }
+        let [x, y, z, channels] = dimensions.map(|d| d as usize);
+        let bounds = (
+            Point::new(float(24), float(28), float(32)),
+            Point::new(float(36), float(40), float(44)),
+        );
+
+        // the header can claim any size, so it mustn't overflow on the way to the data's
+        let too_large = || LoadError::Parse("grid too large".to_string());
+        let count = x
+            .checked_mul(y)
+            .and_then(|c| c.checked_mul(z))
+            .and_then(|c| c.checked_mul(channels))
+            .ok_or_else(too_large)?;
+        let data = &bytes[48..];
+        let truncated = || LoadError::Parse("truncated data".to_string());
+        let values: Vec<f32> = match encoding {
+            1 if data.len() / 4 >= count => (0..count).map(|i| float(48 + 4 * i)).collect(),
+            1 => return Err(truncated()),
+            3 if data.len() >= count => data[..count].iter().map(|&b| b as f32 / 255.0).collect(),
+            3 => return Err(truncated()),
+            _ => {
+                return Err(LoadError::Parse(format!(
+                    "unsupported encoding {}",
+                    encoding
+                )))
+            }
+        };
+        let densities: Vec<f32> = values
+            .chunks(channels)
+            .map(|cell| cell.iter().sum::<f32>() / channels as f32)
+            .collect();
+        let max_density = densities.iter().fold(0.0, |max: f32, &d| max.max(d));
+
+        Ok(VoxelGrid {
+            resolution: [x, y, z],
+            densities,
+            max_density,
+            bounds,
+        })
+    }
+
+    pub fn resolution(&self) -> [usize; 3] {
+        self.resolution
+    }
+
+    pub fn max_density(&self) -> f32 {
+        self.max_density
+    }
+
+    pub fn bounds(&self) -> (Point, Point) {
+        self.bounds
+    }
+
+    /// Trilinearly interpolated density at `uvw` in [0, 1]^3 across the grid.
+    pub fn density(&self, uvw: [f32; 3]) -> f32 {
+        let [nx, ny, nz] = self.resolution;
+        // the position relative to the cells below it and how far towards the next
+        let axis = |u: f32, n: usize| {
+            let x = (u * n as f32 - 0.5).clamp(0.0, (n - 1) as f32);
+            let i = (x as usize).min(n - 1);
+            (i, (i + 1).min(n - 1), x - i as f32)
+        };
+        let (x0, x1, tx) = axis(uvw[0], nx);
+        let (y0, y1, ty) = axis(uvw[1], ny);
+        let (z0, z1, tz) = axis(uvw[2], nz);
+        let at = |x: usize, y: usize, z: usize| self.densities[(z * ny + y) * nx + x];
+        let lerp = |a: f32, b: f32, t: f32| a * (1.0 - t) + b * t;
+        let plane = |z: usize| {
+            lerp(
+                lerp(at(x0, y0, z), at(x1, y0, z), tx),
+                lerp(at(x0, y1, z), at(x1, y1, z), tx),
+                ty,
+            )
+        };
+        lerp(plane(z0), plane(z1), tz)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn float_grid_is_parsed_and_interpolated() {
+        let mut bytes = b"VOL".to_vec();
+        bytes.push(3);
+        for int in [1i32, 2, 1, 1, 1].iter() {
+            bytes.extend_from_slice(&int.to_le_bytes());
+        }
+        for float in [0.0f32, 0.0, 0.0, 1.0, 1.0, 1.0, 0.25, 0.75].iter() {
+            bytes.extend_from_slice(&float.to_le_bytes());
+        }
+        let grid = VoxelGrid::parse(&bytes).unwrap();
+        assert_eq!(grid.resolution(), [2, 1, 1]);
+        assert_eq!(grid.max_density(), 0.75);
+        // cell centers hold the values, halfway between them is the average
+        assert_eq!(grid.density([0.25, 0.5, 0.5]), 0.25);
+        assert_eq!(grid.density([0.5, 0.5, 0.5]), 0.5);
+        assert_eq!(grid.density([1.0, 0.5, 0.5]), 0.75);
+
+        match VoxelGrid::parse(&bytes[..bytes.len() - 1]) {
+            Err(LoadError::Parse(message)) => assert_eq!(message, "truncated data"),
+            _ => panic!("expected a parse error"),
+        }
+
+        // a header whose size overflows is rejected rather than wrapping around
+        let mut huge = bytes.clone();
+        for (i, int) in [i32::MAX, i32::MAX, i32::MAX, 4].iter().enumerate() {
+            huge[8 + 4 * i..12 + 4 * i].copy_from_slice(&int.to_le_bytes());
+        }
+        match VoxelGrid::parse(&huge) {
+            Err(LoadError::Parse(message)) => assert_eq!(message, "grid too large"),
+            _ => panic!("expected a parse error"),
+        }
+    }
+}
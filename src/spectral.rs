@@ -0,0 +1,523 @@
+//! Spectral rendering support. In spectral mode the three channels of a `Spectrum` carried
+//! along a path hold radiance at three wavelengths instead of red, green and blue.
+
+use crate::common::Spectrum;
+
+pub const LAMBDA_MIN: f32 = 360.0;
+pub const LAMBDA_MAX: f32 = 830.0;
+
+/// Wavelengths standing in for the channels when rendering in RGB, in nanometers.
+const RGB_WAVELENGTHS: [f32; 3] = [610.0, 550.0, 465.0];
+
+/// Integral of the (fitted) CIE y colour matching function over the visible range.
+const CIE_Y_INTEGRAL: f32 = 106.922;
+
+/// Linear sRGB of the equal-energy spectrum, divided out so RGB white stays white.
+const EQUAL_ENERGY_RGB: [f32; 3] = [1.2003, 0.9498, 0.9082];
+
+/// The wavelengths a path is traced at.
+#[derive(Clone, Copy, Debug)]
+pub struct Wavelengths {
+    lambda: [f32; 3],
+    spectral: bool,
+    /// Whether the path has already dropped all but the hero wavelength.
+    hero_alone: bool,
+}
+
+impl Wavelengths {
+    /// RGB rendering: channels are colours and nothing is converted.
+    pub fn rgb() -> Wavelengths {
+        Wavelengths {
+            lambda: RGB_WAVELENGTHS,
+            spectral: false,
+            hero_alone: false,
+        }
+    }
+
+    /// Hero wavelength sampling: one uniformly sampled wavelength, the others evenly rotated
+    /// away from it through the visible range, so all three are stratified.
+    pub fn sample() -> Wavelengths {
+        let u = fastrand::f32();
+        let mut lambda = [0.0; 3];
+        for (i, l) in lambda.iter_mut().enumerate() {
+            let offset = (u + i as f32 / 3.0).fract();
+            *l = LAMBDA_MIN + offset * (LAMBDA_MAX - LAMBDA_MIN);
+        }
+        Wavelengths {
+            lambda,
+            spectral: true,
+            hero_alone: false,
+        }
+    }
+
+    #[inline(always)]
+    pub fn is_spectral(&self) -> bool {
+        self.spectral
+    }
+
+    /// The wavelength of each channel, in nanometers.
+    #[inline(always)]
+    pub fn lambdas(&self) -> [f32; 3] {
+        self.lambda
+    }
+
+    /// Turns an RGB colour into its value at each wavelength. A no-op in RGB mode.
+    #[inline(always)]
+    pub fn upsample(self, rgb: Spectrum) -> Spectrum {
+        if !self.spectral {
+            return rgb;
+        }
+        let [r, g, b] = rgb.rgb();
+        let [l0, l1, l2] = self.lambda;
+        Spectrum::new_f(smits(r, g, b, l0), smits(r, g, b, l1), smits(r, g, b, l2))
+    }
+
+    /// Path throughput weights that keep only the hero wavelength, for when the others can't
+    /// follow it, such as after a dispersive refraction. The hero then stands for all three
+    /// samples of the film's estimate, which only has to be accounted for once per path.
+    pub fn hero_only(self) -> Spectrum {
+        if self.hero_alone {
+            Spectrum::new_f(1.0, 0.0, 0.0)
+        } else {
+            Spectrum::new_f(3.0, 0.0, 0.0)
+        }
+    }
+
+    /// The same wavelengths, for the rest of a path that `hero_only` has been applied to.
+    pub fn without_secondary(self) -> Wavelengths {
+        Wavelengths {
+            hero_alone: true,
+            ..self
+        }
+    }
+
+    /// Builds a spectrum from a function of wavelength. In RGB mode the function is looked
+    /// up at a representative wavelength per channel.
+    #[inline(always)]
+    pub fn map(&self, f: impl Fn(f32) -> f32) -> Spectrum {
+        let [l0, l1, l2] = self.lambda;
+        Spectrum::new_f(f(l0), f(l1), f(l2))
+    }
+
+    /// Converts radiance at these wavelengths to linear sRGB through CIE XYZ, as a single
+    /// sample of the film's estimate. A no-op in RGB mode.
+    pub fn to_rgb(self, radiance: Spectrum) -> Spectrum {
+        if !self.spectral {
+            return radiance;
+        }
+        let (mut x, mut y, mut z) = (0.0, 0.0, 0.0);
+        for (l, value) in self.lambda.iter().zip(radiance.rgb().iter()) {
+            x += value * cie_x(*l);
+            y += value * cie_y(*l);
+            z += value * cie_z(*l);
+        }
+        // each wavelength was picked with pdf 1 / range, and there are three of them
+        let scale = (LAMBDA_MAX - LAMBDA_MIN) / (3.0 * CIE_Y_INTEGRAL);
+        // single samples can fall outside the gamut; only the pixel average has to be in it
+        xyz_to_rgb(x * scale, y * scale, z * scale)
+    }
+}
+
+/// CIE XYZ to linear sRGB, white balanced so the equal-energy spectrum is white.
+fn xyz_to_rgb(x: f32, y: f32, z: f32) -> Spectrum {
+    let r = 3.2406 * x - 1.5372 * y - 0.4986 * z;
+    let g = -0.9689 * x + 1.8758 * y + 0.0415 * z;
+    let b = 0.0557 * x - 0.2040 * y + 1.0570 * z;
+    Spectrum::new_f(
+        r / EQUAL_ENERGY_RGB[0],
+        g / EQUAL_ENERGY_RGB[1],
+        b / EQUAL_ENERGY_RGB[2],
+    )
+}
+
+/// Integrates `f` over the visible range in 1 nm steps.
+fn integrate_visible(f: impl Fn(f32) -> f32) -> f32 {
+    let steps = (LAMBDA_MAX - LAMBDA_MIN) as usize;
+    (0..steps).map(|i| f(LAMBDA_MIN + i as f32 + 0.5)).sum()
+}
+
+/// Lumens per watt of light at 555 nm, where the eye is most sensitive.
+pub const MAX_LUMINOUS_EFFICACY: f32 = 683.0;
+
+/// Lumens per watt of visible light with the spectrum Smits' method gives an RGB colour.
+pub fn luminous_efficacy(rgb: Spectrum) -> f32 {
+    let [r, g, b] = rgb.rgb();
+    let power = integrate_visible(|l| smits(r, g, b, l));
+    if power <= 0.0 {
+        return 0.0;
+    }
+    MAX_LUMINOUS_EFFICACY * integrate_visible(|l| smits(r, g, b, l) * cie_y(l)) / power
+}
+
+/// Planck's law: spectral radiance of a blackbody at `temperature` Kelvin, in
+/// W / (sr m² nm).
+pub fn planck(lambda: f32, temperature: f32) -> f32 {
+    const C: f64 = 299_792_458.0;
+    const H: f64 = 6.626_070_15e-34;
+    const K_B: f64 = 1.380_649e-23;
+    let l = lambda as f64 * 1e-9;
+    let radiance =
+        2.0 * H * C * C / (l.powi(5) * ((H * C / (l * K_B * temperature as f64)).exp_m1()));
+    (radiance * 1e-9) as f32
+}
+
+/// Light emitted by an ideal blackbody, scaled to a given luminance.
+#[derive(Clone, Copy, Debug)]
+pub struct Blackbody {
+    temperature: f32,
+    /// Turns Planck's law into the requested luminance.
+    scale: f32,
+    /// The same light in RGB, as the film would see it.
+    rgb: Spectrum,
+}
+
+impl Blackbody {
+    /// A blackbody at `temperature` Kelvin whose luminance is `luminance` in our radiance
+    /// units (nits for physically specified lights).
+    pub fn new(temperature: f32, luminance: f32) -> Blackbody {
+        let y = integrate_visible(|l| planck(l, temperature) * cie_y(l)) / CIE_Y_INTEGRAL;
+        let scale = if y > 0.0 { luminance / y } else { 0.0 };
+        let x = integrate_visible(|l| planck(l, temperature) * cie_x(l)) / CIE_Y_INTEGRAL;
+        let z = integrate_visible(|l| planck(l, temperature) * cie_z(l)) / CIE_Y_INTEGRAL;
+        let [r, g, b] = xyz_to_rgb(x * scale, y * scale, z * scale).rgb();
+        Blackbody {
+            temperature,
+            scale,
+            rgb: Spectrum::new_f(f32::max(0.0, r), f32::max(0.0, g), f32::max(0.0, b)),
+        }
+    }
+
+    pub fn temperature(&self) -> f32 {
+        self.temperature
+    }
+
+    /// Radiance at `lambda` nanometers.
+    #[inline(always)]
+    pub fn value(&self, lambda: f32) -> f32 {
+        self.scale * planck(lambda, self.temperature)
+    }
+
+    pub fn rgb(&self) -> Spectrum {
+        self.rgb
+    }
+
+    /// Lumens per watt of total emitted power, most of which is infrared for incandescent
+    /// temperatures.
+    pub fn luminous_efficacy(temperature: f32) -> f32 {
+        const STEFAN_BOLTZMANN: f32 = 5.670_374e-8;
+        let total = STEFAN_BOLTZMANN * temperature.powi(4) / std::f32::consts::PI;
+        let visible = integrate_visible(|l| planck(l, temperature) * cie_y(l));
+        MAX_LUMINOUS_EFFICACY * visible / total
+    }
+}
+
+/// Wavelength at which dispersive media are refracted in RGB mode: the Fraunhofer d line,
+/// where glass catalogues quote their index.
+const RGB_DISPERSION_WAVELENGTH: f32 = 587.6;
+
+/// Index of refraction of a dielectric, possibly varying with wavelength.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Ior {
+    Constant(f32),
+    /// n(λ) = a + b / λ², with λ in micrometers.
+    Cauchy {
+        a: f32,
+        b: f32,
+    },
+    /// n(λ)² = 1 + Σ b_i λ² / (λ² - c_i), with λ in micrometers.
+    Sellmeier {
+        b: [f32; 3],
+        c: [f32; 3],
+    },
+}
+
+impl Ior {
+    /// Schott N-BK7 crown glass.
+    pub fn bk7() -> Ior {
+        Ior::Sellmeier {
+            b: [1.039_612, 0.231_792_34, 1.010_469_5],
+            c: [0.006_000_699, 0.020_017_914, 103.560_65],
+        }
+    }
+
+    /// Dense flint glass, for strongly dispersive prisms.
+    pub fn flint() -> Ior {
+        Ior::Cauchy { a: 1.7, b: 0.0135 }
+    }
+
+    pub fn diamond() -> Ior {
+        Ior::Sellmeier {
+            b: [0.3306, 4.3356, 0.0],
+            c: [0.030_625, 0.011_236, 0.0],
+        }
+    }
+
+    pub fn is_dispersive(&self) -> bool {
+        !matches!(self, Ior::Constant(_))
+    }
+
+    /// The index at `lambda` nanometers.
+    pub fn at(&self, lambda: f32) -> f32 {
+        let micrometers = lambda / 1000.0;
+        let l2 = micrometers * micrometers;
+        match *self {
+            Ior::Constant(ior) => ior,
+            Ior::Cauchy { a, b } => a + b / l2,
+            Ior::Sellmeier { b, c } => {
+                let sum: f32 = (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum();
+                f32::sqrt(1.0 + sum)
+            }
+        }
+    }
+
+    /// The index for each channel of a path. Dispersive media get a single value in RGB mode,
+    /// since all channels have to follow the same direction.
+    pub fn for_wavelengths(&self, wavelengths: Wavelengths) -> [f32; 3] {
+        if self.is_dispersive() && !wavelengths.is_spectral() {
+            return [self.at(RGB_DISPERSION_WAVELENGTH); 3];
+        }
+        let [l0, l1, l2] = wavelengths.lambdas();
+        [self.at(l0), self.at(l1), self.at(l2)]
+    }
+}
+
+/// Piecewise Gaussian used by the colour matching function fits.
+#[inline(always)]
+fn gaussian(lambda: f32, mu: f32, sigma_low: f32, sigma_high: f32) -> f32 {
+    let sigma = if lambda < mu { sigma_low } else { sigma_high };
+    let t = (lambda - mu) / sigma;
+    f32::exp(-0.5 * t * t)
+}
+
+// CIE 1931 colour matching functions, multi-lobe fits from Wyman, Sloan and Shirley,
+// "Simple Analytic Approximations to the CIE XYZ Color Matching Functions" (2013).
+
+pub fn cie_x(lambda: f32) -> f32 {
+    1.056 * gaussian(lambda, 599.8, 37.9, 31.0) + 0.362 * gaussian(lambda, 442.0, 16.0, 26.7)
+        - 0.065 * gaussian(lambda, 501.1, 20.4, 26.2)
+}
+
+pub fn cie_y(lambda: f32) -> f32 {
+    0.821 * gaussian(lambda, 568.8, 46.9, 40.5) + 0.286 * gaussian(lambda, 530.9, 16.3, 31.1)
+}
+
+pub fn cie_z(lambda: f32) -> f32 {
+    1.217 * gaussian(lambda, 437.0, 11.8, 36.0) + 0.681 * gaussian(lambda, 459.0, 26.0, 13.8)
+}
+
+// Smits, "An RGB to Spectrum Conversion for Reflectances" (1999): ten bins from 380 to
+// 720 nm for white and the six primaries and secondaries.
+
+const SMITS_BINS: usize = 10;
+const SMITS_WHITE: [f32; SMITS_BINS] = [
+    1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000,
+];
+const SMITS_CYAN: [f32; SMITS_BINS] = [
+    0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000,
+];
+const SMITS_MAGENTA: [f32; SMITS_BINS] = [
+    1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959,
+];
+const SMITS_YELLOW: [f32; SMITS_BINS] = [
+    0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840,
+];
+const SMITS_RED: [f32; SMITS_BINS] = [
+    0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149,
+];
+const SMITS_GREEN: [f32; SMITS_BINS] = [
+    0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025,
+];
+const SMITS_BLUE: [f32; SMITS_BINS] = [
+    1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496,
+];
+
+/// Linear interpolation between bin centers, clamped at both ends.
+#[inline(always)]
+fn smits_basis(curve: &[f32; SMITS_BINS], lambda: f32) -> f32 {
+    let bin_width = (720.0 - 380.0) / SMITS_BINS as f32;
+    let x = ((lambda - 380.0) / bin_width - 0.5).clamp(0.0, (SMITS_BINS - 1) as f32);
+    let i = usize::min(x as usize, SMITS_BINS - 2);
+    let t = x - i as f32;
+    curve[i] * (1.0 - t) + curve[i + 1] * t
+}
+
+/// Value at `lambda` of Smits' spectrum for an RGB reflectance: white for the smallest
+/// channel, then the secondary and primary covering what's left.
+fn smits(r: f32, g: f32, b: f32, lambda: f32) -> f32 {
+    let basis = |curve| smits_basis(curve, lambda);
+    if r <= g && r <= b {
+        let white = r * basis(&SMITS_WHITE);
+        if g <= b {
+            white + (g - r) * basis(&SMITS_CYAN) + (b - g) * basis(&SMITS_BLUE)
+        } else {
+            white + (b - r) * basis(&SMITS_CYAN) + (g - b) * basis(&SMITS_GREEN)
+        }
+    } else if g <= r && g <= b {
+        let white = g * basis(&SMITS_WHITE);
+        if r <= b {
+            white + (r - g) * basis(&SMITS_MAGENTA) + (b - r) * basis(&SMITS_BLUE)
+        } else {
+            white + (b - g) * basis(&SMITS_MAGENTA) + (r - b) * basis(&SMITS_RED)
+        }
+    } else {
+        let white = b * basis(&SMITS_WHITE);
+        if r <= g {
+            white + (r - b) * basis(&SMITS_YELLOW) + (g - r) * basis(&SMITS_GREEN)
+        } else {
+            white + (g - b) * basis(&SMITS_YELLOW) + (r - g) * basis(&SMITS_RED)
+        }
+    }
+}
+
+/// Metals with measured complex indices of refraction.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Metal {
+    Gold,
+    Silver,
+    Copper,
+    Aluminium,
+}
+
+/// (eta, k) every 50 nm from 400 to 700 nm.
+const METAL_SAMPLES: usize = 7;
+const GOLD_IOR: [(f32, f32); METAL_SAMPLES] = [
+    (1.658, 1.956),
+    (1.500, 1.880),
+    (0.970, 1.870),
+    (0.430, 2.450),
+    (0.250, 2.980),
+    (0.170, 3.490),
+    (0.160, 3.950),
+];
+const SILVER_IOR: [(f32, f32); METAL_SAMPLES] = [
+    (0.173, 1.950),
+    (0.144, 2.520),
+    (0.130, 3.050),
+    (0.125, 3.340),
+    (0.124, 3.730),
+    (0.140, 4.150),
+    (0.142, 4.520),
+];
+const COPPER_IOR: [(f32, f32); METAL_SAMPLES] = [
+    (1.180, 2.210),
+    (1.170, 2.400),
+    (1.120, 2.570),
+    (1.020, 2.580),
+    (0.300, 3.200),
+    (0.210, 3.670),
+    (0.210, 4.050),
+];
+const ALUMINIUM_IOR: [(f32, f32); METAL_SAMPLES] = [
+    (0.490, 4.860),
+    (0.620, 5.470),
+    (0.770, 6.080),
+    (0.960, 6.690),
+    (1.200, 7.260),
+    (1.470, 7.790),
+    (1.830, 8.310),
+];
+
+impl Metal {
+    /// Complex index of refraction (eta, k) at `lambda`, held constant outside the data.
+    pub fn ior(&self, lambda: f32) -> (f32, f32) {
+        let table = match self {
+            Metal::Gold => &GOLD_IOR,
+            Metal::Silver => &SILVER_IOR,
+            Metal::Copper => &COPPER_IOR,
+            Metal::Aluminium => &ALUMINIUM_IOR,
+        };
+        let x = ((lambda - 400.0) / 50.0).clamp(0.0, (METAL_SAMPLES - 1) as f32);
+        let i = usize::min(x as usize, METAL_SAMPLES - 2);
+        let t = x - i as f32;
+        let ((eta0, k0), (eta1, k1)) = (table[i], table[i + 1]);
+        (eta0 + (eta1 - eta0) * t, k0 + (k1 - k0) * t)
+    }
+}
+
+/// Fresnel reflectance of a conductor with complex index of refraction `eta + ik`, for
+/// unpolarized light coming from a medium of index 1.
+pub fn fresnel_conductor(cos_i: f32, eta: f32, k: f32) -> f32 {
+    let cos_i = cos_i.clamp(0.0, 1.0);
+    let cos2 = cos_i * cos_i;
+    let sin2 = 1.0 - cos2;
+    let (eta2, k2) = (eta * eta, k * k);
+
+    let t0 = eta2 - k2 - sin2;
+    let a2_plus_b2 = f32::sqrt(t0 * t0 + 4.0 * eta2 * k2);
+    let a = f32::sqrt(f32::max(0.0, 0.5 * (a2_plus_b2 + t0)));
+
+    let t1 = a2_plus_b2 + cos2;
+    let t2 = 2.0 * cos_i * a;
+    let r_s = (t1 - t2) / (t1 + t2);
+
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let r_p = r_s * (t3 - t4) / (t3 + t4);
+
+    0.5 * (r_s + r_p)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn white_stays_white() {
+        let wavelengths = Wavelengths::sample();
+        let white = wavelengths.upsample(Spectrum::white());
+        for value in white.rgb().iter() {
+            assert!((value - 1.0).abs() < 1e-3);
+        }
+
+        // averaged over many wavelengths, a flat spectrum comes out as RGB white
+        let n = 20000;
+        let mut sum = [0.0; 3];
+        for i in 0..n {
+            let l = LAMBDA_MIN + (i as f32 + 0.5) / n as f32 * (LAMBDA_MAX - LAMBDA_MIN);
+            let wavelengths = Wavelengths {
+                lambda: [l; 3],
+                spectral: true,
+                hero_alone: false,
+            };
+            let rgb = wavelengths.to_rgb(Spectrum::white()).rgb();
+            for c in 0..3 {
+                sum[c] += rgb[c] / n as f32;
+            }
+        }
+        for value in sum.iter() {
+            assert!((value - 1.0).abs() < 0.02, "{:?}", sum);
+        }
+    }
+
+    #[test]
+    fn glass_disperses_blue_more_than_red() {
+        let bk7 = Ior::bk7();
+        assert!((bk7.at(587.6) - 1.5168).abs() < 1e-3);
+        assert!(bk7.at(450.0) > bk7.at(650.0));
+        assert!((Ior::diamond().at(587.6) - 2.417).abs() < 0.01);
+    }
+
+    #[test]
+    fn incandescent_efficacy() {
+        // a 2700 K filament manages about 14 lm/W, the sun's 5800 K about 90
+        let incandescent = Blackbody::luminous_efficacy(2700.0);
+        let sun = Blackbody::luminous_efficacy(5800.0);
+        assert!(
+            incandescent > 10.0 && incandescent < 20.0,
+            "{}",
+            incandescent
+        );
+        assert!(sun > 80.0 && sun < 100.0, "{}", sun);
+
+        let warm = Blackbody::new(2700.0, 1.0).rgb().rgb();
+        assert!(warm[0] > warm[1] && warm[1] > warm[2]);
+    }
+
+    #[test]
+    fn gold_reflects_red_more_than_blue() {
+        let red = Metal::Gold.ior(650.0);
+        let blue = Metal::Gold.ior(450.0);
+        assert!(fresnel_conductor(1.0, red.0, red.1) > fresnel_conductor(1.0, blue.0, blue.1));
+        assert!((fresnel_conductor(0.0, red.0, red.1) - 1.0).abs() < 1e-3);
+    }
+}